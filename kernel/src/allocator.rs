@@ -15,12 +15,18 @@ unsafe extern "C" {
 
 pub struct PageAllocator {
     next_paddr: *const u8,
+    /// 解放されたページのリスト. 各ページの先頭に次のページのアドレスを置き, 0 で終わる
+    free_list: usize,
+    /// free_list にあるページの数
+    free_count: usize,
 }
 
 impl PageAllocator {
     pub const fn init() -> Self {
         PageAllocator {
             next_paddr: unsafe { &__page_area_start as *const u8 },
+            free_list: 0,
+            free_count: 0,
         }
     }
 
@@ -33,6 +39,16 @@ impl PageAllocator {
         };
 
         unsafe {
+            // 1ページなら解放されたページを使い回す
+            if n == 1 && self.free_list != 0 {
+                let page = self.free_list as *mut T;
+                self.free_list = *(self.free_list as *const usize);
+                self.free_count -= 1;
+                let count = offset / size_of::<T>();
+                ptr::write_bytes(page, 0, count);
+                return slice::from_raw_parts_mut(page, count);
+            }
+
             // 現在の先頭を確保対象として保持しておく
             let start_paddr = self.next_paddr;
             // 確保する分を足して次の始点を更新
@@ -49,6 +65,48 @@ impl PageAllocator {
             slice::from_raw_parts_mut(start, count)
         }
     }
+
+    /// 管理しているページの総数
+    pub fn total_pages(&self) -> usize {
+        let start = unsafe { &__page_area_start as *const u8 as usize };
+        let end = unsafe { &__page_area_end as *const u8 as usize };
+        (end - start) / PAGE_SIZE
+    }
+
+    /// alloc_pages で割り当てたページのうち paddr の1ページを返す
+    ///
+    /// 複数ページで割り当てた領域も1ページずつ返せる
+    pub fn free_page(&mut self, paddr: usize) {
+        let start = unsafe { &__page_area_start as *const u8 as usize };
+        assert!(
+            paddr.is_multiple_of(PAGE_SIZE) && start <= paddr && paddr < self.next_paddr as usize,
+            "freed a page that is not allocated: {paddr:#x}"
+        );
+        unsafe {
+            *(paddr as *mut usize) = self.free_list;
+        }
+        self.free_list = paddr;
+        self.free_count += 1;
+    }
+
+    /// n ページを alloc_pages で割り当てられるか
+    ///
+    /// 解放されたページは1ページの割り当てにしか使わないので, 2ページ以上は未使用の領域から取る
+    pub fn can_alloc(&self, n: usize) -> bool {
+        let end = unsafe { &__page_area_end as *const u8 as usize };
+        let rest = (end - self.next_paddr as usize) / PAGE_SIZE;
+        if n == 1 {
+            rest + self.free_count >= 1
+        } else {
+            rest >= n
+        }
+    }
+
+    /// 割り当て済みのページ数
+    pub fn used_pages(&self) -> usize {
+        let start = unsafe { &__page_area_start as *const u8 as usize };
+        (self.next_paddr as usize - start) / PAGE_SIZE - self.free_count
+    }
 }
 
 pub struct GlobalPageAllocator {
@@ -69,6 +127,26 @@ impl GlobalPageAllocator {
     pub fn alloc_pages<T>(&self, n: usize) -> &mut [T] {
         unsafe { (&mut *self.inner.get()).alloc_pages::<T>(n) }
     }
+
+    #[inline]
+    pub fn can_alloc(&self, n: usize) -> bool {
+        unsafe { (*self.inner.get()).can_alloc(n) }
+    }
+
+    #[inline]
+    pub fn free_page(&self, paddr: usize) {
+        unsafe { (&mut *self.inner.get()).free_page(paddr) }
+    }
+
+    #[inline]
+    pub fn total_pages(&self) -> usize {
+        unsafe { (*self.inner.get()).total_pages() }
+    }
+
+    #[inline]
+    pub fn used_pages(&self) -> usize {
+        unsafe { (*self.inner.get()).used_pages() }
+    }
 }
 
 pub static PAGE_ALLOC: GlobalPageAllocator = GlobalPageAllocator::new();
@@ -109,10 +187,12 @@ unsafe impl GlobalAlloc for BumpPointerAlloc {
         unsafe {
             let res = *head % align;
             let start = if res == 0 { *head } else { *head + align - res };
-            if start + align > *self.end.get() {
+            // 要求されたサイズ分だけ進める
+            let next = start + layout.size();
+            if next > *self.end.get() {
                 ptr::null_mut()
             } else {
-                *head = start + align;
+                *head = next;
                 start as *mut u8
             }
         }
@@ -1,12 +1,103 @@
 #![allow(dead_code)]
 
-use core::arch::asm;
+use core::{arch::asm, cell::UnsafeCell};
+
+use syscall::{SIGINT, SIGTSTP};
+
+use crate::proc;
 
 pub fn read_byte() -> i32 {
     let ret = sbi_call(0, 0, 0, 0, 0, 0, 0, 2);
     ret.err
 }
 
+//
+// コンソールの入力
+//
+// SBI から読んだ入力はバッファに溜めてから読ませる
+// 読むプロセスがいなくてもタイマー割り込みのたびに入力を調べ,
+// Ctrl-C と Ctrl-Z は前面のプロセスグループへのシグナルに変える
+//
+
+/// Ctrl-C. 前面のプロセスグループに SIGINT を送る
+const CTRL_C: u8 = 0x03;
+/// Ctrl-Z. 前面のプロセスグループに SIGTSTP を送る
+const CTRL_Z: u8 = 0x1a;
+/// 読まれるのを待つ入力を溜めておける大きさ
+const INPUT_SIZE: usize = 256;
+
+struct InputBuffer {
+    buf: [u8; INPUT_SIZE],
+    head: usize,
+    len: usize,
+}
+
+struct Input {
+    inner: UnsafeCell<InputBuffer>,
+}
+
+unsafe impl Sync for Input {}
+
+impl Input {
+    /// # Safety
+    /// この参照のライフタイムは検証されない
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get_mut(&self) -> &mut InputBuffer {
+        unsafe { &mut *self.inner.get() }
+    }
+}
+
+static INPUT: Input = Input {
+    inner: UnsafeCell::new(InputBuffer {
+        buf: [0; INPUT_SIZE],
+        head: 0,
+        len: 0,
+    }),
+};
+
+/// SBI から読める入力を全てバッファに移す
+///
+/// 前面のプロセスグループがある間は Ctrl-C と Ctrl-Z をシグナルにして送り, バッファには入れない
+/// バッファが一杯の場合は読んだ入力を捨てる
+pub fn poll_input() {
+    loop {
+        let byte = read_byte();
+        if byte < 0 {
+            return;
+        }
+        let byte = byte as u8;
+        let sig = match byte {
+            CTRL_C => SIGINT,
+            CTRL_Z => SIGTSTP,
+            _ => 0,
+        };
+        let foreground = proc::foreground();
+        if sig != 0 && foreground != 0 {
+            // 前面のグループが既に無くなっていれば何もしない
+            let _ = proc::kill(-(foreground as isize), sig);
+            continue;
+        }
+        let input = unsafe { INPUT.get_mut() };
+        if input.len < INPUT_SIZE {
+            input.buf[(input.head + input.len) % INPUT_SIZE] = byte;
+            input.len += 1;
+        }
+    }
+}
+
+/// バッファから1バイト取り出す. 空の場合は None
+pub fn pop_input() -> Option<u8> {
+    poll_input();
+    let input = unsafe { INPUT.get_mut() };
+    if input.len == 0 {
+        return None;
+    }
+    let byte = input.buf[input.head];
+    input.head = (input.head + 1) % INPUT_SIZE;
+    input.len -= 1;
+    Some(byte)
+}
+
 pub struct Writer;
 
 impl Writer {
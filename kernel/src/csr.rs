@@ -1,7 +1,11 @@
 use core::arch::asm;
 
 pub const SSTATUS_SPIE: usize = 1 << 5;
+/// トラップ直前の特権モード. 立っていればスーパーバイザーモード
+pub const SSTATUS_SPP: usize = 1 << 8;
 pub const SSTATUS_SUM: usize = 1 << 18;
+/// sie のスーパーバイザータイマー割り込みの許可
+pub const SIE_STIE: usize = 1 << 5;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Csr {
@@ -11,6 +15,8 @@ pub enum Csr {
     Sepc,
     Sscratch,
     Satp,
+    Sie,
+    Sstatus,
 }
 
 macro_rules! read_csr_asm {
@@ -41,18 +47,21 @@ pub fn read_csr(csr: Csr) -> usize {
         Csr::Sepc => unsafe { read_csr_asm!(value, "sepc") },
         Csr::Sscratch => unsafe { read_csr_asm!(value, "sscratch") },
         Csr::Satp => unsafe { read_csr_asm!(value, "satp") },
+        Csr::Sie => unsafe { read_csr_asm!(value, "sie") },
+        Csr::Sstatus => unsafe { read_csr_asm!(value, "sstatus") },
     }
     value
 }
 
 /// # Panics
-/// stvec, sscratch, satp, sepc 以外のレジスタへ書き込もうとすると panic する
+/// stvec, sscratch, satp, sepc, sie 以外のレジスタへ書き込もうとすると panic する
 #[inline(always)]
 pub unsafe fn write_csr(csr: Csr, value: usize) {
     match csr {
         Csr::Stvec => unsafe { write_csr_asm!("stvec", value) },
         Csr::Sscratch => unsafe { write_csr_asm!("sscratch", value) },
         Csr::Sepc => unsafe { write_csr_asm!("sepc", value) },
+        Csr::Sie => unsafe { write_csr_asm!("sie", value) },
         Csr::Satp => unsafe {
             asm!(
                 "sfence.vma",
@@ -0,0 +1,117 @@
+use crate::{
+    console::{self, Writer},
+    proc, random,
+    vfs::{Fs, Node},
+};
+use syscall::{FILE_TYPE_CHAR_DEVICE, Metadata};
+
+//
+// キャラクタデバイスを vfs::Node として公開するファイルシステム
+//
+
+pub struct DevFs;
+
+impl Fs for DevFs {
+    type NodeType = DevNode;
+    fn lookup(&self, name: &str) -> Option<Self::NodeType> {
+        match name {
+            "console" => Some(DevNode::Console),
+            "null" => Some(DevNode::Null),
+            "zero" => Some(DevNode::Zero),
+            "random" => Some(DevNode::Random),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DevNode {
+    Console,
+    Null,
+    Zero,
+    Random,
+}
+
+impl Node for DevNode {
+    fn get_id(&self) -> usize {
+        *self as usize
+    }
+
+    /// デバイスは大きさを持たない
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> Result<(), ()> {
+        // デバイスの内容を一括で読み出すことはできない
+        Err(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            file_type: FILE_TYPE_CHAR_DEVICE,
+            mode: 0o666,
+            id: self.get_id() as u64,
+            ..Default::default()
+        }
+    }
+
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        match self {
+            DevNode::Console => read_console(buf),
+            // 常に終端
+            DevNode::Null => Ok(0),
+            DevNode::Zero => {
+                buf.fill(0);
+                Ok(buf.len())
+            }
+            DevNode::Random => {
+                random::fill_bytes(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize, ()> {
+        match self {
+            DevNode::Console => {
+                for &c in buf {
+                    Writer::write_byte(c).map_err(|_| ())?;
+                }
+                Ok(buf.len())
+            }
+            // 書き込まれた内容は捨てる
+            DevNode::Null | DevNode::Zero | DevNode::Random => Ok(buf.len()),
+        }
+    }
+}
+
+/// 最低1バイト読めるまで待ち, その後は読める分だけ読む
+///
+/// 入力が無い間は他のプロセスに実行を譲る
+/// 読んでいる間にシグナルで中断された場合はエラー
+fn read_console(buf: &mut [u8]) -> Result<usize, ()> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    let mut n = 0;
+    while n < buf.len() {
+        match console::pop_input() {
+            Some(byte) => {
+                buf[n] = byte;
+                n += 1;
+            }
+            None if n > 0 => break,
+            None => {
+                proc::yield_to_others();
+                // Ctrl-Z で自分が止められた場合は再開されるまで待つ
+                proc::handle_stop();
+                if proc::interrupted() {
+                    return Err(());
+                }
+            }
+        }
+    }
+    Ok(n)
+}
@@ -0,0 +1,109 @@
+use core::{cell::Cell, fmt};
+extern crate alloc;
+use alloc::rc::Rc;
+
+use crate::vfs::{self, Node};
+use syscall::{Metadata, O_ACCMODE, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+
+/// 1プロセスが同時に開けるファイルの数
+pub const NOFILE: usize = 32;
+
+/// オープンされたファイル
+///
+/// 読み書きの位置はファイルディスクリプタ間で共有される
+pub struct OpenFile {
+    node: Rc<dyn Node>,
+    offset: Cell<usize>,
+    /// open に渡されたフラグ
+    flags: usize,
+}
+
+impl OpenFile {
+    /// flags は syscall の O_* の組み合わせ
+    pub fn open(path: &str, flags: usize) -> Option<Rc<Self>> {
+        let node = match vfs::lookup(path) {
+            Some(node) => node,
+            None if flags & O_CREAT != 0 => vfs::create(path)?,
+            None => return None,
+        };
+        let file = Self::with_flags(node, flags);
+        if flags & O_TRUNC != 0 && file.writable() {
+            file.node.truncate().ok()?;
+        }
+        Some(file)
+    }
+
+    /// 読み書きできるファイルとして開く
+    pub fn new(node: Rc<dyn Node>) -> Rc<Self> {
+        Self::with_flags(node, O_RDWR)
+    }
+
+    pub fn with_flags(node: Rc<dyn Node>, flags: usize) -> Rc<Self> {
+        Rc::new(Self {
+            node,
+            offset: Cell::new(0),
+            flags,
+        })
+    }
+
+    fn readable(&self) -> bool {
+        matches!(self.flags & O_ACCMODE, O_RDONLY | O_RDWR)
+    }
+
+    fn writable(&self) -> bool {
+        matches!(self.flags & O_ACCMODE, O_WRONLY | O_RDWR)
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, ()> {
+        if !self.readable() {
+            return Err(());
+        }
+        let n = self.node.read_at(self.offset.get(), buf)?;
+        self.offset.set(self.offset.get() + n);
+        Ok(n)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, ()> {
+        if !self.writable() {
+            return Err(());
+        }
+        // 追記モードでは毎回終端から書き込む
+        if self.flags & O_APPEND != 0 {
+            self.offset.set(self.node.size());
+        }
+        let n = self.node.write_at(self.offset.get(), buf)?;
+        self.offset.set(self.offset.get() + n);
+        Ok(n)
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.node.metadata()
+    }
+
+    /// ディレクトリの次のエントリの名前を buf に書き込み, その長さを返す
+    ///
+    /// offset をエントリの番号として使う. 終端では 0 を返す
+    pub fn read_dir(&self, buf: &mut [u8]) -> Result<usize, ()> {
+        let Some(name) = self.node.read_dir(self.offset.get()) else {
+            return Ok(0);
+        };
+        // 名前が収まらない場合はエラーにして, 次の呼び出しで読み直せるようにする
+        let name = name.as_bytes();
+        if name.len() > buf.len() {
+            return Err(());
+        }
+        buf[..name.len()].copy_from_slice(name);
+        self.offset.set(self.offset.get() + 1);
+        Ok(name.len())
+    }
+}
+
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("id", &self.node.get_id())
+            .field("offset", &self.offset.get())
+            .field("flags", &self.flags)
+            .finish()
+    }
+}
@@ -1,92 +1,489 @@
 use core::slice;
 extern crate alloc;
+use alloc::rc::Rc;
 
 use crate::{
     allocator::{self, PAGE_SIZE},
     console::{self, Writer},
-    log_info, log_warn, proc,
-    vfs::{self, Fs, Node},
+    file::OpenFile,
+    log_info, log_warn,
+    mem::{copy_from_user, copy_to_user},
+    pipe,
+    proc::{self, CreateError},
+    trap::TrapFrame,
+    vfs,
 };
 use syscall::{
-    SYS_CREATE_PROCESS, SYS_EXIT_PROCESS, SYS_LIST_PROCESS, SYS_READ_BYTE, SYS_WRITE_BYTE,
-    SYS_YIELD_PROCESS,
+    E2BIG, EFAULT, EINVAL, EIO, ENAMETOOLONG, ENOEXEC, ENOMEM, Metadata, SYS_BRK, SYS_CLOSE,
+    SYS_CREATE_PROCESS, SYS_DUP, SYS_DUP2, SYS_EXIT_PROCESS, SYS_FSTAT, SYS_GET_ARGS, SYS_GET_ENV,
+    SYS_GETPGID, SYS_KILL, SYS_LIST_PROCESS, SYS_MMAP, SYS_MPROTECT, SYS_MUNMAP, SYS_OPEN,
+    SYS_PIPE, SYS_READ, SYS_READ_BYTE, SYS_READDIR, SYS_SETPGID, SYS_SIGACTION, SYS_SIGPROCMASK,
+    SYS_SIGRETURN, SYS_STAT, SYS_TCGETPGRP, SYS_TCSETPGRP, SYS_WAIT_PROCESS, SYS_WRITE,
+    SYS_WRITE_BYTE, SYS_YIELD_PROCESS, SigAction,
 };
-use zerocopy::{AsBytes, FromBytes, FromZeroes};
-
-#[allow(unused)]
-#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
-#[repr(C)]
-struct TrapFrame {
-    ra: usize,
-    gp: usize,
-    tp: usize,
-    t0: usize,
-    t1: usize,
-    t2: usize,
-    t3: usize,
-    t4: usize,
-    t5: usize,
-    t6: usize,
-    a0: isize,
-    a1: usize,
-    a2: usize,
-    a3: usize,
-    a4: usize,
-    a5: usize,
-    a6: usize,
-    a7: usize,
-    s0: usize,
-    s1: usize,
-    s2: usize,
-    s3: usize,
-    s4: usize,
-    s5: usize,
-    s6: usize,
-    s7: usize,
-    s8: usize,
-    s9: usize,
-    s10: usize,
-    s11: usize,
-    sp: usize,
+
+/// read/write でカーネルのスタック上に取るバッファの大きさ
+const IO_CHUNK_SIZE: usize = 256;
+/// ユーザーから受け取るパスの長さの上限
+const PATH_MAX: usize = 4096;
+/// ユーザーから受け取る引数や環境変数の列の長さの上限
+const ARG_MAX: usize = 128 * 1024;
+
+/// ユーザー空間の dst へ Metadata をコピーする
+fn copy_metadata_to_user(dst: *mut Metadata, meta: &Metadata) -> Result<(), ()> {
+    let bytes = unsafe {
+        slice::from_raw_parts(meta as *const Metadata as *const u8, size_of::<Metadata>())
+    };
+    copy_to_user(dst as *mut u8, bytes)
 }
 
-fn handle_create_process(frame: &mut TrapFrame) {
-    let path_ptr = frame.a0 as *const u8;
-    let path_len = frame.a1;
-    let mut bytes = alloc::vec::Vec::with_capacity(path_len);
+/// ユーザー空間の ptr から len バイトを読み出す. len が max を超える場合は too_long を返す
+///
+/// ユーザーが渡した長さの分だけカーネルのヒープを確保するので, 先に上限を確かめる
+fn copy_bytes_from_user(
+    ptr: *const u8,
+    len: usize,
+    max: usize,
+    too_long: isize,
+) -> Result<alloc::vec::Vec<u8>, isize> {
+    if len > max {
+        return Err(-too_long);
+    }
+    let mut bytes = alloc::vec![0u8; len];
+    copy_from_user(&mut bytes, ptr).map_err(|()| -EFAULT)?;
+    Ok(bytes)
+}
 
-    unsafe {
-        crate::csr::set_sum();
-        for i in 0..path_len {
-            bytes.push(*path_ptr.add(i));
+/// a0, a1 にパス, a2, a4 に引数の列, a5 に標準入出力にする fd の配列, a6, a7 に環境変数の列を受け取る
+///
+/// 引数と環境変数の列は NUL で区切った文字列. 標準入出力の配列が null の場合は 0, 1, 2 を引き継ぐ
+/// 実行ファイルが正しい ELF ファイルでない場合は -ENOEXEC を, 読めない場合は -EIO を,
+/// 読み込むメモリが足りない場合は -ENOMEM を返す
+/// パスや列が長すぎる場合は -ENAMETOOLONG, -E2BIG を, アドレスが正しくない場合は -EFAULT を返す
+fn handle_create_process(frame: &mut TrapFrame) {
+    let copied = copy_bytes_from_user(frame.a0 as *const u8, frame.a1, PATH_MAX, ENAMETOOLONG)
+        .and_then(|bytes| {
+            let args = copy_bytes_from_user(frame.a2 as *const u8, frame.a4, ARG_MAX, E2BIG)?;
+            let env = copy_bytes_from_user(frame.a6 as *const u8, frame.a7, ARG_MAX, E2BIG)?;
+            Ok((bytes, args, env))
+        });
+    let (bytes, args, env) = match copied {
+        Ok(copied) => copied,
+        Err(err) => {
+            frame.a0 = err;
+            return;
         }
-        crate::csr::clear_sum();
-    }
+    };
+
+    let stdio_ptr = frame.a5 as *const [usize; 3];
+    let stdio = if stdio_ptr.is_null() {
+        None
+    } else {
+        let mut fds = [0usize; 3];
+        let dst =
+            unsafe { slice::from_raw_parts_mut(fds.as_mut_ptr() as *mut u8, size_of_val(&fds)) };
+        let Ok(()) = copy_from_user(dst, stdio_ptr as *const u8) else {
+            frame.a0 = -EFAULT;
+            return;
+        };
+        Some(fds)
+    };
 
-    let path = core::str::from_utf8(&bytes).unwrap();
+    let Ok(path) = core::str::from_utf8(&bytes) else {
+        frame.a0 = -1;
+        return;
+    };
     log_info!("ksyscall", "path='{}'", path);
-    let fs = vfs::MemoryFs;
 
-    let node = if let Some(node) = fs.lookup(path) {
+    let node = if let Some(node) = vfs::lookup(path) {
         node
     } else {
         log_warn!("ksyscall", "file not found");
         frame.a0 = -1;
         return;
     };
+    if !node.metadata().is_file() {
+        frame.a0 = -1;
+        return;
+    }
 
-    let n = node.size().div_ceil(PAGE_SIZE);
+    let size = node.size();
+    let n = size.div_ceil(PAGE_SIZE);
+    if !proc::can_alloc_pages(n) {
+        log_warn!("ksyscall", "{}: out of memory", path);
+        frame.a0 = -ENOMEM;
+        return;
+    }
     let buf_ptr = allocator::PAGE_ALLOC.alloc_pages::<u8>(n).as_mut_ptr();
     let buf = unsafe { slice::from_raw_parts_mut(buf_ptr, n * PAGE_SIZE) };
-    node.read(buf).unwrap();
-    proc::create_process(buf);
+    // 大きさを調べた後に内容が変わるファイルもあるので, 読めなくても panic しない
+    if node.read(buf).is_err() {
+        log_warn!("ksyscall", "{}: read failed", path);
+        frame.a0 = -EIO;
+        return;
+    }
+
+    // プロセス名はパスの最後の要素にする
+    // ELF の検査ではファイルの大きさを超えて読まないように, ページの余りを渡さない
+    let name = path.rsplit('/').next().unwrap_or(path);
+    frame.a0 = match proc::create_process(name, &buf[..size], &args, &env, stdio) {
+        Ok(pid) => pid as isize,
+        Err(CreateError::InvalidElf(e)) => {
+            log_warn!("ksyscall", "{}: {}", path, e);
+            -ENOEXEC
+        }
+        Err(CreateError::NoResource) => -1,
+    };
+}
+
+/// 子プロセスの状態の変化を待つ. WNOHANG で変化が無かった場合は 0 を返す
+fn handle_wait_process(frame: &mut TrapFrame) {
+    let pid = frame.a0 as usize;
+    let status_ptr = frame.a1 as *mut i32;
+    let options = frame.a2;
+    let Some(status) = proc::wait_process(pid, options) else {
+        frame.a0 = -1;
+        return;
+    };
+    let Some(code) = status else {
+        frame.a0 = 0;
+        return;
+    };
+    if !status_ptr.is_null() && copy_to_user(status_ptr as *mut u8, &code.to_ne_bytes()).is_err() {
+        frame.a0 = -EFAULT;
+        return;
+    }
+    frame.a0 = pid as isize;
 }
 
-pub fn handle_syscall(trap_frame: *mut u8) {
-    let trap_frame_slice =
-        unsafe { core::slice::from_raw_parts_mut(trap_frame, size_of::<TrapFrame>()) };
+fn handle_setpgid(frame: &mut TrapFrame) {
+    let pid = frame.a0 as usize;
+    let pgid = frame.a1;
+    frame.a0 = match proc::set_pgid(pid, pgid) {
+        Ok(()) => 0,
+        Err(()) => -1,
+    };
+}
+
+fn handle_getpgid(frame: &mut TrapFrame) {
+    frame.a0 = match proc::get_pgid(frame.a0 as usize) {
+        Some(pgid) => pgid as isize,
+        None => -1,
+    };
+}
+
+/// ヒープの終わりを a0 にして新しい終わりを返す. a0 が 0 の場合は今の終わりを返す
+fn handle_brk(frame: &mut TrapFrame) {
+    frame.a0 = match proc::set_brk(frame.a0 as usize) {
+        Ok(brk) => brk as isize,
+        Err(()) => -ENOMEM,
+    };
+}
+
+/// mmap, munmap, mprotect の失敗をエラー番号にする
+fn map_error_code(err: proc::MapError) -> isize {
+    match err {
+        proc::MapError::Invalid => -EINVAL,
+        proc::MapError::NoMemory => -ENOMEM,
+    }
+}
 
-    let frame = TrapFrame::mut_from_prefix(trap_frame_slice).unwrap();
+/// a0 にアドレスのヒント, a1 に長さ, a2 に prot, a4 に flags を受け取る
+fn handle_mmap(frame: &mut TrapFrame) {
+    let (addr, len, prot, flags) = (frame.a0 as usize, frame.a1, frame.a2, frame.a4);
+    frame.a0 = match proc::mmap(addr, len, prot, flags) {
+        Ok(addr) => addr as isize,
+        Err(err) => map_error_code(err),
+    };
+}
+
+fn handle_munmap(frame: &mut TrapFrame) {
+    frame.a0 = match proc::munmap(frame.a0 as usize, frame.a1) {
+        Ok(()) => 0,
+        Err(err) => map_error_code(err),
+    };
+}
+
+fn handle_mprotect(frame: &mut TrapFrame) {
+    let (addr, len, prot) = (frame.a0 as usize, frame.a1, frame.a2);
+    frame.a0 = match proc::mprotect(addr, len, prot) {
+        Ok(()) => 0,
+        Err(err) => map_error_code(err),
+    };
+}
+
+fn handle_kill(frame: &mut TrapFrame) {
+    let pid = frame.a0;
+    let sig = frame.a1;
+    frame.a0 = match proc::kill(pid, sig) {
+        Ok(()) => 0,
+        Err(()) => -1,
+    };
+}
+
+/// a0 のシグナルの処理方法を a1 の SigAction にし, 前の処理方法を a2 に書き込む
+///
+/// a1, a2 は null でもよい
+fn handle_sigaction(frame: &mut TrapFrame) {
+    let sig = frame.a0 as usize;
+    let new_ptr = frame.a1 as *const SigAction;
+    let old_ptr = frame.a2 as *mut SigAction;
+    let new = if new_ptr.is_null() {
+        None
+    } else {
+        let mut action = SigAction::default();
+        let dst = unsafe {
+            slice::from_raw_parts_mut(
+                &mut action as *mut SigAction as *mut u8,
+                size_of::<SigAction>(),
+            )
+        };
+        let Ok(()) = copy_from_user(dst, new_ptr as *const u8) else {
+            frame.a0 = -EFAULT;
+            return;
+        };
+        Some(action)
+    };
+    let Ok(old) = proc::sigaction(sig, new) else {
+        frame.a0 = -1;
+        return;
+    };
+    if !old_ptr.is_null() {
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                &old as *const SigAction as *const u8,
+                size_of::<SigAction>(),
+            )
+        };
+        if copy_to_user(old_ptr as *mut u8, bytes).is_err() {
+            frame.a0 = -EFAULT;
+            return;
+        }
+    }
+    frame.a0 = 0;
+}
+
+/// 引数の列を buf に書き込み, 引数の列全体の長さを返す
+fn handle_get_args(frame: &mut TrapFrame) {
+    copy_list_to_user(frame, &proc::current_args());
+}
+
+/// 環境変数の列を buf に書き込み, 環境変数の列全体の長さを返す
+fn handle_get_env(frame: &mut TrapFrame) {
+    copy_list_to_user(frame, &proc::current_env());
+}
+
+/// a0, a1 のバッファに list を入る分だけ書き込み, 全体の長さを返す
+fn copy_list_to_user(frame: &mut TrapFrame, list: &[u8]) {
+    let buf_ptr = frame.a0 as *mut u8;
+    let len = frame.a1;
+    let n = len.min(list.len());
+    frame.a0 = match copy_to_user(buf_ptr, &list[..n]) {
+        Ok(()) => list.len() as isize,
+        Err(()) => -EFAULT,
+    };
+}
+
+fn handle_open(frame: &mut TrapFrame) {
+    let flags = frame.a2;
+    let bytes = match copy_bytes_from_user(frame.a0 as *const u8, frame.a1, PATH_MAX, ENAMETOOLONG)
+    {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            frame.a0 = err;
+            return;
+        }
+    };
+
+    let Ok(path) = core::str::from_utf8(&bytes) else {
+        frame.a0 = -1;
+        return;
+    };
+
+    let Some(file) = OpenFile::open(path, flags) else {
+        log_warn!("ksyscall", "open: '{}' not found", path);
+        frame.a0 = -1;
+        return;
+    };
+
+    frame.a0 = match proc::install_file(file) {
+        Some(fd) => fd as isize,
+        None => -1,
+    };
+}
+
+fn handle_read(frame: &mut TrapFrame) {
+    let fd = frame.a0 as usize;
+    let buf_ptr = frame.a1 as *mut u8;
+    let len = frame.a2;
+
+    let Some(file) = proc::get_file(fd) else {
+        frame.a0 = -1;
+        return;
+    };
+
+    // カーネルスタック上のバッファを経由して少しずつコピーする
+    let mut chunk = [0u8; IO_CHUNK_SIZE];
+    let mut total = 0;
+    while total < len {
+        let want = (len - total).min(IO_CHUNK_SIZE);
+        let Ok(n) = file.read(&mut chunk[..want]) else {
+            frame.a0 = -1;
+            return;
+        };
+        let Ok(()) = copy_to_user(unsafe { buf_ptr.add(total) }, &chunk[..n]) else {
+            frame.a0 = -EFAULT;
+            return;
+        };
+        total += n;
+        // 要求より少ない場合はそれ以上待たずに返す
+        if n < want {
+            break;
+        }
+    }
+    frame.a0 = total as isize;
+}
+
+fn handle_write(frame: &mut TrapFrame) {
+    let fd = frame.a0 as usize;
+    let buf_ptr = frame.a1 as *const u8;
+    let len = frame.a2;
+
+    let Some(file) = proc::get_file(fd) else {
+        frame.a0 = -1;
+        return;
+    };
+
+    let mut chunk = [0u8; IO_CHUNK_SIZE];
+    let mut total = 0;
+    while total < len {
+        let n = (len - total).min(IO_CHUNK_SIZE);
+        let Ok(()) = copy_from_user(&mut chunk[..n], unsafe { buf_ptr.add(total) }) else {
+            frame.a0 = -EFAULT;
+            return;
+        };
+        let Ok(written) = file.write(&chunk[..n]) else {
+            frame.a0 = -1;
+            return;
+        };
+        total += written;
+        if written < n {
+            break;
+        }
+    }
+    frame.a0 = total as isize;
+}
+
+fn handle_readdir(frame: &mut TrapFrame) {
+    let fd = frame.a0 as usize;
+    let buf_ptr = frame.a1 as *mut u8;
+    let len = frame.a2.min(IO_CHUNK_SIZE);
+
+    let Some(file) = proc::get_file(fd) else {
+        frame.a0 = -1;
+        return;
+    };
+
+    let mut name = [0u8; IO_CHUNK_SIZE];
+    frame.a0 = match file.read_dir(&mut name[..len]) {
+        Ok(n) => match copy_to_user(buf_ptr, &name[..n]) {
+            Ok(()) => n as isize,
+            Err(()) => -EFAULT,
+        },
+        Err(()) => -1,
+    };
+}
+
+fn handle_stat(frame: &mut TrapFrame) {
+    let stat_ptr = frame.a2 as *mut Metadata;
+    let bytes = match copy_bytes_from_user(frame.a0 as *const u8, frame.a1, PATH_MAX, ENAMETOOLONG)
+    {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            frame.a0 = err;
+            return;
+        }
+    };
+
+    let Some(node) = core::str::from_utf8(&bytes).ok().and_then(vfs::lookup) else {
+        frame.a0 = -1;
+        return;
+    };
+    frame.a0 = match copy_metadata_to_user(stat_ptr, &node.metadata()) {
+        Ok(()) => 0,
+        Err(()) => -EFAULT,
+    };
+}
+
+fn handle_fstat(frame: &mut TrapFrame) {
+    let fd = frame.a0 as usize;
+    let stat_ptr = frame.a1 as *mut Metadata;
+
+    let Some(file) = proc::get_file(fd) else {
+        frame.a0 = -1;
+        return;
+    };
+    frame.a0 = match copy_metadata_to_user(stat_ptr, &file.metadata()) {
+        Ok(()) => 0,
+        Err(()) => -EFAULT,
+    };
+}
+
+fn handle_pipe(frame: &mut TrapFrame) {
+    let fds_ptr = frame.a0 as *mut usize;
+
+    let (reader, writer) = pipe::create();
+    let Some(read_fd) = proc::install_file(OpenFile::new(Rc::new(reader))) else {
+        frame.a0 = -1;
+        return;
+    };
+    let Some(write_fd) = proc::install_file(OpenFile::new(Rc::new(writer))) else {
+        let _ = proc::close_file(read_fd);
+        frame.a0 = -1;
+        return;
+    };
+
+    let fds = [read_fd, write_fd];
+    let bytes = unsafe { slice::from_raw_parts(fds.as_ptr() as *const u8, size_of_val(&fds)) };
+    frame.a0 = match copy_to_user(fds_ptr as *mut u8, bytes) {
+        Ok(()) => 0,
+        Err(()) => {
+            let _ = proc::close_file(read_fd);
+            let _ = proc::close_file(write_fd);
+            -EFAULT
+        }
+    };
+}
+
+fn handle_dup(frame: &mut TrapFrame) {
+    let fd = frame.a0 as usize;
+    frame.a0 = match proc::get_file(fd).and_then(proc::install_file) {
+        Some(new_fd) => new_fd as isize,
+        None => -1,
+    };
+}
+
+fn handle_dup2(frame: &mut TrapFrame) {
+    let old_fd = frame.a0 as usize;
+    let new_fd = frame.a1;
+    frame.a0 = match proc::dup_file(old_fd, new_fd) {
+        Ok(()) => new_fd as isize,
+        Err(()) => -1,
+    };
+}
+
+fn handle_close(frame: &mut TrapFrame) {
+    let fd = frame.a0 as usize;
+    frame.a0 = match proc::close_file(fd) {
+        Ok(()) => 0,
+        Err(()) => -1,
+    };
+}
+
+/// システムコールを処理する
+///
+/// 呼び出しの次の命令以外から再開する場合はその位置を返す
+pub fn handle_syscall(frame: &mut TrapFrame) -> Option<usize> {
     let sysno = frame.a3;
     match sysno {
         SYS_WRITE_BYTE => {
@@ -94,8 +491,7 @@ pub fn handle_syscall(trap_frame: *mut u8) {
             Writer::write_byte(c).unwrap();
         }
         SYS_READ_BYTE => loop {
-            let byte = console::read_byte();
-            if byte >= 0 {
+            if let Some(byte) = console::pop_input() {
                 frame.a0 = byte as isize;
                 break;
             }
@@ -104,7 +500,8 @@ pub fn handle_syscall(trap_frame: *mut u8) {
             proc::yield_process();
         }
         SYS_EXIT_PROCESS => {
-            proc::end_process();
+            // 終了コードは 0 から 255 の範囲にする
+            proc::end_process(frame.a0 as i32 & 0xff);
         }
         SYS_CREATE_PROCESS => {
             handle_create_process(frame);
@@ -112,6 +509,89 @@ pub fn handle_syscall(trap_frame: *mut u8) {
         SYS_LIST_PROCESS => {
             proc::show_process_list(false);
         }
+        SYS_OPEN => {
+            handle_open(frame);
+        }
+        SYS_READ => {
+            handle_read(frame);
+        }
+        SYS_WRITE => {
+            handle_write(frame);
+        }
+        SYS_CLOSE => {
+            handle_close(frame);
+        }
+        SYS_READDIR => {
+            handle_readdir(frame);
+        }
+        SYS_STAT => {
+            handle_stat(frame);
+        }
+        SYS_FSTAT => {
+            handle_fstat(frame);
+        }
+        SYS_PIPE => {
+            handle_pipe(frame);
+        }
+        SYS_DUP => {
+            handle_dup(frame);
+        }
+        SYS_DUP2 => {
+            handle_dup2(frame);
+        }
+        SYS_WAIT_PROCESS => {
+            handle_wait_process(frame);
+        }
+        SYS_GET_ARGS => {
+            handle_get_args(frame);
+        }
+        SYS_GET_ENV => {
+            handle_get_env(frame);
+        }
+        SYS_SETPGID => {
+            handle_setpgid(frame);
+        }
+        SYS_GETPGID => {
+            handle_getpgid(frame);
+        }
+        SYS_TCSETPGRP => {
+            proc::set_foreground(frame.a0 as usize);
+            frame.a0 = 0;
+        }
+        SYS_TCGETPGRP => {
+            frame.a0 = proc::foreground() as isize;
+        }
+        SYS_KILL => {
+            handle_kill(frame);
+        }
+        SYS_SIGACTION => {
+            handle_sigaction(frame);
+        }
+        SYS_SIGPROCMASK => {
+            frame.a0 = match proc::sigprocmask(frame.a0 as usize, frame.a1 as u32) {
+                Ok(old) => old as isize,
+                Err(()) => -1,
+            };
+        }
+        SYS_SIGRETURN => {
+            // 戻せなかった場合は SIGSEGV が届いているので, 次の命令から再開して処理させる
+            if let Some(pc) = proc::signal_return(frame) {
+                return Some(pc);
+            }
+        }
+        SYS_BRK => {
+            handle_brk(frame);
+        }
+        SYS_MMAP => {
+            handle_mmap(frame);
+        }
+        SYS_MUNMAP => {
+            handle_munmap(frame);
+        }
+        SYS_MPROTECT => {
+            handle_mprotect(frame);
+        }
         _ => unimplemented!("{}", sysno),
     }
+    None
 }
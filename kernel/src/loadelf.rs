@@ -1,4 +1,9 @@
-use crate::{log_debug, log_info, mem::PageFlags};
+use core::fmt;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::{allocator::PAGE_SIZE, log_debug, log_info, mem::PageFlags, proc::IMAGE_END};
 use bitflags::bitflags;
 use zerocopy::{FromBytes, FromZeroes};
 
@@ -25,6 +30,21 @@ struct Elf64Ehdr {
     e_shstrndx: u16,   /* Section header string table index */
 }
 
+#[derive(Debug, FromZeroes, FromBytes)]
+#[repr(C)]
+struct Elf64Dyn {
+    d_tag: i64, /* Dynamic entry type */
+    d_val: u64, /* Integer or address value */
+}
+
+#[derive(Debug, FromZeroes, FromBytes)]
+#[repr(C)]
+struct Elf64Rela {
+    r_offset: u64, /* Address */
+    r_info: u64,   /* Relocation type and symbol index */
+    r_addend: i64, /* Addend */
+}
+
 #[derive(Debug, FromZeroes, FromBytes)]
 #[repr(C)]
 struct Elf64Phdr {
@@ -33,7 +53,7 @@ struct Elf64Phdr {
     p_offset: u64, /* Segment file offset */
     p_vaddr: u64,  /* Segment virtual address */
     p_paddr: u64,  /* Segment physical address */
-    p_filesz: i64, /* Segment size in file */
+    p_filesz: u64, /* Segment size in file */
     p_memsz: u64,  /* Segment size in memory */
     p_align: u64,  /* Segment alignment */
 }
@@ -43,8 +63,117 @@ struct Elf64Phdr {
 // create_process_from_loaded() に渡せる形にする
 //
 
+const ELFMAG: [u8; 4] = *b"\x7fELF";
+const EI_CLASS: usize = 4;
+const EI_DATA: usize = 5;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+
 const PT_LOAD: u32 = 1;
-const SEGMENT_MAX: usize = 12;
+const PT_DYNAMIC: u32 = 2;
+const PT_NOTE: u32 = 4;
+const PT_TLS: u32 = 7;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+
+const DT_NULL: i64 = 0;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_RELAENT: i64 = 9;
+const DT_REL: i64 = 17;
+
+const R_RISCV_NONE: u32 = 0;
+const R_RISCV_RELATIVE: u32 = 3;
+
+/// 1つの PT_LOAD の p_memsz の上限
+const SEGMENT_MAX: usize = 64 * 1024 * 1024;
+/// PT_TLS の p_memsz の上限. TLS の領域はプロセスを作るときに全て割り当てる
+const TLS_MAX: usize = 1024 * 1024;
+/// PT_TLS の p_align の上限
+const TLS_ALIGN_MAX: usize = 64 * 1024;
+
+/// ELF ファイルを読み込めなかった理由
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElfError {
+    /// ELF ヘッダがファイルに収まっていない
+    TooShort,
+    /// 先頭が `\x7fELF` でない
+    BadMagic,
+    /// 64ビットの ELF でない
+    UnsupportedClass(u8),
+    /// リトルエンディアンでない
+    UnsupportedEndian(u8),
+    /// RISC-V 用でない
+    UnsupportedMachine(u16),
+    /// 実行ファイルでない
+    UnsupportedType(u16),
+    /// プログラムヘッダの大きさが Elf64Phdr と違う
+    BadPhentsize(u16),
+    /// プログラムヘッダの表がファイルに収まっていない
+    PhdrOutOfBounds,
+    /// 番号のプログラムヘッダのセグメントがファイルに収まっていない
+    SegmentOutOfBounds(usize),
+    /// 番号のプログラムヘッダの p_filesz が p_memsz より大きい
+    FileszExceedsMemsz(usize),
+    /// 番号のプログラムヘッダのセグメントの末尾がアドレス空間を超える
+    BadSegmentAddress(usize),
+    /// 番号の PT_LOAD の p_memsz が SEGMENT_MAX より大きい
+    SegmentTooLarge(usize),
+    /// 番号の PT_LOAD の末尾が IMAGE_END を超え, ヒープや mmap, スタック, カーネルの領域に入る
+    SegmentOutOfRange(usize),
+    /// PIE を PIE_BASE 以上に移すと, プログラムと TLS の領域が IMAGE_END を超える
+    ImageTooLarge,
+    /// 中身のある PT_LOAD が1つも無い
+    NoLoadSegment,
+    /// e_entry が実行できる PT_LOAD の中に無い
+    BadEntry,
+    /// PT_TLS が2つ以上ある
+    MultipleTls,
+    /// PT_TLS の初期値が PT_LOAD の外にあるか, 大きさや境界の指定が正しくないか,
+    /// TLS の領域がプログラムを置ける範囲に収まらない
+    BadTls,
+    /// 番号のプログラムヘッダの PT_NOTE の中身が壊れている
+    BadNote(usize),
+    /// 番号のプログラムヘッダの PT_DYNAMIC の中身が壊れているか, 再配置の表が読めない
+    BadDynamic(usize),
+    /// 扱えない種類の再配置
+    UnsupportedRelocation(u32),
+    /// 番号の再配置の書き込み先が読み込むセグメントの外にある
+    BadRelocation(usize),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ElfError::TooShort => write!(f, "file too short for ELF header"),
+            ElfError::BadMagic => write!(f, "bad ELF magic"),
+            ElfError::UnsupportedClass(class) => write!(f, "unsupported ELF class {}", class),
+            ElfError::UnsupportedEndian(data) => {
+                write!(f, "unsupported ELF data encoding {}", data)
+            }
+            ElfError::UnsupportedMachine(machine) => write!(f, "unsupported machine {}", machine),
+            ElfError::UnsupportedType(ty) => write!(f, "unsupported ELF type {}", ty),
+            ElfError::BadPhentsize(size) => write!(f, "bad program header size {}", size),
+            ElfError::PhdrOutOfBounds => write!(f, "program headers out of file"),
+            ElfError::SegmentOutOfBounds(i) => write!(f, "segment {} out of file", i),
+            ElfError::FileszExceedsMemsz(i) => write!(f, "segment {} has filesz > memsz", i),
+            ElfError::BadSegmentAddress(i) => write!(f, "segment {} has bad address", i),
+            ElfError::SegmentTooLarge(i) => write!(f, "segment {} is too large", i),
+            ElfError::SegmentOutOfRange(i) => write!(f, "segment {} out of user image range", i),
+            ElfError::ImageTooLarge => write!(f, "image does not fit below heap area"),
+            ElfError::NoLoadSegment => write!(f, "no loadable segment"),
+            ElfError::BadEntry => write!(f, "entry point not in executable segment"),
+            ElfError::MultipleTls => write!(f, "multiple TLS segments"),
+            ElfError::BadTls => write!(f, "bad TLS segment"),
+            ElfError::BadNote(i) => write!(f, "segment {} has malformed notes", i),
+            ElfError::BadDynamic(i) => write!(f, "segment {} has malformed dynamic section", i),
+            ElfError::UnsupportedRelocation(ty) => write!(f, "unsupported relocation type {}", ty),
+            ElfError::BadRelocation(i) => write!(f, "relocation {} out of image", i),
+        }
+    }
+}
 
 bitflags! {
     #[derive(Debug, Clone, Copy)]
@@ -65,15 +194,122 @@ pub struct LoadableSegment {
     pub memsz: usize,
 }
 
+/// PT_TLS の情報. スレッドごとの領域の初期値になる
+#[derive(Debug)]
+pub struct TlsTemplate {
+    /// 初期値は PT_LOAD のセグメントにも含まれていて, この位置から filesz だけ読める
+    pub vaddr: usize,
+    /// 初期値の大きさ. 残りの memsz までは 0 で埋める
+    pub filesz: usize,
+    pub memsz: usize,
+    pub align: usize,
+}
+
+/// PT_GNU_STACK の情報
+#[derive(Debug)]
+pub struct StackInfo {
+    /// 要求されたスタックの大きさ. 0 の場合は指定なし
+    pub size: usize,
+    pub executable: bool,
+}
+
+/// 読み込んだ後に vaddr へ value の 8 バイトを書き込む再配置
+#[derive(Debug)]
+pub struct Relocation {
+    pub vaddr: usize,
+    pub value: usize,
+}
+
 #[derive(Debug)]
 pub struct LoadedElf {
     pub entry_point: usize,
-    pub loadable_segments: [Option<LoadableSegment>; SEGMENT_MAX],
+    pub loadable_segments: Vec<LoadableSegment>,
+    pub tls: Option<TlsTemplate>,
+    pub stack: Option<StackInfo>,
+    /// 位置独立実行形式 (ET_DYN) で, 任意の位置に読み込めるか
+    pub pie: bool,
+    pub relocations: Vec<Relocation>,
+}
+
+impl LoadedElf {
+    /// 読み込むセグメント全体が占める仮想アドレスの範囲 (開始, 終了)
+    pub fn image_range(&self) -> Option<(usize, usize)> {
+        let start = self.loadable_segments.iter().map(|seg| seg.vaddr).min()?;
+        let end = self
+            .loadable_segments
+            .iter()
+            .map(|seg| seg.vaddr + seg.memsz)
+            .max()?;
+        Some((start, end))
+    }
+
+    /// 全てのアドレスを bias だけずらす. PIE を本来と違う位置に読み込むときに使う
+    pub fn relocate(&mut self, bias: usize) {
+        self.entry_point += bias;
+        for seg in self.loadable_segments.iter_mut() {
+            seg.vaddr += bias;
+        }
+        if let Some(tls) = &mut self.tls {
+            tls.vaddr += bias;
+        }
+        for reloc in self.relocations.iter_mut() {
+            reloc.vaddr += bias;
+            reloc.value = reloc.value.wrapping_add(bias);
+        }
+    }
+
+    /// vaddr から len バイトが読み込むセグメントのどれかに収まっているか
+    fn contains(&self, vaddr: usize, len: usize) -> bool {
+        self.loadable_segments.iter().any(|seg| {
+            seg.vaddr <= vaddr
+                && vaddr
+                    .checked_add(len)
+                    .is_some_and(|end| end <= seg.vaddr + seg.memsz)
+        })
+    }
+
+    /// vaddr から len バイトのファイル上の内容. セグメントのファイルの部分に無い場合は None
+    fn file_data(&self, vaddr: usize, len: usize) -> Option<&'static [u8]> {
+        let seg = self.loadable_segments.iter().find(|seg| {
+            seg.vaddr <= vaddr
+                && vaddr
+                    .checked_add(len)
+                    .is_some_and(|end| end <= seg.vaddr + seg.filesz)
+        })?;
+        let start = vaddr - seg.vaddr;
+        Some(&seg.data[start..start + len])
+    }
+
+    /// 読み込んで再配置を適用した後の vaddr からの内容を buf に書き込む
+    ///
+    /// セグメントのファイルの部分に無いところ (BSS やセグメントの間) は 0 になる
+    pub fn read_image(&self, vaddr: usize, buf: &mut [u8]) {
+        buf.fill(0);
+        for seg in self.loadable_segments.iter() {
+            copy_overlap(buf, vaddr, seg.vaddr, seg.data);
+        }
+        for reloc in self.relocations.iter() {
+            copy_overlap(buf, vaddr, reloc.vaddr, &(reloc.value as u64).to_le_bytes());
+        }
+    }
+}
+
+/// buf が start から, data が data_start から始まるとみなして, 重なる部分を buf にコピーする
+fn copy_overlap(buf: &mut [u8], start: usize, data_start: usize, data: &[u8]) {
+    let from = start.max(data_start);
+    let to = (start + buf.len()).min(data_start + data.len());
+    if from < to {
+        buf[from - start..to - start].copy_from_slice(&data[from - data_start..to - data_start]);
+    }
 }
 
-pub fn load_elf(elf_data: &'static [u8]) -> LoadedElf {
+/// ELF ファイルを検査し, 読み込むセグメントの一覧を作る
+///
+/// 壊れたファイルでもパニックせずに ElfError を返す
+pub fn load_elf(elf_data: &'static [u8]) -> Result<LoadedElf, ElfError> {
     log_info!("load_elf", "Loading elf data at {:p}", elf_data);
-    let ehdr = Elf64Ehdr::read_from_prefix(elf_data).unwrap();
+    let ehdr = Elf64Ehdr::read_from_prefix(elf_data).ok_or(ElfError::TooShort)?;
+    check_ehdr(&ehdr)?;
 
     log_debug!("load_elf", "Loading ELF header:");
     let e_entry = ehdr.e_entry as usize;
@@ -83,20 +319,27 @@ pub fn load_elf(elf_data: &'static [u8]) -> LoadedElf {
     log_debug!("load_elf", "e_phoff={:#x}", e_phoff);
     log_debug!("load_elf", "e_phnum={:#x}", e_phnum);
 
-    let mut segments = [const { None }; SEGMENT_MAX];
+    // プログラムヘッダの表全体がファイルに収まっているか
+    let phdrs = e_phnum
+        .checked_mul(size_of::<Elf64Phdr>())
+        .and_then(|size| elf_data.get(e_phoff..e_phoff.checked_add(size)?))
+        .ok_or(ElfError::PhdrOutOfBounds)?;
 
-    for (i, loadable_segment) in segments.iter_mut().enumerate().take(e_phnum) {
+    let mut loaded = LoadedElf {
+        entry_point: e_entry,
+        loadable_segments: Vec::new(),
+        tls: None,
+        stack: None,
+        pie: ehdr.e_type == ET_DYN,
+        relocations: Vec::new(),
+    };
+    let mut dynamic = None;
+
+    for i in 0..e_phnum {
         // プログラムヘッダの情報が入った構造体を作る
-        let ph_start = e_phoff + i * size_of::<Elf64Phdr>();
+        let ph_start = i * size_of::<Elf64Phdr>();
         let phdr =
-            Elf64Phdr::read_from_prefix(&elf_data[ph_start..]).expect("read_from_prefix failed!");
-
-        // PT_LOAD のみを収集する
-        if phdr.p_type != PT_LOAD {
-            continue;
-        }
-
-        log_debug!("load_elf", "Program header (PT_LOAD) {}:", i);
+            Elf64Phdr::read_from_prefix(&phdrs[ph_start..]).ok_or(ElfError::PhdrOutOfBounds)?;
 
         // 変数を取り出す
         let p_type = phdr.p_type;
@@ -105,6 +348,40 @@ pub fn load_elf(elf_data: &'static [u8]) -> LoadedElf {
         let p_vaddr = phdr.p_vaddr as usize;
         let p_filesz = phdr.p_filesz as usize;
         let p_memsz = phdr.p_memsz as usize;
+        let p_align = phdr.p_align as usize;
+
+        match p_type {
+            PT_LOAD | PT_DYNAMIC | PT_TLS | PT_NOTE => {}
+            // PT_GNU_STACK はファイルの内容を持たない
+            PT_GNU_STACK => {
+                let executable =
+                    SegmentFlags::from_bits_truncate(p_flags).contains(SegmentFlags::X);
+                log_debug!(
+                    "load_elf",
+                    "Program header (PT_GNU_STACK) {}: size={:#x} executable={}",
+                    i,
+                    p_memsz,
+                    executable
+                );
+                loaded.stack = Some(StackInfo {
+                    size: p_memsz,
+                    executable,
+                });
+                continue;
+            }
+            // それ以外は読み込みに関係しないので無視する
+            _ => {
+                log_debug!(
+                    "load_elf",
+                    "Program header {}: ignored p_type={:#x}",
+                    i,
+                    p_type
+                );
+                continue;
+            }
+        }
+
+        log_debug!("load_elf", "Program header {}:", i);
         log_debug!("load_elf", "p_type={:#x}", p_type);
         log_debug!("load_elf", "p_flags={:#x}", p_flags);
         log_debug!("load_elf", "p_offset={:#x}", p_offset);
@@ -112,32 +389,212 @@ pub fn load_elf(elf_data: &'static [u8]) -> LoadedElf {
         log_debug!("load_elf", "p_filesz={:#x}", p_filesz);
         log_debug!("load_elf", "p_memsz={:#x}", p_memsz);
 
-        // フラグをページテーブルの使うフラグに変換する
-        let seg_flags = SegmentFlags::from_bits_truncate(p_flags);
-        let mut page_flags = PageFlags::empty();
-        if seg_flags.contains(SegmentFlags::R) {
-            page_flags |= PageFlags::R;
+        if p_filesz > p_memsz {
+            return Err(ElfError::FileszExceedsMemsz(i));
         }
-        if seg_flags.contains(SegmentFlags::W) {
-            page_flags |= PageFlags::W;
+        // ページ境界に切り上げても溢れないようにする
+        if p_vaddr
+            .checked_add(p_memsz)
+            .and_then(|end| end.checked_add(PAGE_SIZE))
+            .is_none()
+        {
+            return Err(ElfError::BadSegmentAddress(i));
         }
-        if seg_flags.contains(SegmentFlags::X) {
-            page_flags |= PageFlags::X;
+        // PIE はずらす前の位置で確かめる. ずらした後の位置は choose_load_bias() が確かめる
+        if p_type == PT_LOAD {
+            if p_memsz > SEGMENT_MAX {
+                return Err(ElfError::SegmentTooLarge(i));
+            }
+            if p_vaddr + p_memsz > IMAGE_END {
+                return Err(ElfError::SegmentOutOfRange(i));
+            }
         }
+        let data = p_offset
+            .checked_add(p_filesz)
+            .and_then(|end| elf_data.get(p_offset..end))
+            .ok_or(ElfError::SegmentOutOfBounds(i))?;
 
-        let seg = LoadableSegment {
-            flags: page_flags,
-            vaddr: p_vaddr,
-            data: &elf_data[p_offset..p_offset + p_filesz],
-            filesz: p_filesz,
-            memsz: p_memsz,
-        };
+        match p_type {
+            PT_LOAD => loaded.loadable_segments.push(LoadableSegment {
+                flags: page_flags(p_flags),
+                vaddr: p_vaddr,
+                data,
+                filesz: p_filesz,
+                memsz: p_memsz,
+            }),
+            PT_TLS => {
+                if loaded.tls.is_some() {
+                    return Err(ElfError::MultipleTls);
+                }
+                loaded.tls = Some(TlsTemplate {
+                    vaddr: p_vaddr,
+                    filesz: p_filesz,
+                    memsz: p_memsz,
+                    align: p_align.max(1),
+                });
+            }
+            // 再配置の表は PT_LOAD の中にあるので, 全てのセグメントを読んでから調べる
+            PT_DYNAMIC => dynamic = Some((i, data)),
+            _ => log_notes(data, p_align).ok_or(ElfError::BadNote(i))?,
+        }
+    }
 
-        *loadable_segment = Some(seg);
+    if !loaded.loadable_segments.iter().any(|seg| seg.memsz > 0) {
+        return Err(ElfError::NoLoadSegment);
+    }
+    if !loaded.loadable_segments.iter().any(|seg| {
+        seg.flags.contains(PageFlags::X) && seg.vaddr <= e_entry && e_entry < seg.vaddr + seg.memsz
+    }) {
+        return Err(ElfError::BadEntry);
     }
+    if let Some(tls) = &loaded.tls
+        && (!tls.align.is_power_of_two()
+            || tls.align > TLS_ALIGN_MAX
+            || tls.memsz > TLS_MAX
+            || !loaded.contains(tls.vaddr, tls.filesz))
+    {
+        return Err(ElfError::BadTls);
+    }
+    if let Some((i, data)) = dynamic {
+        loaded.relocations = read_relocations(&loaded, i, data)?;
+    }
+    log_debug!(
+        "load_elf",
+        "pie={} relocations={}",
+        loaded.pie,
+        loaded.relocations.len()
+    );
 
-    LoadedElf {
-        entry_point: e_entry,
-        loadable_segments: segments,
+    Ok(loaded)
+}
+
+/// 番号 index のプログラムヘッダの PT_DYNAMIC の中身から RELA の表を探し, 再配置の一覧を作る
+///
+/// 扱えるのは R_RISCV_RELATIVE だけ
+fn read_relocations(
+    loaded: &LoadedElf,
+    index: usize,
+    dynamic: &[u8],
+) -> Result<Vec<Relocation>, ElfError> {
+    let bad = ElfError::BadDynamic(index);
+    let mut rela = None;
+    let mut relasz = 0;
+    let mut relaent = size_of::<Elf64Rela>();
+    for i in 0..dynamic.len() / size_of::<Elf64Dyn>() {
+        let entry = Elf64Dyn::read_from_prefix(&dynamic[i * size_of::<Elf64Dyn>()..]).ok_or(bad)?;
+        let value = entry.d_val as usize;
+        match entry.d_tag {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => relasz = value,
+            DT_RELAENT => relaent = value,
+            // RISC-V では RELA だけを使う
+            DT_REL => return Err(bad),
+            _ => {}
+        }
+    }
+    if relaent != size_of::<Elf64Rela>() {
+        return Err(bad);
+    }
+    let Some(rela) = rela else {
+        return Ok(Vec::new());
+    };
+    let table = loaded.file_data(rela, relasz).ok_or(bad)?;
+
+    let mut relocations = Vec::new();
+    for i in 0..relasz / relaent {
+        let entry = Elf64Rela::read_from_prefix(&table[i * relaent..]).ok_or(bad)?;
+        let r_type = (entry.r_info & 0xffff_ffff) as u32;
+        let vaddr = entry.r_offset as usize;
+        match r_type {
+            R_RISCV_NONE => {}
+            R_RISCV_RELATIVE => {
+                if !loaded.contains(vaddr, size_of::<u64>()) {
+                    return Err(ElfError::BadRelocation(i));
+                }
+                relocations.push(Relocation {
+                    vaddr,
+                    value: entry.r_addend as usize,
+                });
+            }
+            _ => return Err(ElfError::UnsupportedRelocation(r_type)),
+        }
+    }
+    Ok(relocations)
+}
+
+/// セグメントのフラグをページテーブルの使うフラグに変換する
+fn page_flags(p_flags: u32) -> PageFlags {
+    let seg_flags = SegmentFlags::from_bits_truncate(p_flags);
+    let mut page_flags = PageFlags::empty();
+    if seg_flags.contains(SegmentFlags::R) {
+        page_flags |= PageFlags::R;
+    }
+    if seg_flags.contains(SegmentFlags::W) {
+        page_flags |= PageFlags::W;
+    }
+    if seg_flags.contains(SegmentFlags::X) {
+        page_flags |= PageFlags::X;
+    }
+    page_flags
+}
+
+/// PT_NOTE の中身を読み, 1つずつログに出す
+///
+/// 各ノートは namesz, descsz, type の後に名前と内容が続き, それぞれ align に揃えられている
+/// 壊れている場合は None を返す
+fn log_notes(mut data: &[u8], align: usize) -> Option<()> {
+    // p_align が 8 のもの以外は 4 バイトに揃える
+    let align = if align == 8 { 8 } else { 4 };
+    let pad = |n: usize| n.checked_next_multiple_of(align);
+    while !data.is_empty() {
+        let word = |i: usize| -> Option<usize> {
+            let bytes = data.get(i * 4..i * 4 + 4)?;
+            Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+        };
+        let (namesz, descsz, note_type) = (word(0)?, word(1)?, word(2)?);
+        let name_start: usize = 12;
+        let desc_start = pad(name_start.checked_add(namesz)?)?;
+        let next = pad(desc_start.checked_add(descsz)?)?;
+        if desc_start + descsz > data.len() {
+            return None;
+        }
+        // 名前は NUL で終わる
+        let name = &data[name_start..name_start + namesz];
+        let name = name.strip_suffix(b"\0").unwrap_or(name);
+        log_debug!(
+            "load_elf",
+            "note name={:?} type={:#x} descsz={:#x}",
+            core::str::from_utf8(name).unwrap_or("?"),
+            note_type,
+            descsz
+        );
+        // 最後のノートは末尾の詰め物が無いことがある
+        data = data.get(next..).unwrap_or(&[]);
+    }
+    Some(())
+}
+
+/// このカーネルで実行できる ELF ファイルのヘッダか調べる
+fn check_ehdr(ehdr: &Elf64Ehdr) -> Result<(), ElfError> {
+    if ehdr.e_ident[..ELFMAG.len()] != ELFMAG {
+        return Err(ElfError::BadMagic);
+    }
+    if ehdr.e_ident[EI_CLASS] != ELFCLASS64 {
+        return Err(ElfError::UnsupportedClass(ehdr.e_ident[EI_CLASS]));
+    }
+    if ehdr.e_ident[EI_DATA] != ELFDATA2LSB {
+        return Err(ElfError::UnsupportedEndian(ehdr.e_ident[EI_DATA]));
+    }
+    if ehdr.e_machine != EM_RISCV {
+        return Err(ElfError::UnsupportedMachine(ehdr.e_machine));
+    }
+    if ehdr.e_type != ET_EXEC && ehdr.e_type != ET_DYN {
+        return Err(ElfError::UnsupportedType(ehdr.e_type));
+    }
+    // プログラムヘッダが無い場合は大きさが 0 のこともある
+    if ehdr.e_phnum > 0 && ehdr.e_phentsize as usize != size_of::<Elf64Phdr>() {
+        return Err(ElfError::BadPhentsize(ehdr.e_phentsize));
     }
+    Ok(())
 }
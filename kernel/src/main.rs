@@ -8,12 +8,18 @@ mod allocator;
 mod boot;
 mod console;
 mod csr;
+mod devfs;
+mod file;
 mod ksyscall;
 mod loadelf;
 mod log;
 mod mem;
+mod pipe;
 mod proc;
+mod procfs;
+mod random;
 mod timer;
+mod tmpfs;
 mod trap;
 mod utils;
 mod vfs;
@@ -30,6 +36,9 @@ use crate::{
 #[unsafe(no_mangle)]
 pub static SH_ELF: &[u8] = include_bytes!("../../sh.elf");
 pub static PS_ELF: &[u8] = include_bytes!("../../ps.elf");
+pub static CAT_ELF: &[u8] = include_bytes!("../../cat.elf");
+pub static GREP_ELF: &[u8] = include_bytes!("../../grep.elf");
+pub static PRINTENV_ELF: &[u8] = include_bytes!("../../printenv.elf");
 
 fn dump_main_info() {
     log_info!("main", "kernel_entry\t\t: {:p}", kernel_entry as *const u8);
@@ -54,9 +63,10 @@ fn main() {
 
     proc::create_idle_process();
     let buf = test_vfs(vfs::MemoryFs);
-    proc::create_process(buf);
+    proc::create_process("sh", buf, &[], &[], None).expect("failed to create sh");
 
     proc::dump_process_list(false);
+    timer::init();
     proc::start_process();
     unreachable!()
 }
@@ -1,67 +1,67 @@
-use bitflags::bitflags;
+use pagetable::FrameAllocator;
+
+pub use pagetable::PageFlags;
 
 use crate::allocator::{self, PAGE_SIZE};
-use crate::utils::is_aligned;
+use crate::csr;
 
 pub const SATP_SV39: usize = 8 << 60;
-const VPN_MASK: usize = 0b1_1111_1111;
 
-bitflags! {
-    #[derive(Debug, Clone, Copy)]
-    pub struct PageFlags: usize {
-        const V = 1 << 0;
-        const R = 1 << 1;
-        const W = 1 << 2;
-        const X = 1 << 3;
-        const U = 1 << 4;
-    }
-}
+/// カーネルのページアロケータから割り当てるページテーブル
+pub type PageTable = pagetable::PageTable<KernelFrames>;
 
-pub fn map_page(table2: &mut [usize], vaddr: usize, paddr: usize, flags: PageFlags) {
-    if !is_aligned(vaddr, PAGE_SIZE) {
-        panic!("vaddr={:p} is not aligned", vaddr as *const u8);
-    }
+/// ページテーブルの段に使うページを PAGE_ALLOC から割り当て, 返す FrameAllocator
+#[derive(Debug)]
+pub struct KernelFrames;
 
-    if !is_aligned(paddr, PAGE_SIZE) {
-        panic!("paddr={:p} is not aligned", paddr as *const u8);
+impl FrameAllocator for KernelFrames {
+    fn alloc_frame(&mut self) -> Option<usize> {
+        if !allocator::PAGE_ALLOC.can_alloc(1) {
+            return None;
+        }
+        // alloc_pages はゼロクリアしたページを返す
+        Some(allocator::PAGE_ALLOC.alloc_pages::<usize>(1).as_mut_ptr() as usize)
     }
 
-    let vpn2 = vaddr >> 30 & VPN_MASK;
-    if table2[vpn2] & PageFlags::V.bits() == 0 {
-        // このエントリに対応する2段目のページテーブルが存在しないので作成する
-        let pt_paddr = allocator::PAGE_ALLOC.alloc_pages::<usize>(1).as_mut_ptr() as usize;
-        table2[vpn2] = (pt_paddr / PAGE_SIZE) << 10 | PageFlags::V.bits();
+    fn free_frame(&mut self, paddr: usize) {
+        allocator::PAGE_ALLOC.free_page(paddr);
     }
+}
 
-    let vpn1 = vaddr >> 21 & VPN_MASK;
-    let table1 = unsafe {
-        // table1のアドレスは [usize; 512] を指しているので *mut usize
-        // 先ほど確保した pt_paddr を使わない理由は, 最初から定義されている場合もあるため
-        let table1_addr = (table2[vpn2] >> 10) * PAGE_SIZE;
-
-        if !is_aligned(table1_addr, PAGE_SIZE) {
-            panic!("table1_addr={:p} is not aligned", table1_addr as *const u8);
-        }
+/// page_table を使うときに satp に設定する値
+pub fn satp(page_table: &PageTable) -> usize {
+    SATP_SV39 | (page_table.root() / PAGE_SIZE)
+}
 
-        core::slice::from_raw_parts_mut(table1_addr as *mut usize, 512)
-    };
-    if table1[vpn1] & PageFlags::V.bits() == 0 {
-        // このエントリに対応する1段目のページテーブルが存在しないので作成する
-        let pt_paddr = allocator::PAGE_ALLOC.alloc_pages::<usize>(1).as_mut_ptr() as usize;
-        table1[vpn1] = (pt_paddr / PAGE_SIZE) << 10 | PageFlags::V.bits();
+/// ページテーブルを書き換えた後に, キャッシュされている古い変換を捨てる
+pub fn flush_tlb() {
+    unsafe {
+        core::arch::asm!("sfence.vma");
     }
+}
 
-    let vpn0 = vaddr >> 12 & VPN_MASK;
-    let table0 = unsafe {
-        let table0_addr = (table1[vpn1] >> 10) * PAGE_SIZE;
-
-        if !is_aligned(table0_addr, PAGE_SIZE) {
-            panic!("table0_addr={:p} is not aligned", table0_addr as *const u8);
-        }
+/// ユーザー空間の src から dst の長さ分だけコピーする
+///
+/// src から dst の長さ分が実行中のプロセスの読める領域に無い場合は何もせず Err を返す
+pub fn copy_from_user(dst: &mut [u8], src: *const u8) -> Result<(), ()> {
+    crate::proc::populate_user_range(src as usize, dst.len(), PageFlags::R)?;
+    unsafe {
+        csr::set_sum();
+        core::ptr::copy_nonoverlapping(src, dst.as_mut_ptr(), dst.len());
+        csr::clear_sum();
+    }
+    Ok(())
+}
 
-        core::slice::from_raw_parts_mut(table0_addr as *mut usize, 512)
-    };
-    // TODO: A/Dビットの設定
-    // ハードウェアの実装に依存する
-    table0[vpn0] = (paddr / PAGE_SIZE) << 10 | flags.bits() | PageFlags::V.bits();
+/// ユーザー空間の dst へ src をコピーする
+///
+/// dst から src の長さ分が実行中のプロセスの書き込める領域に無い場合は何もせず Err を返す
+pub fn copy_to_user(dst: *mut u8, src: &[u8]) -> Result<(), ()> {
+    crate::proc::populate_user_range(dst as usize, src.len(), PageFlags::W)?;
+    unsafe {
+        csr::set_sum();
+        core::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+        csr::clear_sum();
+    }
+    Ok(())
 }
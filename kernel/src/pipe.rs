@@ -0,0 +1,199 @@
+use core::cell::RefCell;
+extern crate alloc;
+use alloc::rc::Rc;
+
+use crate::{proc, vfs::Node};
+use syscall::{FILE_TYPE_FIFO, Metadata};
+
+//
+// プロセス間でデータを受け渡すパイプ
+//
+// 読み出し側と書き込み側の端をそれぞれ vfs::Node として扱う
+// 読めるデータや書き込める空きが無い場合はプロセスを待機させる
+//
+
+pub const PIPE_SIZE: usize = 512;
+
+struct PipeBuffer {
+    buf: [u8; PIPE_SIZE],
+    /// 次に読み出す位置
+    head: usize,
+    /// 溜まっているバイト数
+    len: usize,
+    readers: usize,
+    writers: usize,
+}
+
+impl PipeBuffer {
+    fn push(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(PIPE_SIZE - self.len);
+        for &b in &data[..n] {
+            self.buf[(self.head + self.len) % PIPE_SIZE] = b;
+            self.len += 1;
+        }
+        n
+    }
+
+    fn pop(&mut self, data: &mut [u8]) -> usize {
+        let n = data.len().min(self.len);
+        for b in &mut data[..n] {
+            *b = self.buf[self.head];
+            self.head = (self.head + 1) % PIPE_SIZE;
+            self.len -= 1;
+        }
+        n
+    }
+}
+
+struct Pipe {
+    inner: RefCell<PipeBuffer>,
+}
+
+impl Pipe {
+    /// 待機するプロセスを区別するための値
+    fn channel(self: &Rc<Self>) -> usize {
+        Rc::as_ptr(self) as usize
+    }
+}
+
+/// パイプを作り, (読み出し側, 書き込み側) の端を返す
+pub fn create() -> (PipeReader, PipeWriter) {
+    let pipe = Rc::new(Pipe {
+        inner: RefCell::new(PipeBuffer {
+            buf: [0; PIPE_SIZE],
+            head: 0,
+            len: 0,
+            readers: 1,
+            writers: 1,
+        }),
+    });
+    (PipeReader { pipe: pipe.clone() }, PipeWriter { pipe })
+}
+
+pub struct PipeReader {
+    pipe: Rc<Pipe>,
+}
+
+pub struct PipeWriter {
+    pipe: Rc<Pipe>,
+}
+
+fn pipe_metadata(pipe: &Rc<Pipe>) -> Metadata {
+    Metadata {
+        file_type: FILE_TYPE_FIFO,
+        mode: 0o600,
+        id: pipe.channel() as u64,
+        size: pipe.inner.borrow().len as u64,
+        ..Default::default()
+    }
+}
+
+impl Node for PipeReader {
+    fn get_id(&self) -> usize {
+        self.pipe.channel()
+    }
+
+    /// 読み出せるバイト数
+    fn size(&self) -> usize {
+        self.pipe.inner.borrow().len
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        pipe_metadata(&self.pipe)
+    }
+
+    /// 1バイト以上読めるまで待つ
+    ///
+    /// 書き込み側が全て閉じられていて空の場合は 0 を返す
+    /// 待っている間にシグナルで中断された場合はエラー
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            {
+                let mut inner = self.pipe.inner.borrow_mut();
+                if inner.len > 0 {
+                    let n = inner.pop(buf);
+                    drop(inner);
+                    // 空きができたので書き込み側を起こす
+                    proc::wakeup(self.pipe.channel());
+                    return Ok(n);
+                }
+                if inner.writers == 0 {
+                    return Ok(0);
+                }
+            }
+            if proc::interrupted() {
+                return Err(());
+            }
+            proc::sleep(self.pipe.channel());
+        }
+    }
+}
+
+impl Node for PipeWriter {
+    fn get_id(&self) -> usize {
+        self.pipe.channel()
+    }
+
+    fn size(&self) -> usize {
+        self.pipe.inner.borrow().len
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        pipe_metadata(&self.pipe)
+    }
+
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, ()> {
+        Err(())
+    }
+
+    /// 全て書き込めるまで待つ
+    ///
+    /// 読み出し側が全て閉じられている場合はエラー
+    /// 待っている間にシグナルで中断された場合は書き込めた分を返す
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize, ()> {
+        let mut written = 0;
+        while written < buf.len() {
+            {
+                let mut inner = self.pipe.inner.borrow_mut();
+                if inner.readers == 0 {
+                    return Err(());
+                }
+                written += inner.push(&buf[written..]);
+            }
+            // データが増えたので読み出し側を起こす
+            proc::wakeup(self.pipe.channel());
+            if written < buf.len() {
+                if proc::interrupted() {
+                    return if written > 0 { Ok(written) } else { Err(()) };
+                }
+                proc::sleep(self.pipe.channel());
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.inner.borrow_mut().readers -= 1;
+        proc::wakeup(self.pipe.channel());
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.inner.borrow_mut().writers -= 1;
+        proc::wakeup(self.pipe.channel());
+    }
+}
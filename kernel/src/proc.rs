@@ -17,10 +17,14 @@ impl Pid {
 }
 
 #[derive(Debug, PartialEq, Clone)]
-enum ProcState {
+pub enum ProcState {
     Unused,
     Runnable,
     Running,
+    /// wakeup() されるまで待機している
+    Blocked,
+    /// SIGCONT を受け取るまで実行しない
+    Stopped,
     Exited,
 }
 
@@ -87,25 +91,279 @@ impl Context {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// ユーザー空間にマップした領域
+///
+/// ページは最初にアクセスしたときに backing に従って内容を作り, マップする
+#[derive(Debug, Clone)]
+pub struct MappedRegion {
+    pub start: usize,
+    pub end: usize,
+    pub flags: PageFlags,
+    pub backing: Backing,
+}
+
+/// 領域のページの内容の元
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backing {
+    /// 0 で埋めたページ
+    Zero,
+    /// 実行ファイルのセグメントの内容に再配置を適用したもの
+    Image,
+}
+
+#[derive(Debug)]
 struct Process {
     pid: Pid,
+    /// 親プロセスの pid. カーネルが作ったプロセスや親が先に終了したものは 0
+    ppid: usize,
+    /// プロセスグループの id. シグナルをまとめて送る単位
+    pgid: usize,
+    name: String,
+    /// NUL で区切った引数の列
+    args: Vec<u8>,
+    /// NUL で区切った "NAME=value" の列
+    env: Vec<u8>,
+    exit_code: i32,
     state: ProcState,
     kernel_stack: KernelStack,
     context: Context,
-    pt_number: usize,
+    /// カーネル空間とユーザー空間をマップしたページテーブル. Unused のものは None
+    page_table: Option<mem::PageTable>,
     entry_point: usize,
+    /// ユーザーモードに入るときの tp. TLS の領域の先頭で, TLS が無い場合は 0
+    tp: usize,
+    /// ユーザーモードに入るときの sp. ユーザースタックの上端
+    user_sp: usize,
+    /// ヒープの先頭. プログラムと TLS の領域の直後のページ
+    heap_start: usize,
+    /// ヒープの終わり (program break). heap_start から brk を含むページまでを領域にしている
+    brk: usize,
+    files: [Option<Rc<OpenFile>>; NOFILE],
+    regions: Vec<MappedRegion>,
+    /// mmap でマップした領域 (VMA) の一覧. 開始アドレスの順に並べ, 重なりは無い
+    vmas: Vec<MappedRegion>,
+    /// Backing::Image の領域のページの内容を作るのに使う実行ファイル
+    image: Option<Rc<loadelf::LoadedElf>>,
+    /// Blocked のときに待っている対象
+    wait_channel: usize,
+    /// 届いていてまだ処理していないシグナルのビット集合
+    pending: u32,
+    /// 届いても処理を後回しにするシグナルのビット集合
+    blocked: u32,
+    /// シグナルごとの処理方法
+    actions: [SigAction; NSIG],
+    /// 停止したことを wait でまだ親に知らせていないか
+    stop_unreported: bool,
 }
 
 impl Process {
+    /// 切り替えるときに satp に設定する値
+    fn satp(&self) -> usize {
+        self.page_table.as_ref().map_or(0, mem::satp)
+    }
+
+    fn page_table_mut(&mut self) -> &mut mem::PageTable {
+        self.page_table.as_mut().expect("process has no page table")
+    }
+
+    /// ヒープの領域を heap_start から end までにする. end が heap_start 以下なら取り除く
+    fn set_heap_region(&mut self, end: usize) {
+        let start = self.heap_start;
+        self.regions.retain(|region| region.start != start);
+        if end > start {
+            self.regions.push(MappedRegion {
+                start,
+                end,
+                flags: PageFlags::R | PageFlags::W,
+                backing: Backing::Zero,
+            });
+            self.regions.sort_by_key(|region| region.start);
+        }
+    }
+
+    /// start から len バイトが, access を全て許す領域で隙間なく覆われているか
+    fn user_accessible(&self, start: usize, len: usize, access: PageFlags) -> bool {
+        let Some(end) = start.checked_add(len) else {
+            return false;
+        };
+        if end > USER_END {
+            return false;
+        }
+        let mut addr = start;
+        while addr < end {
+            let Some(region) = self.regions.iter().chain(&self.vmas).find(|region| {
+                region.flags.contains(access) && region.start <= addr && addr < region.end
+            }) else {
+                return false;
+            };
+            addr = region.end;
+        }
+        true
+    }
+
+    /// start から end までが mmap で使える範囲にあり, どの VMA とも重ならないか
+    fn vma_free(&self, start: usize, end: usize) -> bool {
+        start >= MMAP_BASE
+            && end <= MMAP_END
+            && !self
+                .vmas
+                .iter()
+                .any(|vma| vma.start < end && start < vma.end)
+    }
+
+    /// len バイトをマップできる空いている位置. hint が空いていればそこを使い, 無ければ下から探す
+    fn find_free_area(&self, hint: usize, len: usize) -> Option<usize> {
+        if hint
+            .checked_add(len)
+            .is_some_and(|end| self.vma_free(hint, end))
+        {
+            return Some(hint);
+        }
+        let mut start = MMAP_BASE;
+        for vma in self.vmas.iter() {
+            if start + len <= vma.start {
+                break;
+            }
+            start = start.max(vma.end);
+        }
+        (start.checked_add(len)? <= MMAP_END).then_some(start)
+    }
+
+    /// start から end までに隙間なく VMA があるか
+    fn vma_covers(&self, start: usize, end: usize) -> bool {
+        let mut pos = start;
+        for vma in self
+            .vmas
+            .iter()
+            .filter(|vma| vma.end > start && vma.start < end)
+        {
+            if vma.start > pos {
+                return false;
+            }
+            pos = vma.end;
+        }
+        pos >= end
+    }
+
+    /// addr をまたぐ VMA を addr で2つに分ける
+    fn split_vma_at(&mut self, addr: usize) {
+        if let Some(i) = self
+            .vmas
+            .iter()
+            .position(|vma| vma.start < addr && addr < vma.end)
+        {
+            let mut upper = self.vmas[i].clone();
+            upper.start = addr;
+            self.vmas[i].end = addr;
+            self.vmas.insert(i + 1, upper);
+        }
+    }
+
+    /// start と end で VMA を分け, start から end までに含まれる VMA の添字の範囲を返す
+    fn split_vmas(&mut self, start: usize, end: usize) -> Range<usize> {
+        self.split_vma_at(start);
+        self.split_vma_at(end);
+        let first = self.vmas.partition_point(|vma| vma.start < start);
+        let last = self.vmas.partition_point(|vma| vma.start < end);
+        first..last
+    }
+
+    /// 隣り合っていて権限の同じ VMA をつなげる
+    fn merge_vmas(&mut self) {
+        self.vmas.dedup_by(|next, prev| {
+            if prev.end == next.start && prev.flags == next.flags {
+                prev.end = next.end;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    /// start から end までの VMA のマッピングを外す. VMA の無い部分は何もしない
+    fn unmap_vmas(&mut self, start: usize, end: usize) {
+        let range = self.split_vmas(start, end);
+        let page_table = self.page_table.as_mut().expect("process has no page table");
+        for vma in self.vmas.drain(range) {
+            for vaddr in (vma.start..vma.end).step_by(PAGE_SIZE) {
+                if let Some(paddr) = page_table.unmap(vaddr) {
+                    allocator::PAGE_ALLOC.free_page(paddr);
+                }
+            }
+        }
+    }
+
+    /// page のページがまだマップされていなければ, 含まれる領域に従って内容を作ってマップする
+    ///
+    /// page を含む領域が無い場合や領域が access の権限を許さない場合, 既にマップされている場合,
+    /// 物理ページが足りない場合は false を返す
+    fn populate(&mut self, page: usize, access: PageFlags) -> bool {
+        let Some(region) = self
+            .regions
+            .iter()
+            .chain(&self.vmas)
+            .find(|region| region.start <= page && page < region.end)
+        else {
+            return false;
+        };
+        let Some(page_table) = self.page_table.as_mut() else {
+            return false;
+        };
+        if !region.flags.contains(access) || page_table.translate(page).is_some() {
+            return false;
+        }
+        if free_pages() == 0 {
+            log_warn!("proc", "page fault: out of memory");
+            return false;
+        }
+        // alloc_pages はゼロクリアしたページを返す
+        let frame = allocator::PAGE_ALLOC.alloc_pages::<u8>(1);
+        if let (Backing::Image, Some(image)) = (region.backing, &self.image) {
+            image.read_image(page, frame);
+        }
+        log_debug!(
+            "proc",
+            "populate vaddr={:#x} backing={:?} flag={:?}",
+            page,
+            region.backing,
+            region.flags
+        );
+        let paddr = frame.as_mut_ptr() as usize;
+        if let Err(err) = page_table.map(page, paddr, PageFlags::U | region.flags) {
+            log_warn!("proc", "page fault: {}", err);
+            return false;
+        }
+        mem::flush_tlb();
+        true
+    }
+
     const fn unused() -> Self {
         Self {
             pid: Pid(usize::MAX),
+            ppid: 0,
+            pgid: 0,
+            name: String::new(),
+            args: Vec::new(),
+            env: Vec::new(),
+            exit_code: 0,
             state: ProcState::Unused,
             kernel_stack: KernelStack::null(),
             context: Context::zero(),
-            pt_number: 0,
+            page_table: None,
             entry_point: 0,
+            tp: 0,
+            user_sp: 0,
+            heap_start: 0,
+            brk: 0,
+            files: [const { None }; NOFILE],
+            regions: Vec::new(),
+            vmas: Vec::new(),
+            image: None,
+            wait_channel: 0,
+            pending: 0,
+            blocked: 0,
+            actions: [DEFAULT_ACTION; NSIG],
+            stop_unreported: false,
         }
     }
 }
@@ -115,12 +373,24 @@ impl Process {
 //
 
 use crate::allocator::PAGE_SIZE;
-use crate::mem::{self, PageFlags};
-use crate::utils::align_up;
-use crate::{allocator, csr, loadelf, log_debug, log_info, log_warn, println};
+use crate::file::{NOFILE, OpenFile};
+use crate::loadelf::ElfError;
+use crate::mem::{self, PageFlags, copy_from_user, copy_to_user};
+use crate::trap::TrapFrame;
+use crate::utils::{align_down, align_up, is_aligned};
+use crate::{allocator, console, csr, loadelf, log_debug, log_info, log_warn, println, random};
 use core::arch::asm;
+use core::ops::Range;
 use core::{arch::naked_asm, cell::UnsafeCell};
-use core::slice;
+use syscall::{
+    MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, NSIG, O_RDWR, PROT_EXEC, PROT_READ, PROT_WRITE,
+    SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_SETMASK, SIG_UNBLOCK, SIGCHLD, SIGCONT, SIGKILL, SIGSEGV,
+    SIGSTOP, SIGTSTP, SigAction, USER_STACK_MAX, USER_STACK_SIZE, USER_STACK_TOP, WAIT_STOPPED,
+    WNOHANG, WUNTRACED,
+};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+extern crate alloc;
+use alloc::{collections::BTreeMap, rc::Rc, string::String, vec::Vec};
 
 struct ProcessTableCell<T> {
     inner: UnsafeCell<T>,
@@ -151,9 +421,30 @@ impl<T> ProcessTableCell<T> {
 
 pub const NPROC: usize = 128;
 
+/// ユーザー空間の上限. これより上はカーネルの領域
+const USER_END: usize = 0x8000_0000;
+/// PIE を読み込む最も低い位置. 0 番地付近を使わないようにする
+const PIE_BASE: usize = 0x100_0000;
+/// ASLR で PIE の読み込み位置をずらす最大の幅
+const ASLR_RANGE: usize = 0x1000_0000;
+// ユーザースタックの配置はユーザープログラムと共有する syscall クレートで決める. スタックはユーザー空間の最上部に置く
+const _: () = assert!(USER_STACK_TOP == USER_END);
+/// brk で広げられるヒープの最大の大きさ
+const HEAP_MAX: usize = 64 * 1024 * 1024;
+/// mmap でマップする範囲の下端. HEAP_MAX まで広げたヒープとも重ならない位置にする
+const MMAP_BASE: usize = 0x4000_0000;
+/// プログラムのセグメントを置ける上限. この上に HEAP_MAX までのヒープを置いても mmap の範囲に届かない
+pub const IMAGE_END: usize = MMAP_BASE - HEAP_MAX;
+/// mmap でマップする範囲の上端. 最大の大きさのユーザースタックとガードページの下
+const MMAP_END: usize = USER_STACK_TOP - USER_STACK_MAX - PAGE_SIZE;
+
 struct ProcessTable {
     procs: [Process; NPROC],
     current: usize, // 実行中のプロセスへのインデックス
+    /// 次に割り当てる pid. 0 は idle プロセスが使う
+    next_pid: usize,
+    /// コンソールの前面のプロセスグループ. 0 の場合は無い
+    foreground: usize,
 }
 
 impl ProcessTable {
@@ -161,6 +452,8 @@ impl ProcessTable {
         Self {
             procs: [const { Process::unused() }; NPROC],
             current: 0,
+            next_pid: 1,
+            foreground: 0,
         }
     }
 
@@ -169,7 +462,6 @@ impl ProcessTable {
         &mut self.procs
     }
 
-    #[allow(unused)]
     #[inline]
     fn procs_ref(&self) -> &[Process; NPROC] {
         &self.procs
@@ -177,7 +469,6 @@ impl ProcessTable {
 
     /// # Safety
     /// この呼び出し前に schedule() など, 内部のインデックスを変える操作を行っていないか
-    #[allow(unused)]
     #[inline]
     unsafe fn current_proc_ref(&self) -> &Process {
         &self.procs[self.current]
@@ -235,6 +526,7 @@ fn schedule<'a>() -> &'a Process {
         }
     }
     log_warn!("scheduler", "No runnable process found");
+    ptable.current = 0;
     &procs[0]
 }
 
@@ -246,103 +538,286 @@ fn mark_current_running() {
     ptable.procs[idx].state = ProcState::Running;
 }
 
-fn create_process_from_loaded(loaded: loadelf::LoadedElf) {
+/// 子プロセスの標準入出力 (fd 0, 1, 2) に渡すファイルを決める
+///
+/// stdio が None のときは実行中のプロセスの 0, 1, 2 をそのまま引き継ぐ
+/// カーネルが直接作るプロセス (実行中が idle) はコンソールにつなぐ
+fn child_files(stdio: Option<[usize; 3]>) -> Option<[Option<Rc<OpenFile>>; NOFILE]> {
+    let ptable = unsafe { PTABLE.get() };
+    let mut files = [const { None }; NOFILE];
+    if ptable.current == 0 {
+        let console = OpenFile::open("/dev/console", O_RDWR).expect("/dev/console not found");
+        for file in files.iter_mut().take(3) {
+            *file = Some(console.clone());
+        }
+        return Some(files);
+    }
+
+    let parent = unsafe { ptable.current_proc_ref() };
+    let fds = stdio.unwrap_or([0, 1, 2]);
+    for (file, fd) in files.iter_mut().zip(fds) {
+        *file = Some(parent.files.get(fd)?.clone()?);
+    }
+    Some(files)
+}
+
+fn create_process_from_loaded(
+    name: &str,
+    loaded: loadelf::LoadedElf,
+    args: Vec<u8>,
+    env: Vec<u8>,
+    files: [Option<Rc<OpenFile>>; NOFILE],
+) -> Result<usize, CreateError> {
+    let ptable = unsafe { PTABLE.get_mut() };
+    let ppid = unsafe { ptable.current_proc_ref().pid.as_usize() };
+    let pid = ptable.next_pid;
+    // 親のプロセスグループを引き継ぐ. カーネルが作ったプロセスは自分のグループを作る
+    let pgid = match ppid {
+        0 => pid,
+        _ => unsafe { ptable.current_proc_ref().pgid },
+    };
+
     // プロセステーブルを &mut の参照で取得する
     // この参照のライフタイムは検証されないので, 複数つくらないようにする
-    let procs = unsafe { PTABLE.get_mut().procs_mut() };
+    let procs = ptable.procs_mut();
 
     // プロセステーブルの中で状態が Unused のうち最初に見つけたものを取得する
-    let (idx, proc) = procs
-        .iter_mut()
-        .enumerate()
-        .find(|(_, p)| p.state == ProcState::Unused)
-        .expect("create process failed!");
+    let Some(proc) = procs.iter_mut().find(|p| p.state == ProcState::Unused) else {
+        log_warn!("proc", "process table is full");
+        return Err(CreateError::NoResource);
+    };
 
     // カーネルスタック領域の取得
     let page_count = 1;
+    if !can_alloc_pages(page_count) {
+        log_warn!("proc", "kernel stack: out of memory");
+        return Err(CreateError::NoResource);
+    }
     let kernel_stack_base = allocator::PAGE_ALLOC
         .alloc_pages::<u8>(page_count)
         .as_mut_ptr();
     let kernel_stack_size = allocator::PAGE_SIZE * page_count;
 
     // ページテーブルの作成
-    let page_table_ptr = allocator::PAGE_ALLOC.alloc_pages::<usize>(1).as_mut_ptr();
-    let page_table = unsafe { core::slice::from_raw_parts_mut(page_table_ptr, 512) };
+    let mut page_table = mem::PageTable::new(mem::KernelFrames).ok_or(CreateError::NoResource)?;
 
     // カーネル空間をマッピング
-    map_kernel_pages(page_table);
+    map_kernel_pages(&mut page_table);
 
     // ユーザー空間をマッピング
-    map_user_pages(&loaded, page_table);
-
-    let pt_number = mem::SATP_SV39 | ((page_table_ptr as usize) / allocator::PAGE_SIZE);
-
-    // TODO: インデックスがPidになるのは一時的な実装
-    // → これはProcState::Exitedを導入して被らないようにしている
-    // TODO: spにカーネルのスタックポインタを使用するとプロセス起動時に読めてしまう
-    proc.pid = Pid(idx);
+    let (mut regions, tp) = map_user_pages(&loaded, &mut page_table)?;
+    // ヒープはプログラムと TLS の領域の直後から始める. 最初は空
+    let heap_start = regions.last().map_or(0, |region| region.end);
+    let stack = map_user_stack(loaded.stack.as_ref());
+    let user_sp = stack.end;
+    regions.push(stack);
+
+    proc.pid = Pid(pid);
+    proc.ppid = ppid;
+    proc.pgid = pgid;
+    proc.pending = 0;
+    proc.blocked = 0;
+    proc.actions = [DEFAULT_ACTION; NSIG];
+    proc.stop_unreported = false;
+    proc.name = String::from(name);
+    proc.args = args;
+    proc.env = env;
+    proc.exit_code = 0;
     proc.state = ProcState::Runnable;
     proc.kernel_stack.base = kernel_stack_base;
     proc.kernel_stack.size = kernel_stack_size;
     proc.context.ra = user_entry as usize;
     proc.context.sp = proc.kernel_stack.top() as usize;
-    proc.pt_number = pt_number;
+    proc.page_table = Some(page_table);
     proc.entry_point = loaded.entry_point;
+    proc.tp = tp;
+    proc.user_sp = user_sp;
+    proc.heap_start = heap_start;
+    proc.brk = heap_start;
+    proc.regions = regions;
+    proc.vmas = Vec::new();
+    proc.image = Some(Rc::new(loaded));
+    proc.files = files;
+
+    ptable.next_pid += 1;
+    Ok(pid)
 }
 
 /// カーネル空間のマッピングを行う関数
 /// カーネルの最初からallocatorが確保できる領域の最後までを一対一でマップする
-fn map_kernel_pages(page_table: &mut [usize]) {
+fn map_kernel_pages(page_table: &mut mem::PageTable) {
     let flags = PageFlags::R | PageFlags::W | PageFlags::X;
     let start_paddr = unsafe { &__kernel_base as *const u8 as usize };
     let end_paddr = unsafe { &allocator::__heap_end as *const u8 as usize };
     let mut paddr = start_paddr;
     while paddr < end_paddr {
-        mem::map_page(page_table, paddr, paddr, flags);
+        page_table
+            .map(paddr, paddr, flags)
+            .expect("failed to map kernel pages");
         paddr += allocator::PAGE_SIZE;
     }
 }
 
-/// ユーザーのマッピングを行う関数
-/// allocatorが連続した領域を確保してくれることを前提にする
-fn map_user_pages(loaded: &loadelf::LoadedElf, page_table: &mut [usize]) {
-    for seg in loaded.loadable_segments.iter().flatten() {
-        // 必要なページ数を計算
-        let pages_num = seg.memsz.div_ceil(allocator::PAGE_SIZE);
-
-        // マッピング先の領域を取得
-        let page_ptr = allocator::PAGE_ALLOC
-            .alloc_pages::<u8>(pages_num)
-            .as_mut_ptr();
-        let page: &mut [u8] = unsafe { slice::from_raw_parts_mut(page_ptr, seg.filesz) };
-
-        // ユーザープログラムのデータをコピー
-        log_debug!("proc", "copying user program dst={:p}", page);
-        // セグメントの先頭がアラインされていない場合に同じ途中からの位置からコピーする
-        let seg_start = seg.vaddr % PAGE_SIZE;
-        // WARNING: 同じ長さでないとpanicする
-        page[seg_start..seg_start + seg.filesz].copy_from_slice(seg.data);
-
-        // ユーザーフラグの設定
-        let user_flags = PageFlags::U | seg.flags;
-
-        // ユーザ空間のマッピング
-        let page_start_paddr = page_ptr as usize;
-        let page_start_vaddr = align_up(seg.vaddr, PAGE_SIZE);
+/// ユーザーのプログラムの領域を決める関数
+///
+/// セグメントはページ境界に揃っていなくてもよい. 先頭を含むページから末尾を含むページまでを領域にし,
+/// 隣り合うセグメントが同じページを共有する場合は, そのページのフラグを合わせる
+/// ページはここでは割り当てず, 最初にアクセスしたときに populate() が内容を作ってマップする
+///
+/// PT_TLS がある場合は, プログラムの直後に TLS の領域を割り当てて初期値をコピーする
+/// TLS の領域が IMAGE_END を超える場合や, マップできなかった場合は Err を返す
+///
+/// 領域の一覧と, tp の初期値にする TLS の領域の先頭 (TLS が無い場合は 0) を返す
+fn map_user_pages(
+    loaded: &loadelf::LoadedElf,
+    page_table: &mut mem::PageTable,
+) -> Result<(Vec<MappedRegion>, usize), CreateError> {
+    // 仮想ページの先頭アドレスから, フラグへの対応
+    let mut pages: BTreeMap<usize, PageFlags> = BTreeMap::new();
+    for seg in loaded.loadable_segments.iter() {
+        if seg.memsz == 0 {
+            continue;
+        }
+        let start_vaddr = align_down(seg.vaddr, PAGE_SIZE);
+        let end_vaddr = align_up(seg.vaddr + seg.memsz, PAGE_SIZE);
         log_debug!(
             "proc",
-            "mapping vaddr={:#x} to paddr={:#x}, pages_num={}, flag={:?}",
-            page_start_vaddr,
-            page_start_paddr,
-            pages_num,
+            "segment vaddr={:#x} filesz={:#x} memsz={:#x} pages={:#x}-{:#x} flag={:?}",
+            seg.vaddr,
+            seg.filesz,
+            seg.memsz,
+            start_vaddr,
+            end_vaddr,
             seg.flags
         );
-        for i in 0..pages_num {
-            let paddr = page_start_paddr + i * PAGE_SIZE;
-            let vaddr = page_start_vaddr + i * PAGE_SIZE;
-            mem::map_page(page_table, vaddr, paddr, user_flags);
+        for vaddr in (start_vaddr..end_vaddr).step_by(PAGE_SIZE) {
+            *pages.entry(vaddr).or_insert(PageFlags::empty()) |= seg.flags;
+        }
+    }
+
+    // 連続していてフラグが同じページは1つの領域にまとめる
+    let mut regions: Vec<MappedRegion> = Vec::new();
+    for (&vaddr, &flags) in pages.iter() {
+        match regions.last_mut() {
+            Some(region) if region.end == vaddr && region.flags == flags => {
+                region.end += PAGE_SIZE;
+            }
+            _ => regions.push(MappedRegion {
+                start: vaddr,
+                end: vaddr + PAGE_SIZE,
+                flags,
+                backing: Backing::Image,
+            }),
         }
     }
+
+    // TLS の領域は小さいので最初から割り当てる. 初期値は再配置を適用した後のものを使う
+    let tp = match &loaded.tls {
+        Some(tls) => {
+            let image_end = regions.last().map_or(0, |region| region.end);
+            let start = align_up(image_end, tls.align.max(PAGE_SIZE));
+            let end = align_up(start + tls.memsz, PAGE_SIZE);
+            // ヒープはこの後ろに置くので, mmap やスタックの範囲に入らないようにする
+            if end > IMAGE_END {
+                return Err(CreateError::InvalidElf(ElfError::BadTls));
+            }
+            log_debug!(
+                "proc",
+                "TLS block vaddr={:#x}-{:#x} template={:#x} filesz={:#x} memsz={:#x}",
+                start,
+                end,
+                tls.vaddr,
+                tls.filesz,
+                tls.memsz
+            );
+            let pages_num = (end - start) / PAGE_SIZE;
+            if !can_alloc_pages(pages_num) {
+                log_warn!("proc", "TLS block: out of memory");
+                return Err(CreateError::NoResource);
+            }
+            let block = allocator::PAGE_ALLOC.alloc_pages::<u8>(pages_num);
+            loaded.read_image(tls.vaddr, &mut block[..tls.filesz]);
+            let flags = PageFlags::R | PageFlags::W;
+            for i in 0..pages_num {
+                let paddr = block.as_ptr() as usize + i * PAGE_SIZE;
+                if let Err(err) = page_table.map(start + i * PAGE_SIZE, paddr, PageFlags::U | flags)
+                {
+                    log_warn!("proc", "TLS block: {}", err);
+                    return Err(CreateError::NoResource);
+                }
+            }
+            regions.push(MappedRegion {
+                start,
+                end,
+                flags,
+                backing: Backing::Zero,
+            });
+            start
+        }
+        None => 0,
+    };
+    Ok((regions, tp))
+}
+
+/// ユーザースタックの領域を USER_STACK_TOP から下に決めて返す
+///
+/// 大きさは PT_GNU_STACK の指定を USER_STACK_MAX までで使い, 指定が無ければ USER_STACK_SIZE にする
+/// 溢れたときに他の領域を壊さないように, スタックの下の1ページは領域に含めないでおく
+/// ページは使ったところだけ populate() が割り当てる
+fn map_user_stack(info: Option<&loadelf::StackInfo>) -> MappedRegion {
+    let size = match info.map(|info| info.size) {
+        None | Some(0) => USER_STACK_SIZE,
+        Some(size) => align_up(size.min(USER_STACK_MAX), PAGE_SIZE),
+    };
+    let mut flags = PageFlags::R | PageFlags::W;
+    if info.is_some_and(|info| info.executable) {
+        flags |= PageFlags::X;
+    }
+    let start = USER_STACK_TOP - size;
+    log_debug!(
+        "proc",
+        "user stack vaddr={:#x}-{:#x} guard={:#x} flag={:?}",
+        start,
+        USER_STACK_TOP,
+        start - PAGE_SIZE,
+        flags
+    );
+    MappedRegion {
+        start,
+        end: USER_STACK_TOP,
+        flags,
+        backing: Backing::Zero,
+    }
+}
+
+/// PIE を読み込む位置を本来の位置からずらす幅を選ぶ
+///
+/// PIE_BASE より下に置かれた PIE は PIE_BASE 以上に移す
+/// aslr 機能が有効な場合は, さらに ASLR_RANGE までの範囲でページ単位の乱数だけずらす
+/// プログラムとその後ろに置く TLS の領域が IMAGE_END を超えない分だけずらし,
+/// PIE_BASE に移しただけで超える場合は Err を返す
+fn choose_load_bias(loaded: &loadelf::LoadedElf) -> Result<usize, ElfError> {
+    if !loaded.pie {
+        return Ok(0);
+    }
+    let Some((start, end)) = loaded.image_range() else {
+        return Ok(0);
+    };
+    // TLS の領域は境界に揃えるための隙間も含めて見積もる
+    let tls_size = loaded.tls.as_ref().map_or(0, |tls| {
+        tls.align.max(PAGE_SIZE) + align_up(tls.memsz, PAGE_SIZE)
+    });
+    let bias = PIE_BASE.saturating_sub(align_down(start, PAGE_SIZE));
+    let end = align_up(end, PAGE_SIZE) + tls_size + bias;
+    if end > IMAGE_END {
+        return Err(ElfError::ImageTooLarge);
+    }
+    if !cfg!(feature = "aslr") {
+        return Ok(bias);
+    }
+    let room = (IMAGE_END - end).min(ASLR_RANGE);
+    let pages = room / PAGE_SIZE + 1;
+    Ok(bias + (random::next_u64() as usize % pages) * PAGE_SIZE)
 }
 
 //
@@ -350,11 +825,19 @@ fn map_user_pages(loaded: &loadelf::LoadedElf, page_table: &mut [usize]) {
 //
 
 extern "C" fn user_entry() {
+    let proc = unsafe { PTABLE.get_mut().current_proc_ref() };
+    let (tp, sp) = (proc.tp, proc.user_sp);
+    // sp を変えた後はカーネルのスタックを使わずにユーザーモードに入る
     unsafe {
         asm!(
+            "mv tp, {1}",
+            "mv sp, {2}",
             "csrw sstatus, {0}",
             "sret",
             in(reg) csr::SSTATUS_SPIE,
+            in(reg) tp,
+            in(reg) sp,
+            options(noreturn),
         );
     }
 }
@@ -362,12 +845,12 @@ extern "C" fn user_entry() {
 /// # Safety
 /// この関数内に状態を変更する処理を書かないこと
 fn switch_context(prev: &mut Process, next: &Process) {
-    let pt_number = next.pt_number;
+    let satp = next.satp();
     let kernel_stack_top = next.kernel_stack.top();
     let entry_point = next.entry_point;
     unsafe {
         // ページングの有効化
-        csr::write_csr(csr::Csr::Satp, pt_number);
+        csr::write_csr(csr::Csr::Satp, satp);
         // 割り込み時のカーネルスタックのspの保存
         csr::write_csr(csr::Csr::Sscratch, kernel_stack_top as usize);
         // user_entryでsretしたときに最初に飛ぶアドレス
@@ -439,9 +922,252 @@ extern "C" fn _start_proc(ctx: *const Context) {
 //
 
 /// プロセスを生成する関数
-pub fn create_process(elf_data: &'static [u8]) {
-    let loaded = loadelf::load_elf(elf_data);
-    create_process_from_loaded(loaded);
+///
+/// args は NUL で区切った引数の列で, 空の場合は name を引数とする
+/// env は NUL で区切った "NAME=value" の列
+/// stdio は子プロセスの fd 0, 1, 2 にする実行中のプロセスのファイルディスクリプタ
+///
+/// 生成したプロセスの pid を返す
+pub fn create_process(
+    name: &str,
+    elf_data: &'static [u8],
+    args: &[u8],
+    env: &[u8],
+    stdio: Option<[usize; 3]>,
+) -> Result<usize, CreateError> {
+    let mut loaded = loadelf::load_elf(elf_data).map_err(CreateError::InvalidElf)?;
+    let bias = choose_load_bias(&loaded).map_err(CreateError::InvalidElf)?;
+    log_debug!("proc", "load bias={:#x}", bias);
+    loaded.relocate(bias);
+    let files = child_files(stdio).ok_or(CreateError::NoResource)?;
+    let args = if args.is_empty() {
+        Vec::from(name.as_bytes())
+    } else {
+        Vec::from(args)
+    };
+    create_process_from_loaded(name, loaded, args, Vec::from(env), files)
+}
+
+/// プロセスを生成できなかった理由
+#[derive(Debug)]
+pub enum CreateError {
+    /// 実行ファイルが正しい ELF ファイルでない
+    InvalidElf(ElfError),
+    /// 標準入出力の fd が不正か, プロセステーブルが一杯
+    NoResource,
+}
+
+/// 実行中のプロセスのヒープの終わり (program break) を new_brk にし, 新しい値を返す
+///
+/// new_brk が 0 の場合は変えずに今の値を返す
+/// ヒープの先頭より前や HEAP_MAX を超える位置, 物理ページが足りない場合は Err を返す
+/// 広げたページは使ったときに 0 で埋めて割り当て, 縮めた分のページはマップを外す
+pub fn set_brk(new_brk: usize) -> Result<usize, ()> {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    if new_brk == 0 {
+        return Ok(proc.brk);
+    }
+    if new_brk < proc.heap_start || new_brk - proc.heap_start > HEAP_MAX {
+        return Err(());
+    }
+    let old_end = align_up(proc.brk, PAGE_SIZE);
+    let new_end = align_up(new_brk, PAGE_SIZE);
+    if new_end > old_end {
+        // ページは使ったときに割り当てるが, 明らかに足りない分は断る
+        if free_pages() < (new_end - old_end) / PAGE_SIZE {
+            log_warn!("proc", "brk: out of memory");
+            return Err(());
+        }
+    } else {
+        let page_table = proc.page_table_mut();
+        for vaddr in (new_end..old_end).step_by(PAGE_SIZE) {
+            if let Some(paddr) = page_table.unmap(vaddr) {
+                allocator::PAGE_ALLOC.free_page(paddr);
+            }
+        }
+        mem::flush_tlb();
+    }
+    proc.brk = new_brk;
+    proc.set_heap_region(new_end);
+    Ok(new_brk)
+}
+
+/// 割り当てられる物理ページの数
+pub fn free_pages() -> usize {
+    allocator::PAGE_ALLOC.total_pages() - allocator::PAGE_ALLOC.used_pages()
+}
+
+/// n ページを続けて割り当てられるか
+///
+/// 解放されたページがあると free_pages() だけ残っていても続けて割り当てられるとは限らない
+pub fn can_alloc_pages(n: usize) -> bool {
+    allocator::PAGE_ALLOC.can_alloc(n)
+}
+
+/// mmap, munmap, mprotect が失敗した理由
+#[derive(Debug)]
+pub enum MapError {
+    /// 引数が正しくないか, 対応していないフラグが指定された
+    Invalid,
+    /// 空いている領域か物理ページが足りない. mprotect では範囲にマップしていない部分がある
+    NoMemory,
+}
+
+/// mmap, mprotect の prot をページの権限にする
+fn prot_flags(prot: usize) -> Result<PageFlags, MapError> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(MapError::Invalid);
+    }
+    let mut flags = PageFlags::empty();
+    // 書き込みだけを許すページは作れないので, PROT_WRITE は読み込みも許す
+    if prot & (PROT_READ | PROT_WRITE) != 0 {
+        flags |= PageFlags::R;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags |= PageFlags::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        flags |= PageFlags::X;
+    }
+    Ok(flags)
+}
+
+/// 実行中のプロセスに len バイトの 0 で埋めたメモリを prot の権限でマップし, その先頭を返す
+///
+/// ページは最初にアクセスしたときに割り当てる
+///
+/// flags には MAP_PRIVATE | MAP_ANONYMOUS が必要で, MAP_FIXED を加えると addr にマップする
+/// MAP_FIXED が無い場合の addr は位置のヒントで, 空いていなければ別の位置を選ぶ
+/// どちらの場合も MMAP_BASE から MMAP_END までの範囲にマップする
+pub fn mmap(addr: usize, len: usize, prot: usize, flags: usize) -> Result<usize, MapError> {
+    let page_flags = prot_flags(prot)?;
+    if len == 0
+        || flags & (MAP_PRIVATE | MAP_ANONYMOUS) != MAP_PRIVATE | MAP_ANONYMOUS
+        || flags & !(MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0
+    {
+        return Err(MapError::Invalid);
+    }
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(MapError::NoMemory)?;
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    let start = if flags & MAP_FIXED != 0 {
+        let in_range =
+            addr >= MMAP_BASE && addr.checked_add(len).is_some_and(|end| end <= MMAP_END);
+        if !is_aligned(addr, PAGE_SIZE) || !in_range {
+            return Err(MapError::Invalid);
+        }
+        addr
+    } else {
+        proc.find_free_area(align_down(addr, PAGE_SIZE), len)
+            .ok_or(MapError::NoMemory)?
+    };
+    let end = start + len;
+    // ページは使ったときに割り当てるが, 明らかに足りない分は断る
+    if free_pages() < len / PAGE_SIZE {
+        log_warn!("proc", "mmap: out of memory");
+        return Err(MapError::NoMemory);
+    }
+    if flags & MAP_FIXED != 0 {
+        proc.unmap_vmas(start, end);
+        mem::flush_tlb();
+    }
+    let index = proc.vmas.partition_point(|vma| vma.start < start);
+    proc.vmas.insert(
+        index,
+        MappedRegion {
+            start,
+            end,
+            flags: page_flags,
+            backing: Backing::Zero,
+        },
+    );
+    proc.merge_vmas();
+    log_debug!(
+        "proc",
+        "mmap vaddr={:#x}-{:#x} flag={:?}",
+        start,
+        end,
+        page_flags
+    );
+    Ok(start)
+}
+
+/// 実行中のプロセスの addr から len バイトを含むページのマッピングを外す
+///
+/// mmap でマップしていない部分は無視する
+pub fn munmap(addr: usize, len: usize) -> Result<(), MapError> {
+    if len == 0 || !is_aligned(addr, PAGE_SIZE) {
+        return Err(MapError::Invalid);
+    }
+    let end = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .and_then(|len| addr.checked_add(len))
+        .ok_or(MapError::Invalid)?;
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    proc.unmap_vmas(addr, end);
+    mem::flush_tlb();
+    Ok(())
+}
+
+/// 実行中のプロセスの addr から len バイトを含むページの権限を prot にする
+///
+/// 範囲の全てが mmap でマップした領域でなければならない
+/// 範囲の指定が正しくない場合は Invalid を, マップしていないページを含む場合は NoMemory を返す
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> Result<(), MapError> {
+    let page_flags = prot_flags(prot)?;
+    if !is_aligned(addr, PAGE_SIZE) {
+        return Err(MapError::Invalid);
+    }
+    let end = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .and_then(|len| addr.checked_add(len))
+        .ok_or(MapError::Invalid)?;
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    if !proc.vma_covers(addr, end) {
+        return Err(MapError::NoMemory);
+    }
+    let range = proc.split_vmas(addr, end);
+    let page_table = proc.page_table.as_mut().expect("process has no page table");
+    for vma in proc.vmas[range].iter_mut() {
+        vma.flags = page_flags;
+        for vaddr in (vma.start..vma.end).step_by(PAGE_SIZE) {
+            page_table.protect(vaddr, PageFlags::U | page_flags);
+        }
+    }
+    proc.merge_vmas();
+    mem::flush_tlb();
+    Ok(())
+}
+
+/// 実行中のプロセスの vaddr へのアクセスで起きたページフォルトを処理する
+///
+/// access はアクセスの種類 (R, W, X のどれか) で, まだページを割り当てていない領域への許されたアクセスであれば
+/// ページを割り当ててマップし true を返す. false の場合は呼び出し側で SIGSEGV を送る
+pub fn handle_page_fault(vaddr: usize, access: PageFlags) -> bool {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    proc.populate(align_down(vaddr, PAGE_SIZE), access)
+}
+
+/// カーネルが実行中のプロセスの start から len バイトにアクセスする前に, 範囲を検査してまだ無いページを割り当てる
+///
+/// カーネルの中ではページフォルトを処理しないので, copy_from_user, copy_to_user から呼ぶ
+/// 範囲が access を許す領域に収まっていないか, ページを割り当てられなかった場合は Err を返す
+pub fn populate_user_range(start: usize, len: usize, access: PageFlags) -> Result<(), ()> {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    if !proc.user_accessible(start, len, access) {
+        return Err(());
+    }
+    for page in (align_down(start, PAGE_SIZE)..start + len).step_by(PAGE_SIZE) {
+        let mapped = proc
+            .page_table
+            .as_ref()
+            .is_some_and(|page_table| page_table.translate(page).is_some());
+        if !mapped && !proc.populate(page, access) {
+            return Err(());
+        }
+    }
+    Ok(())
 }
 
 /// 現在のプロセス以外の実行可能プロセスに切り替える
@@ -465,11 +1191,61 @@ pub fn yield_process() {
     switch_context(prev_proc, next_proc);
 }
 
-/// 現在のプロセスを終了する関数
-pub fn end_process() {
+/// 待機チャネルとして使う, プロセステーブルの要素のアドレス
+///
+/// wait_process() で子プロセスの終了を待つ親はこの値で待機する
+fn proc_channel(proc: &Process) -> usize {
+    proc as *const Process as usize
+}
+
+/// 現在のプロセスを終了コード code で終了する関数
+pub fn end_process(code: i32) {
+    // 開いているファイルを閉じる
+    // パイプの相手を起こすことがあるので状態を変える前に行う
+    let files = unsafe {
+        let proc = PTABLE.get_mut().current_proc_mut_ref();
+        core::mem::replace(&mut proc.files, [const { None }; NOFILE])
+    };
+    drop(files);
+
+    // 親に子の終了を知らせる
+    let ppid = unsafe { PTABLE.get().current_proc_ref().ppid };
+    if ppid != 0 {
+        let _ = kill(ppid as isize, SIGCHLD);
+    }
+
+    let ptable = unsafe { PTABLE.get_mut() };
+    let pid = unsafe { ptable.current_proc_ref().pid.as_usize() };
+    for proc in ptable.procs_mut().iter_mut() {
+        if proc.state == ProcState::Unused {
+            continue;
+        }
+        // 子プロセスは親の無いプロセスにする. 終了済みのものは回収する
+        if proc.ppid == pid && proc.pid.as_usize() != pid {
+            proc.ppid = 0;
+            if proc.state == ProcState::Exited {
+                *proc = Process::unused();
+            }
+        }
+    }
+    let ppid = unsafe { ptable.current_proc_ref().ppid };
+    let parent_channel = ptable
+        .procs_ref()
+        .iter()
+        .find(|p| ppid != 0 && p.state != ProcState::Unused && p.pid.as_usize() == ppid)
+        .map(proc_channel);
+
     // スケジュールより先に状態を変える必要がある
-    let prev_proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
-    prev_proc.state = ProcState::Exited;
+    let prev_proc = unsafe { ptable.current_proc_mut_ref() };
+    prev_proc.exit_code = code;
+    // 終了コードを受け取る親がいない場合はすぐに解放する
+    prev_proc.state = match parent_channel {
+        Some(_) => ProcState::Exited,
+        None => ProcState::Unused,
+    };
+    if let Some(chan) = parent_channel {
+        wakeup(chan);
+    }
 
     let next_proc = schedule();
 
@@ -484,40 +1260,131 @@ pub fn end_process() {
     switch_context(prev_proc, next_proc);
 }
 
+/// 現在のプロセスを chan で待機させ, 他のプロセスに切り替える
+///
+/// wakeup(chan) が呼ばれると実行可能に戻る
+/// 戻ってきたときに待っていた条件が満たされているとは限らないので, 呼び出し側で確認し直すこと
+pub fn sleep(chan: usize) {
+    // スケジュールより先に状態を変える必要がある
+    let prev_proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    prev_proc.state = ProcState::Blocked;
+    prev_proc.wait_channel = chan;
+
+    let next_proc = schedule();
+
+    log_debug!(
+        "proc",
+        "switching ... {:?} (blocked) -> {:?}",
+        prev_proc.pid,
+        next_proc.pid
+    );
+
+    mark_current_running();
+    switch_context(prev_proc, next_proc);
+}
+
+/// 子プロセス pid の終了を待ち, その状態を返す
+///
+/// 終了した場合は終了コードを返す. options に WUNTRACED がある場合は停止も報告し,
+/// WAIT_STOPPED に停止させたシグナルの番号を合わせた値を返す
+/// WNOHANG がある場合は待たずに, まだ終了していなければ Some(None) を返す
+///
+/// pid が実行中のプロセスの子でない場合や, 待っている間にシグナルで中断された場合は None
+pub fn wait_process(pid: usize, options: usize) -> Option<Option<i32>> {
+    loop {
+        let ptable = unsafe { PTABLE.get_mut() };
+        let parent = unsafe { ptable.current_proc_ref() };
+        let ppid = parent.pid.as_usize();
+        let chan = proc_channel(parent);
+
+        let child = ptable
+            .procs_mut()
+            .iter_mut()
+            .find(|p| p.state != ProcState::Unused && p.pid.as_usize() == pid && p.ppid == ppid)?;
+        if child.state == ProcState::Exited {
+            let code = child.exit_code;
+            *child = Process::unused();
+            return Some(Some(code));
+        }
+        if options & WUNTRACED != 0 && child.state == ProcState::Stopped && child.stop_unreported {
+            child.stop_unreported = false;
+            return Some(Some(WAIT_STOPPED | child.exit_code));
+        }
+        if options & WNOHANG != 0 {
+            return Some(None);
+        }
+        if interrupted() {
+            return None;
+        }
+        sleep(chan);
+    }
+}
+
+/// 実行中のプロセスの引数を返す
+pub fn current_args() -> Vec<u8> {
+    let proc = unsafe { PTABLE.get().current_proc_ref() };
+    proc.args.clone()
+}
+
+/// 実行中のプロセスの環境変数を返す
+pub fn current_env() -> Vec<u8> {
+    let proc = unsafe { PTABLE.get().current_proc_ref() };
+    proc.env.clone()
+}
+
+/// chan で待機しているプロセスを全て実行可能にする
+pub fn wakeup(chan: usize) {
+    let procs = unsafe { PTABLE.get_mut().procs_mut() };
+    for proc in procs.iter_mut() {
+        if proc.state == ProcState::Blocked && proc.wait_channel == chan {
+            proc.state = ProcState::Runnable;
+        }
+    }
+}
+
 /// idleプロセスを作成する関数
 pub fn create_idle_process() {
     let proc = unsafe { &mut PTABLE.get_mut().procs_mut()[0] };
 
     // カーネルスタック領域の取得
+    // idle プロセスが無いと起動できないので, 足りない場合は panic する
     let page_count = 1;
+    assert!(
+        can_alloc_pages(page_count),
+        "no memory for the idle kernel stack"
+    );
     let kernel_stack_base = allocator::PAGE_ALLOC
         .alloc_pages::<u8>(page_count)
         .as_mut_ptr();
     let kernel_stack_size = allocator::PAGE_SIZE * page_count;
 
     // ページテーブルの作成
-    let page_table_ptr = allocator::PAGE_ALLOC.alloc_pages::<usize>(1).as_mut_ptr();
-    let page_table: &mut [usize] = unsafe { core::slice::from_raw_parts_mut(page_table_ptr, 512) };
-    let pt_number =
-        mem::SATP_SV39 | ((page_table_ptr as *const usize as usize) / allocator::PAGE_SIZE);
+    let mut page_table =
+        mem::PageTable::new(mem::KernelFrames).expect("failed to create idle page table");
 
     // カーネル空間をマッピング
-    map_kernel_pages(page_table);
+    map_kernel_pages(&mut page_table);
 
     proc.pid = Pid(0);
+    proc.name = String::from("idle");
     proc.state = ProcState::Runnable;
     proc.kernel_stack.base = kernel_stack_base;
     proc.kernel_stack.size = kernel_stack_size;
     proc.context.ra = idle_process as usize; // カーネル空間の関数のポインタ
     proc.context.sp = proc.kernel_stack.top() as usize;
-    proc.pt_number = pt_number;
+    proc.page_table = Some(page_table);
 }
 
 /// idleプロセスで実行される関数
+///
+/// カーネルの中ではタイマー割り込みを受けないので, 自分でコンソールの入力を調べる
+/// Ctrl-C などで実行可能になったプロセスがあれば切り替える
 #[allow(unused)]
 fn idle_process() {
     log_debug!("proc", "idling...");
     loop {
+        console::poll_input();
+        yield_to_others();
         core::hint::spin_loop();
     }
 }
@@ -534,12 +1401,12 @@ pub fn start_process() {
 
     mark_current_running();
 
-    let pt_number = next.pt_number;
+    let satp = next.satp();
     let kernel_stack_top = next.kernel_stack.top();
     let entry_point = next.entry_point;
     unsafe {
         // ページングの有効化
-        csr::write_csr(csr::Csr::Satp, pt_number);
+        csr::write_csr(csr::Csr::Satp, satp);
         // 割り込み時のカーネルスタックのspの保存
         csr::write_csr(csr::Csr::Sscratch, kernel_stack_top as usize);
         // user_entryでsretしたときに最初に飛ぶアドレス
@@ -559,3 +1426,486 @@ pub fn show_process_list(verdose: bool) {
         }
     }
 }
+
+/// procfs に渡すプロセスの情報
+pub struct ProcessInfo {
+    pub pid: usize,
+    pub name: String,
+    pub state: ProcState,
+    pub regions: Vec<MappedRegion>,
+}
+
+/// 使用中のプロセスの pid を列挙する
+pub fn pid_list() -> Vec<usize> {
+    let ptable = unsafe { PTABLE.get() };
+    ptable
+        .procs
+        .iter()
+        .filter(|p| p.state != ProcState::Unused)
+        .map(|p| p.pid.as_usize())
+        .collect()
+}
+
+/// pid に対応するプロセスの情報を返す
+pub fn process_info(pid: usize) -> Option<ProcessInfo> {
+    let ptable = unsafe { PTABLE.get() };
+    let proc = ptable
+        .procs
+        .iter()
+        .find(|p| p.state != ProcState::Unused && p.pid.as_usize() == pid)?;
+    Some(ProcessInfo {
+        pid,
+        name: proc.name.clone(),
+        state: proc.state.clone(),
+        regions: {
+            let mut regions: Vec<MappedRegion> =
+                proc.regions.iter().chain(&proc.vmas).cloned().collect();
+            regions.sort_by_key(|region| region.start);
+            regions
+        },
+    })
+}
+
+//
+// ファイルディスクリプタの操作
+//
+
+/// 実行中のプロセスの空いているファイルディスクリプタにファイルを登録する
+///
+/// 登録したファイルディスクリプタの番号を返す
+pub fn install_file(file: Rc<OpenFile>) -> Option<usize> {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    let (fd, slot) = proc
+        .files
+        .iter_mut()
+        .enumerate()
+        .find(|(_, f)| f.is_none())?;
+    *slot = Some(file);
+    Some(fd)
+}
+
+/// 実行中のプロセスのファイルディスクリプタに対応するファイルを返す
+pub fn get_file(fd: usize) -> Option<Rc<OpenFile>> {
+    let proc = unsafe { PTABLE.get().current_proc_ref() };
+    proc.files.get(fd)?.clone()
+}
+
+/// 実行中のプロセスの new_fd を old_fd と同じファイルを指すようにする
+///
+/// new_fd が開いていた場合は閉じる
+pub fn dup_file(old_fd: usize, new_fd: usize) -> Result<(), ()> {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    let file = proc.files.get(old_fd).ok_or(())?.clone().ok_or(())?;
+    let slot = proc.files.get_mut(new_fd).ok_or(())?;
+    let old = slot.replace(file);
+    drop(old);
+    Ok(())
+}
+
+/// 実行中のプロセスのファイルディスクリプタを閉じる
+pub fn close_file(fd: usize) -> Result<(), ()> {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    let file = proc.files.get_mut(fd).ok_or(())?.take().ok_or(())?;
+    // パイプの端であれば相手のプロセスを起こすことがある
+    drop(file);
+    Ok(())
+}
+
+//
+// プロセスグループとシグナル
+//
+// シグナルは届いたときに pending に記録し, ユーザーモードに戻る直前に既定の動作をする
+// 停止は待機中のプロセスにもすぐに行い, 親が wait で気づけるようにする
+//
+
+/// シグナルを受け取ったときの動作
+#[derive(Debug, PartialEq)]
+enum SignalAction {
+    Terminate,
+    Stop,
+    Continue,
+    Ignore,
+    /// ユーザーのハンドラを呼ぶ
+    Handler,
+}
+
+/// 処理方法を変えることも保留することもできないシグナル
+const UNBLOCKABLE: u32 = (1 << SIGKILL) | (1 << SIGSTOP);
+/// 既定の動作で処理する設定
+const DEFAULT_ACTION: SigAction = SigAction {
+    handler: SIG_DFL,
+    mask: 0,
+    flags: 0,
+    restorer: 0,
+};
+/// 既定の動作が停止であるシグナル
+const STOP_SIGNALS: u32 = (1 << SIGSTOP) | (1 << SIGTSTP);
+
+fn default_action(sig: usize) -> SignalAction {
+    match sig {
+        SIGSTOP | SIGTSTP => SignalAction::Stop,
+        SIGCONT => SignalAction::Continue,
+        SIGCHLD => SignalAction::Ignore,
+        _ => SignalAction::Terminate,
+    }
+}
+
+/// proc が sig を受け取ったときの動作
+fn signal_action(proc: &Process, sig: usize) -> SignalAction {
+    if UNBLOCKABLE & (1 << sig) != 0 {
+        return default_action(sig);
+    }
+    match proc.actions[sig].handler {
+        SIG_DFL => default_action(sig),
+        SIG_IGN => SignalAction::Ignore,
+        _ => SignalAction::Handler,
+    }
+}
+
+/// 届いていて保留されていないシグナルのビット集合
+fn deliverable(proc: &Process) -> u32 {
+    proc.pending & !proc.blocked
+}
+
+/// ビット集合に含まれるシグナルの番号を小さい順に返す
+fn signals(set: u32) -> impl Iterator<Item = usize> {
+    (1..NSIG).filter(move |sig| set & (1 << sig) != 0)
+}
+
+/// シグナルで終了したプロセスの終了コード
+fn signal_exit_code(sig: usize) -> i32 {
+    128 + sig as i32
+}
+
+/// pid のプロセスのプロセスグループを pgid にする
+///
+/// pid が 0 の場合は実行中のプロセス, pgid が 0 の場合は pid と同じ値を使う
+/// 対象は実行中のプロセスかその子に限る
+pub fn set_pgid(pid: usize, pgid: usize) -> Result<(), ()> {
+    let ptable = unsafe { PTABLE.get_mut() };
+    let current = unsafe { ptable.current_proc_ref().pid.as_usize() };
+    let pid = if pid == 0 { current } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    let proc = ptable
+        .procs_mut()
+        .iter_mut()
+        .find(|p| p.state != ProcState::Unused && p.pid.as_usize() == pid)
+        .ok_or(())?;
+    if pid != current && proc.ppid != current {
+        return Err(());
+    }
+    proc.pgid = pgid;
+    Ok(())
+}
+
+/// pid のプロセスのプロセスグループを返す. pid が 0 の場合は実行中のプロセス
+pub fn get_pgid(pid: usize) -> Option<usize> {
+    let ptable = unsafe { PTABLE.get() };
+    if pid == 0 {
+        return Some(unsafe { ptable.current_proc_ref().pgid });
+    }
+    ptable
+        .procs_ref()
+        .iter()
+        .find(|p| p.state != ProcState::Unused && p.pid.as_usize() == pid)
+        .map(|p| p.pgid)
+}
+
+/// コンソールの前面のプロセスグループを設定する. 0 の場合は無しにする
+///
+/// 前面のプロセスグループが無い間は Ctrl-C などもそのまま入力として読める
+pub fn set_foreground(pgid: usize) {
+    unsafe { PTABLE.get_mut() }.foreground = pgid;
+}
+
+pub fn foreground() -> usize {
+    unsafe { PTABLE.get() }.foreground
+}
+
+/// シグナル sig を送る. pid が負の場合はプロセスグループ -pid の全てのプロセスに送る
+///
+/// 対象が見つからない場合や sig が範囲外の場合はエラー
+pub fn kill(pid: isize, sig: usize) -> Result<(), ()> {
+    if sig == 0 || sig >= NSIG {
+        return Err(());
+    }
+    let ptable = unsafe { PTABLE.get_mut() };
+    let current = ptable.current;
+    let mut found = false;
+    // 停止したプロセスの親は wait で待っているかもしれないので起こす
+    let mut stopped_parents = Vec::new();
+    for (i, proc) in ptable.procs_mut().iter_mut().enumerate() {
+        if matches!(proc.state, ProcState::Unused | ProcState::Exited) || proc.pid.as_usize() == 0 {
+            continue;
+        }
+        let target = match pid {
+            ..0 => proc.pgid == pid.unsigned_abs(),
+            _ => proc.pid.as_usize() == pid as usize,
+        };
+        if !target {
+            continue;
+        }
+        found = true;
+        if deliver(proc, sig, i == current) {
+            stopped_parents.push(proc.ppid);
+        }
+    }
+    for ppid in stopped_parents {
+        wakeup_parent(ppid);
+    }
+    if found { Ok(()) } else { Err(()) }
+}
+
+/// proc にシグナルを届ける. 実行中でないプロセスを停止させた場合は true を返す
+fn deliver(proc: &mut Process, sig: usize, is_current: bool) -> bool {
+    let bit = 1u32 << sig;
+    // SIGCONT は処理方法に関わらず停止を取り消して再開させる
+    if sig == SIGCONT {
+        proc.pending &= !STOP_SIGNALS;
+        if proc.state == ProcState::Stopped {
+            proc.state = ProcState::Runnable;
+            proc.stop_unreported = false;
+        }
+    }
+    let blocked = proc.blocked & bit != 0;
+    match signal_action(proc, sig) {
+        // 無視するシグナルは保留されていても捨てる
+        SignalAction::Ignore | SignalAction::Continue => false,
+        SignalAction::Stop if is_current || blocked => {
+            proc.pending |= bit;
+            false
+        }
+        SignalAction::Stop => {
+            proc.pending &= !STOP_SIGNALS;
+            proc.state = ProcState::Stopped;
+            proc.stop_unreported = true;
+            proc.exit_code = sig as i32;
+            true
+        }
+        SignalAction::Terminate | SignalAction::Handler => {
+            proc.pending |= bit;
+            // 待機中のプロセスは起こして中断させる. 停止中のものは SIGKILL だけ再開させる
+            if !blocked
+                && (proc.state == ProcState::Blocked
+                    || (proc.state == ProcState::Stopped && sig == SIGKILL))
+            {
+                proc.state = ProcState::Runnable;
+            }
+            false
+        }
+    }
+}
+
+/// ppid のプロセスが wait で待っていれば起こす
+fn wakeup_parent(ppid: usize) {
+    let ptable = unsafe { PTABLE.get() };
+    let chan = ptable
+        .procs_ref()
+        .iter()
+        .find(|p| ppid != 0 && p.state != ProcState::Unused && p.pid.as_usize() == ppid)
+        .map(proc_channel);
+    if let Some(chan) = chan {
+        wakeup(chan);
+    }
+}
+
+/// 実行中のプロセスに, 終了させるかハンドラを呼ぶシグナルが届いているか
+///
+/// 待機を繰り返す処理はこれを確かめ, 真であれば中断してユーザーモードに戻ること
+pub fn interrupted() -> bool {
+    let proc = unsafe { PTABLE.get().current_proc_ref() };
+    signals(deliverable(proc)).any(|sig| {
+        matches!(
+            signal_action(proc, sig),
+            SignalAction::Terminate | SignalAction::Handler
+        )
+    })
+}
+
+/// 実行中のプロセスに届いている停止のシグナルを処理する
+///
+/// 停止した場合は SIGCONT を受け取るまで戻らない
+pub fn handle_stop() {
+    let ptable = unsafe { PTABLE.get_mut() };
+    let proc = unsafe { ptable.current_proc_mut_ref() };
+    let Some(sig) = signals(deliverable(proc) & STOP_SIGNALS)
+        .find(|&sig| signal_action(proc, sig) == SignalAction::Stop)
+    else {
+        return;
+    };
+    proc.pending &= !STOP_SIGNALS;
+    let ppid = proc.ppid;
+    proc.exit_code = sig as i32;
+    proc.stop_unreported = true;
+    wakeup_parent(ppid);
+
+    // スケジュールより先に状態を変える必要がある
+    let prev_proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    prev_proc.state = ProcState::Stopped;
+
+    let next_proc = schedule();
+
+    log_debug!(
+        "proc",
+        "switching ... {:?} (stopped) -> {:?}",
+        prev_proc.pid,
+        next_proc.pid
+    );
+
+    mark_current_running();
+    switch_context(prev_proc, next_proc);
+}
+
+/// ハンドラを呼ぶときにユーザースタックに積む, 割り込まれたときの状態
+#[derive(FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+struct SignalFrame {
+    regs: TrapFrame,
+    /// 再開する命令の位置
+    pc: usize,
+    /// ハンドラを呼ぶ前の保留するシグナルの集合
+    blocked: usize,
+}
+
+/// ユーザースタックのアラインメント
+const STACK_ALIGN: usize = 16;
+
+/// ユーザーモードに戻る前に, 実行中のプロセスに届いているシグナルを処理する
+///
+/// frame は戻るときのレジスタで, pc は再開する命令の位置. 実際に再開する位置を返す
+/// ハンドラを呼ぶ場合はユーザースタックに今の状態を積み, ハンドラから始める
+/// 終了させるシグナルが届いていればプロセスを終了し, 戻らない
+pub fn handle_signals(frame: &mut TrapFrame, pc: usize) -> usize {
+    handle_stop();
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    for sig in signals(deliverable(proc)) {
+        proc.pending &= !(1 << sig);
+        match signal_action(proc, sig) {
+            SignalAction::Terminate => {
+                proc.pending = 0;
+                end_process(signal_exit_code(sig));
+            }
+            SignalAction::Handler => match push_signal_frame(proc, sig, frame, pc) {
+                Ok(handler) => return handler,
+                // スタックが溢れているなどでシグナルフレームを積めない場合は終了する
+                Err(()) => {
+                    proc.pending = 0;
+                    end_process(signal_exit_code(sig));
+                }
+            },
+            // 停止は handle_stop() で処理している
+            SignalAction::Stop | SignalAction::Continue | SignalAction::Ignore => {}
+        }
+    }
+    pc
+}
+
+/// 今の状態をユーザースタックに積み, sig のハンドラを呼ぶようにレジスタを変える
+///
+/// ハンドラの位置を返す. ハンドラから戻ると restorer に戻り, sigreturn で元の状態に戻る
+/// スタックに積めない場合は何も変えずに Err を返す
+fn push_signal_frame(
+    proc: &mut Process,
+    sig: usize,
+    frame: &mut TrapFrame,
+    pc: usize,
+) -> Result<usize, ()> {
+    let action = proc.actions[sig];
+    let saved = SignalFrame {
+        regs: frame.clone(),
+        pc,
+        blocked: proc.blocked as usize,
+    };
+    let sp = signal_frame_sp(frame.sp);
+    copy_to_user(sp as *mut u8, saved.as_bytes())?;
+
+    // ハンドラの実行中は同じシグナルを保留する
+    proc.blocked |= (action.mask | (1 << sig)) & !UNBLOCKABLE;
+    frame.sp = sp;
+    frame.a0 = sig as isize;
+    frame.ra = action.restorer;
+    Ok(action.handler)
+}
+
+/// sp のスタックにシグナルフレームを積むときの位置
+fn signal_frame_sp(sp: usize) -> usize {
+    sp.wrapping_sub(size_of::<SignalFrame>()) & !(STACK_ALIGN - 1)
+}
+
+/// ハンドラから戻ったときに呼ばれ, push_signal_frame() で積んだ状態に戻す
+///
+/// 再開する命令の位置を返す. sp がユーザーの読める領域のシグナルフレームを指していない場合は
+/// 状態を戻さずに SIGSEGV を届け, None を返す
+pub fn signal_return(frame: &mut TrapFrame) -> Option<usize> {
+    let mut saved = SignalFrame::new_zeroed();
+    if copy_from_user(saved.as_bytes_mut(), frame.sp as *const u8).is_err() {
+        fault(SIGSEGV);
+        return None;
+    }
+    *frame = saved.regs;
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    proc.blocked = saved.blocked as u32 & !UNBLOCKABLE;
+    Some(saved.pc)
+}
+
+/// 実行中のプロセスの sig の処理方法を new にし, 前の処理方法を返す
+///
+/// new が None の場合は変えない. SIGKILL と SIGSTOP は変えられない
+pub fn sigaction(sig: usize, new: Option<SigAction>) -> Result<SigAction, ()> {
+    if sig == 0 || sig >= NSIG || (new.is_some() && UNBLOCKABLE & (1 << sig) != 0) {
+        return Err(());
+    }
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    let old = proc.actions[sig];
+    if let Some(new) = new {
+        proc.actions[sig] = new;
+        // 無視するようにしたシグナルは届いていても捨てる
+        if signal_action(proc, sig) == SignalAction::Ignore {
+            proc.pending &= !(1 << sig);
+        }
+    }
+    Ok(old)
+}
+
+/// 実行中のプロセスの保留するシグナルの集合を how に従って set で変え, 前の集合を返す
+pub fn sigprocmask(how: usize, set: u32) -> Result<u32, ()> {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    let old = proc.blocked;
+    let new = match how {
+        SIG_BLOCK => old | set,
+        SIG_UNBLOCK => old & !set,
+        SIG_SETMASK => set,
+        _ => return Err(()),
+    };
+    proc.blocked = new & !UNBLOCKABLE;
+    Ok(old)
+}
+
+/// ユーザーモードで起きた例外を sig として実行中のプロセスに届ける
+///
+/// ハンドラで処理できない場合は保留や無視の設定に関わらず終了させる
+pub fn fault(sig: usize) {
+    let proc = unsafe { PTABLE.get_mut().current_proc_mut_ref() };
+    let bit = 1u32 << sig;
+    if signal_action(proc, sig) != SignalAction::Handler || proc.blocked & bit != 0 {
+        end_process(signal_exit_code(sig));
+        return;
+    }
+    proc.pending |= bit;
+}
+
+/// 実行中のプロセスの他に実行可能なプロセスがあれば切り替える
+///
+/// 入力を待ちながら他のプロセスを進めるために使う
+pub fn yield_to_others() {
+    let ptable = unsafe { PTABLE.get() };
+    let current = ptable.current;
+    let others = ptable
+        .procs_ref()
+        .iter()
+        .enumerate()
+        .any(|(i, p)| i != current && p.state == ProcState::Runnable && p.pid.as_usize() > 0);
+    if others {
+        yield_process();
+    }
+}
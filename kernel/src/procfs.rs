@@ -0,0 +1,215 @@
+use core::fmt::Write;
+extern crate alloc;
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+
+use crate::{
+    allocator::{self, PAGE_SIZE},
+    mem::PageFlags,
+    proc, timer,
+    vfs::{Fs, Node},
+};
+use syscall::{FILE_TYPE_DIRECTORY, FILE_TYPE_REGULAR, Metadata};
+
+//
+// プロセスとカーネルの状態をファイルとして公開するファイルシステム
+//
+// ファイルの内容は lookup で開くときに一度だけ生成し, そのノードを閉じるまで同じ内容を返す
+// カーネルのヒープは解放できないので, size() や read_at() のたびに作り直さないようにする
+//
+
+pub struct ProcFs;
+
+impl Fs for ProcFs {
+    type NodeType = ProcNode;
+    fn lookup(&self, name: &str) -> Option<Self::NodeType> {
+        let name = name.trim_end_matches('/');
+        match name {
+            "" => Some(ProcKind::Root),
+            "meminfo" => Some(ProcKind::MemInfo),
+            "uptime" => Some(ProcKind::Uptime),
+            _ => {
+                let (pid, file) = match name.split_once('/') {
+                    Some((pid, file)) => (pid, Some(file)),
+                    None => (name, None),
+                };
+                let pid = pid.parse().ok()?;
+                proc::process_info(pid)?;
+                match file {
+                    None => Some(ProcKind::PidDir(pid)),
+                    Some("status") => Some(ProcKind::Status(pid)),
+                    Some("maps") => Some(ProcKind::Maps(pid)),
+                    Some(_) => None,
+                }
+            }
+        }
+        .map(ProcNode::new)
+    }
+}
+
+/// /proc の中のファイルやディレクトリの種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProcKind {
+    Root,
+    MemInfo,
+    Uptime,
+    PidDir(usize),
+    Status(usize),
+    Maps(usize),
+}
+
+/// /proc 直下に常に存在するファイル
+const ROOT_FILES: [&str; 2] = ["meminfo", "uptime"];
+/// /proc/<pid> 以下のファイル
+const PID_FILES: [&str; 2] = ["status", "maps"];
+
+impl ProcKind {
+    /// ファイルの内容を生成する
+    ///
+    /// ディレクトリや, 既に存在しないプロセスの場合は None
+    fn content(&self) -> Option<String> {
+        match *self {
+            ProcKind::Root | ProcKind::PidDir(_) => None,
+            ProcKind::MemInfo => Some(meminfo()),
+            ProcKind::Uptime => Some(uptime()),
+            ProcKind::Status(pid) => status(pid),
+            ProcKind::Maps(pid) => maps(pid),
+        }
+    }
+}
+
+/// 開いた /proc のファイル. 内容は開いたときのもの
+pub struct ProcNode {
+    kind: ProcKind,
+    content: Option<String>,
+}
+
+impl ProcNode {
+    fn new(kind: ProcKind) -> Self {
+        Self {
+            kind,
+            content: kind.content(),
+        }
+    }
+}
+
+impl Node for ProcNode {
+    fn get_id(&self) -> usize {
+        // pid ごとに 4 つずつ番号を割り当てる
+        match self.kind {
+            ProcKind::Root => 0,
+            ProcKind::MemInfo => 1,
+            ProcKind::Uptime => 2,
+            ProcKind::PidDir(pid) => (pid + 1) << 2,
+            ProcKind::Status(pid) => ((pid + 1) << 2) + 1,
+            ProcKind::Maps(pid) => ((pid + 1) << 2) + 2,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.content.as_ref().map_or(0, |c| c.len())
+    }
+
+    fn metadata(&self) -> Metadata {
+        let (file_type, mode) = match self.kind {
+            ProcKind::Root | ProcKind::PidDir(_) => (FILE_TYPE_DIRECTORY, 0o555),
+            _ => (FILE_TYPE_REGULAR, 0o444),
+        };
+        // 内容は開くたびに作られるので, 時刻は全て現在時刻とする
+        let now = timer::read_time_seconds();
+        Metadata {
+            file_type,
+            mode,
+            id: self.get_id() as u64,
+            size: self.size() as u64,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<(), ()> {
+        let content = self.content.as_ref().ok_or(())?;
+        if buf.len() < content.len() {
+            return Err(());
+        }
+        buf[..content.len()].copy_from_slice(content.as_bytes());
+        Ok(())
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        let content = self.content.as_ref().ok_or(())?;
+        let bytes = content.as_bytes();
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        let n = buf.len().min(bytes.len() - offset);
+        buf[..n].copy_from_slice(&bytes[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn truncate(&self) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn read_dir(&self, index: usize) -> Option<String> {
+        match self.kind {
+            ProcKind::Root => {
+                if let Some(name) = ROOT_FILES.get(index) {
+                    return Some(name.to_string());
+                }
+                let pids = proc::pid_list();
+                let pid = pids.get(index - ROOT_FILES.len())?;
+                Some(pid.to_string())
+            }
+            ProcKind::PidDir(_) => PID_FILES.get(index).map(|name| name.to_string()),
+            _ => None,
+        }
+    }
+}
+
+fn meminfo() -> String {
+    let total = allocator::PAGE_ALLOC.total_pages() * PAGE_SIZE / 1024;
+    let used = allocator::PAGE_ALLOC.used_pages() * PAGE_SIZE / 1024;
+    format!(
+        "MemTotal:\t{} kB\nMemUsed:\t{} kB\nMemFree:\t{} kB\n",
+        total,
+        used,
+        total - used
+    )
+}
+
+fn uptime() -> String {
+    let (secs, frac) = timer::read_time_parts();
+    // 小数点以下は2桁で表示する
+    let centis = frac * 100 / timer::TIMEBASE_HZ;
+    format!("{}.{:02}\n", secs, centis)
+}
+
+fn status(pid: usize) -> Option<String> {
+    let info = proc::process_info(pid)?;
+    Some(format!(
+        "Name:\t{}\nPid:\t{}\nState:\t{:?}\n",
+        info.name, info.pid, info.state
+    ))
+}
+
+fn maps(pid: usize) -> Option<String> {
+    let info = proc::process_info(pid)?;
+    let mut out = String::new();
+    for region in info.regions.iter() {
+        let flag = |f: PageFlags, c: char| if region.flags.contains(f) { c } else { '-' };
+        let _ = writeln!(
+            out,
+            "{:08x}-{:08x} {}{}{}p",
+            region.start,
+            region.end,
+            flag(PageFlags::R, 'r'),
+            flag(PageFlags::W, 'w'),
+            flag(PageFlags::X, 'x'),
+        );
+    }
+    Some(out)
+}
@@ -0,0 +1,33 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::timer;
+
+// xorshift64* による疑似乱数生成器
+// 暗号論的に安全ではないので注意
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// 初回呼び出し時にタイマの値から種を作る
+fn seed() -> u64 {
+    // 0 だと xorshift が 0 のまま進まないので必ず1ビット立てる
+    (timer::read_time() ^ 0x9e37_79b9_7f4a_7c15) | 1
+}
+
+pub fn next_u64() -> u64 {
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = seed();
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    STATE.store(x, Ordering::Relaxed);
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+pub fn fill_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(size_of::<u64>()) {
+        let bytes = next_u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
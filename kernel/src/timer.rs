@@ -1,5 +1,7 @@
 use core::arch::asm;
 
+use crate::csr::{self, Csr, SIE_STIE};
+
 // QEMU (virt) timebase is typically 10MHz on RISC-V.
 // Update this if your platform reports a different timebase.
 pub const TIMEBASE_HZ: u64 = 10_000_000;
@@ -28,7 +30,6 @@ pub fn read_time() -> u64 {
     value
 }
 
-#[allow(unused)]
 #[inline(always)]
 pub fn read_time_seconds() -> u64 {
     read_time() / TIMEBASE_HZ
@@ -39,3 +40,49 @@ pub fn read_time_parts() -> (u64, u64) {
     let ticks = read_time();
     (ticks / TIMEBASE_HZ, ticks % TIMEBASE_HZ)
 }
+
+//
+// タイマー割り込み
+//
+// ユーザーモードで動いている間だけ一定の間隔で割り込みを受け, コンソールの入力を調べる
+// カーネルの中では sstatus.SIE を立てないので割り込まれない
+//
+
+/// 1秒あたりのタイマー割り込みの回数
+pub const TICK_HZ: u64 = 100;
+
+/// SBI の Timer 拡張
+const SBI_EXT_TIME: usize = 0x5449_4D45;
+const SBI_TIME_SET_TIMER: usize = 0;
+
+/// time が stime_value になったときにタイマー割り込みが起きるようにする
+fn set_timer(stime_value: u64) {
+    unsafe {
+        asm!(
+            "ecall",
+            inout("a0") stime_value as usize => _,
+            lateout("a1") _,
+            in("a6") SBI_TIME_SET_TIMER,
+            in("a7") SBI_EXT_TIME,
+        );
+    }
+}
+
+/// 次のタイマー割り込みを予約する
+fn schedule_next_tick() {
+    set_timer(read_time() + TIMEBASE_HZ / TICK_HZ);
+}
+
+/// タイマー割り込みを有効にし, 最初の割り込みを予約する
+pub fn init() {
+    unsafe {
+        csr::write_csr(Csr::Sie, csr::read_csr(Csr::Sie) | SIE_STIE);
+    }
+    schedule_next_tick();
+}
+
+/// タイマー割り込みの処理. 次の割り込みを予約し, コンソールの入力を調べる
+pub fn handle_tick() {
+    schedule_next_tick();
+    crate::console::poll_input();
+}
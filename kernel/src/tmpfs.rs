@@ -0,0 +1,183 @@
+use core::cell::{Cell, RefCell, UnsafeCell};
+extern crate alloc;
+use alloc::{
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    timer,
+    vfs::{Fs, Node},
+};
+use syscall::{FILE_TYPE_DIRECTORY, FILE_TYPE_REGULAR, Metadata};
+
+//
+// メモリ上に読み書きできるファイルを置くファイルシステム
+//
+// ディレクトリは持たず, /tmp 直下にファイルを並べる
+//
+
+pub struct TmpFile {
+    id: usize,
+    name: String,
+    data: RefCell<Vec<u8>>,
+    ctime: u64,
+    mtime: Cell<u64>,
+}
+
+struct FileList {
+    inner: UnsafeCell<Vec<Rc<TmpFile>>>,
+}
+
+unsafe impl Sync for FileList {}
+
+impl FileList {
+    /// # Safety
+    /// この参照のライフタイムは検証されない
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get_mut(&self) -> &mut Vec<Rc<TmpFile>> {
+        unsafe { &mut *self.inner.get() }
+    }
+}
+
+static FILES: FileList = FileList {
+    inner: UnsafeCell::new(Vec::new()),
+};
+
+pub struct TmpFs;
+
+impl Fs for TmpFs {
+    type NodeType = TmpNode;
+    fn lookup(&self, name: &str) -> Option<Self::NodeType> {
+        if name.is_empty() {
+            return Some(TmpNode::Root);
+        }
+        let files = unsafe { FILES.get_mut() };
+        let file = files.iter().find(|f| f.name == name)?;
+        Some(TmpNode::File(file.clone()))
+    }
+}
+
+impl TmpFs {
+    /// ファイルを作成する. 既に存在する場合はそれを返す
+    pub fn create(&self, name: &str) -> Option<TmpNode> {
+        if name.is_empty() || name.contains('/') {
+            return None;
+        }
+        if let Some(node) = self.lookup(name) {
+            return Some(node);
+        }
+
+        let files = unsafe { FILES.get_mut() };
+        // 0 はルートディレクトリに使う
+        let id = files.len() + 1;
+        let now = timer::read_time_seconds();
+        let file = Rc::new(TmpFile {
+            id,
+            name: name.to_string(),
+            data: RefCell::new(Vec::new()),
+            ctime: now,
+            mtime: Cell::new(now),
+        });
+        files.push(file.clone());
+        Some(TmpNode::File(file))
+    }
+}
+
+pub enum TmpNode {
+    Root,
+    File(Rc<TmpFile>),
+}
+
+impl Node for TmpNode {
+    fn get_id(&self) -> usize {
+        match self {
+            TmpNode::Root => 0,
+            TmpNode::File(file) => file.id,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            TmpNode::Root => 0,
+            TmpNode::File(file) => file.data.borrow().len(),
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<(), ()> {
+        let TmpNode::File(file) = self else {
+            return Err(());
+        };
+        let data = file.data.borrow();
+        if buf.len() < data.len() {
+            return Err(());
+        }
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        match self {
+            TmpNode::Root => Metadata {
+                file_type: FILE_TYPE_DIRECTORY,
+                mode: 0o777,
+                ..Default::default()
+            },
+            TmpNode::File(file) => Metadata {
+                file_type: FILE_TYPE_REGULAR,
+                mode: 0o644,
+                id: file.id as u64,
+                size: self.size() as u64,
+                atime: file.mtime.get(),
+                mtime: file.mtime.get(),
+                ctime: file.ctime,
+            },
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        let TmpNode::File(file) = self else {
+            return Err(());
+        };
+        let data = file.data.borrow();
+        if offset >= data.len() {
+            return Ok(0);
+        }
+        let n = buf.len().min(data.len() - offset);
+        buf[..n].copy_from_slice(&data[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, ()> {
+        let TmpNode::File(file) = self else {
+            return Err(());
+        };
+        let mut data = file.data.borrow_mut();
+        let end = offset + buf.len();
+        if data.len() < end {
+            // 終端より後ろに書き込んだ場合, 間は 0 で埋める
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(buf);
+        file.mtime.set(timer::read_time_seconds());
+        Ok(buf.len())
+    }
+
+    fn truncate(&self) -> Result<(), ()> {
+        let TmpNode::File(file) = self else {
+            return Err(());
+        };
+        file.data.borrow_mut().clear();
+        file.mtime.set(timer::read_time_seconds());
+        Ok(())
+    }
+
+    fn read_dir(&self, index: usize) -> Option<String> {
+        let TmpNode::Root = self else {
+            return None;
+        };
+        let files = unsafe { FILES.get_mut() };
+        files.get(index).map(|f| f.name.clone())
+    }
+}
@@ -1,6 +1,51 @@
 use core::arch::naked_asm;
 
-use crate::csr::{self, Csr, read_csr};
+use syscall::{SIGBUS, SIGILL, SIGSEGV, SIGTRAP};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::{
+    csr::{self, Csr, read_csr},
+    log_warn,
+    mem::PageFlags,
+};
+
+/// kernel_entry がカーネルスタックに保存するユーザーのレジスタ
+#[allow(unused)]
+#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub ra: usize,
+    pub gp: usize,
+    pub tp: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub a0: isize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+    pub s9: usize,
+    pub s10: usize,
+    pub s11: usize,
+    pub sp: usize,
+}
 
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
@@ -82,27 +127,122 @@ pub fn kernel_entry() {
     )
 }
 
+/// ユーザーモードでの例外. それぞれシグナルにしてプロセスに届ける
+/// カーネルの中で起きた場合は handle_trap が panic する
+const SCAUSE_INSTRUCTION_MISALIGNED: usize = 0;
+const SCAUSE_INSTRUCTION_ACCESS_FAULT: usize = 1;
+const SCAUSE_ILLEGAL_INSTRUCTION: usize = 2;
+const SCAUSE_BREAKPOINT: usize = 3;
+const SCAUSE_LOAD_MISALIGNED: usize = 4;
+const SCAUSE_LOAD_ACCESS_FAULT: usize = 5;
+const SCAUSE_STORE_MISALIGNED: usize = 6;
+const SCAUSE_STORE_ACCESS_FAULT: usize = 7;
 const SCAUSE_ECALL: usize = 8;
+const SCAUSE_INSTRUCTION_PAGE_FAULT: usize = 12;
+const SCAUSE_LOAD_PAGE_FAULT: usize = 13;
+const SCAUSE_STORE_PAGE_FAULT: usize = 15;
+/// scause の最上位ビットが立っている場合は割り込み
+const SCAUSE_INTERRUPT: usize = 1 << (usize::BITS - 1);
+const SCAUSE_TIMER_INTERRUPT: usize = SCAUSE_INTERRUPT | 5;
 const ECALL_SIZE: usize = 4;
 
+/// ページフォルトの原因になったアクセスの種類
+fn fault_access(scause: usize) -> PageFlags {
+    match scause {
+        SCAUSE_INSTRUCTION_PAGE_FAULT => PageFlags::X,
+        SCAUSE_STORE_PAGE_FAULT => PageFlags::W,
+        _ => PageFlags::R,
+    }
+}
+
 #[allow(unused)]
 #[unsafe(no_mangle)]
 pub extern "C" fn handle_trap(trap_frame: *mut u8) {
     let scause = read_csr(Csr::Scause);
     let stval = read_csr(Csr::Stval);
     let user_pc = read_csr(Csr::Sepc);
-
-    if scause == SCAUSE_ECALL {
-        crate::ksyscall::handle_syscall(trap_frame);
-    } else {
+    // カーネルの中では割り込みを許可していないので, ここに来るのはカーネルのバグだけ
+    // ユーザーのメモリへのアクセスは copy_from_user / copy_to_user が先に検査してページを用意している
+    if read_csr(Csr::Sstatus) & csr::SSTATUS_SPP != 0 {
         panic!(
-            "[TRAP] unexpected trap: scause={:x}, stval={:x}, sepc={:x}",
+            "[TRAP] trap in kernel: scause={:x}, stval={:x}, sepc={:x}",
             scause, stval, user_pc
         );
     }
+    let trap_frame_slice =
+        unsafe { core::slice::from_raw_parts_mut(trap_frame, size_of::<TrapFrame>()) };
+    let frame = TrapFrame::mut_from_prefix(trap_frame_slice).unwrap();
+
+    // 再開する命令の位置
+    let resume_pc = match scause {
+        SCAUSE_ECALL => match crate::ksyscall::handle_syscall(frame) {
+            // sigreturn は戻る位置を自分で決める
+            Some(pc) => pc,
+            // ecall命令の大きさを足して次の命令から再開する
+            None => user_pc + ECALL_SIZE,
+        },
+        // 割り込まれた命令からやり直す
+        SCAUSE_TIMER_INTERRUPT => {
+            crate::timer::handle_tick();
+            user_pc
+        }
+        // まだ割り当てていないページへのアクセスであれば, 割り当ててから同じ命令をやり直す
+        SCAUSE_INSTRUCTION_PAGE_FAULT | SCAUSE_LOAD_PAGE_FAULT | SCAUSE_STORE_PAGE_FAULT
+            if crate::proc::handle_page_fault(stval, fault_access(scause)) =>
+        {
+            user_pc
+        }
+        // ハンドラから戻った場合は同じ命令をやり直す
+        SCAUSE_INSTRUCTION_ACCESS_FAULT
+        | SCAUSE_LOAD_ACCESS_FAULT
+        | SCAUSE_STORE_ACCESS_FAULT
+        | SCAUSE_INSTRUCTION_PAGE_FAULT
+        | SCAUSE_LOAD_PAGE_FAULT
+        | SCAUSE_STORE_PAGE_FAULT => {
+            log_warn!(
+                "trap",
+                "segmentation fault: scause={:x}, stval={:x}, sepc={:x}",
+                scause,
+                stval,
+                user_pc
+            );
+            crate::proc::fault(SIGSEGV);
+            user_pc
+        }
+        // 割り込みは許可したものしか来ない
+        _ if scause & SCAUSE_INTERRUPT != 0 => panic!(
+            "[TRAP] unexpected interrupt: scause={:x}, stval={:x}, sepc={:x}",
+            scause, stval, user_pc
+        ),
+        // それ以外のユーザーモードの例外もプロセスだけを止める
+        _ => {
+            let sig = match scause {
+                SCAUSE_BREAKPOINT => SIGTRAP,
+                SCAUSE_INSTRUCTION_MISALIGNED
+                | SCAUSE_LOAD_MISALIGNED
+                | SCAUSE_STORE_MISALIGNED => SIGBUS,
+                SCAUSE_ILLEGAL_INSTRUCTION => SIGILL,
+                // 他の扱っていない例外も不正な命令として扱う
+                _ => SIGILL,
+            };
+            log_warn!(
+                "trap",
+                "user exception: scause={:x}, stval={:x}, sepc={:x}, signal={}",
+                scause,
+                stval,
+                user_pc,
+                sig
+            );
+            crate::proc::fault(sig);
+            user_pc
+        }
+    };
+
+    // ユーザーモードに戻る前に届いているシグナルを処理する
+    let resume_pc = crate::proc::handle_signals(frame, resume_pc);
 
     unsafe {
-        // ecall命令の大きさを足して次の命令から再開する
-        csr::write_csr(Csr::Sepc, user_pc + ECALL_SIZE);
+        // 途中で他のプロセスに切り替わると sepc は書き換えられているので最後に設定する
+        csr::write_csr(Csr::Sepc, resume_pc);
     }
 }
@@ -21,6 +21,10 @@ pub fn is_aligned(addr: usize, align: usize) -> bool {
     addr.is_multiple_of(align)
 }
 
+pub fn align_down(addr: usize, align: usize) -> usize {
+    addr - (addr % align)
+}
+
 #[allow(unused)]
 pub fn align_up(addr: usize, align: usize) -> usize {
     if is_aligned(addr, align) {
@@ -1,6 +1,15 @@
 use core::fmt::Debug;
+extern crate alloc;
+use alloc::{
+    rc::Rc,
+    string::{String, ToString},
+};
 
-use crate::{PS_ELF, SH_ELF};
+use crate::devfs::DevFs;
+use crate::procfs::ProcFs;
+use crate::tmpfs::TmpFs;
+use crate::{CAT_ELF, GREP_ELF, PRINTENV_ELF, PS_ELF, SH_ELF};
+use syscall::{FILE_TYPE_DIRECTORY, FILE_TYPE_REGULAR, Metadata};
 
 pub trait Fs {
     type NodeType: Node;
@@ -11,47 +20,231 @@ pub trait Node {
     fn get_id(&self) -> usize;
     fn size(&self) -> usize;
     fn read(&self, buf: &mut [u8]) -> Result<(), ()>;
+
+    /// stat で返すファイルの情報
+    fn metadata(&self) -> Metadata;
+
+    /// offset から読み取り, 読み取ったバイト数を返す
+    ///
+    /// 0 を返した場合は終端に達している
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ()>;
+
+    /// offset から書き込み, 書き込んだバイト数を返す
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, ()> {
+        // 書き込みに対応していないノードはエラーにする
+        Err(())
+    }
+
+    /// 大きさを 0 にする
+    fn truncate(&self) -> Result<(), ()> {
+        // 大きさを持たないデバイスなどでは何もしない
+        Ok(())
+    }
+
+    /// ディレクトリの index 番目のエントリの名前を返す
+    ///
+    /// ディレクトリでない場合や index が範囲外の場合は None
+    fn read_dir(&self, _index: usize) -> Option<String> {
+        None
+    }
+}
+
+/// パスを解決してノードを返す
+///
+/// `/dev` 以下は DevFs, `/proc` 以下は ProcFs, `/tmp` 以下は TmpFs から探す
+///
+/// プログラムは `/bin` 以下に置く. 互換性のためルート直下の名前も MemoryFs から探す
+pub fn lookup(path: &str) -> Option<Rc<dyn Node>> {
+    if path == "/" {
+        return Some(Rc::new(RootDir));
+    }
+    if let Some(name) = strip_mount(path, "/dev") {
+        return lookup_in(&DevFs, name);
+    }
+    if let Some(name) = strip_mount(path, "/proc") {
+        return lookup_in(&ProcFs, name);
+    }
+    if let Some(name) = strip_mount(path, "/tmp") {
+        return lookup_in(&TmpFs, name);
+    }
+    if let Some(name) = strip_mount(path, "/bin") {
+        return lookup_in(&MemoryFs, name);
+    }
+    lookup_in(&MemoryFs, path.trim_start_matches('/'))
+}
+
+/// ファイルを作成する
+///
+/// 書き込めるファイルシステムは TmpFs のみ
+pub fn create(path: &str) -> Option<Rc<dyn Node>> {
+    let name = strip_mount(path, "/tmp")?;
+    let node = TmpFs.create(name)?;
+    Some(Rc::new(node))
+}
+
+/// ルートディレクトリに見せるマウントポイントの名前
+const MOUNTS: [&str; 4] = ["bin", "dev", "proc", "tmp"];
+
+/// マウントポイントを並べるだけのルートディレクトリ
+struct RootDir;
+
+impl Node for RootDir {
+    fn get_id(&self) -> usize {
+        0
+    }
+
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            file_type: FILE_TYPE_DIRECTORY,
+            mode: 0o555,
+            ..Default::default()
+        }
+    }
+
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, ()> {
+        Err(())
+    }
+
+    fn read_dir(&self, index: usize) -> Option<String> {
+        MOUNTS.get(index).map(|name| name.to_string())
+    }
+}
+
+/// path がマウントポイント以下であれば, マウントポイントからの相対パスを返す
+fn strip_mount<'a>(path: &'a str, mount: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(mount)?;
+    if rest.is_empty() {
+        Some(rest)
+    } else {
+        rest.strip_prefix('/')
+    }
+}
+
+fn lookup_in<F>(fs: &F, name: &str) -> Option<Rc<dyn Node>>
+where
+    F: Fs,
+    F::NodeType: 'static,
+{
+    let node = fs.lookup(name)?;
+    Some(Rc::new(node))
 }
 
 pub struct MemoryFs;
 
+/// MemoryFs に置くプログラムの名前. 添字をノードの id に使う
+const PROGRAMS: [&str; 5] = ["sh", "ps", "cat", "grep", "printenv"];
+
+/// id のプログラムの中身を返す
+fn program(id: usize) -> Option<&'static [u8]> {
+    match id {
+        0 => Some(SH_ELF),
+        1 => Some(PS_ELF),
+        2 => Some(CAT_ELF),
+        3 => Some(GREP_ELF),
+        4 => Some(PRINTENV_ELF),
+        _ => None,
+    }
+}
+
 impl Fs for MemoryFs {
     type NodeType = MemoryNode;
     fn lookup(&self, name: &str) -> Option<Self::NodeType> {
-        match name {
-            "sh" => Some(MemoryNode::new(0, SH_ELF)),
-            "ps" => Some(MemoryNode::new(1, PS_ELF)),
-            _ => None,
+        if name.is_empty() {
+            return Some(MemoryNode::Root);
         }
+        let id = PROGRAMS.iter().position(|&p| p == name)?;
+        Some(MemoryNode::new(id, program(id)?))
     }
 }
 
 #[derive(Debug)]
-pub struct MemoryNode {
-    id: usize,
-    prefix: &'static [u8],
+pub enum MemoryNode {
+    /// プログラムを並べたディレクトリ
+    Root,
+    File {
+        id: usize,
+        prefix: &'static [u8],
+    },
 }
 
 impl Node for MemoryNode {
     fn get_id(&self) -> usize {
-        self.id
+        match *self {
+            // ファイルの id と重ならないようにする
+            MemoryNode::Root => PROGRAMS.len(),
+            MemoryNode::File { id, .. } => id,
+        }
     }
 
     fn size(&self) -> usize {
-        self.prefix.len()
+        self.bytes().len()
     }
 
     fn read(&self, buf: &mut [u8]) -> Result<(), ()> {
-        if buf.len() < self.prefix.len() {
+        let bytes = self.bytes();
+        if buf.len() < bytes.len() {
             return Err(());
         }
-        buf[0..self.size()].copy_from_slice(self.prefix);
+        buf[0..bytes.len()].copy_from_slice(bytes);
         Ok(())
     }
+
+    fn metadata(&self) -> Metadata {
+        let file_type = match self {
+            MemoryNode::Root => FILE_TYPE_DIRECTORY,
+            MemoryNode::File { .. } => FILE_TYPE_REGULAR,
+        };
+        // カーネルに埋め込まれたファイルなので時刻は全て 0 とする
+        Metadata {
+            file_type,
+            mode: 0o555,
+            id: self.get_id() as u64,
+            size: self.size() as u64,
+            ..Default::default()
+        }
+    }
+
+    fn truncate(&self) -> Result<(), ()> {
+        // カーネルに埋め込まれたファイルは書き換えられない
+        Err(())
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        let bytes = self.bytes();
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        let n = buf.len().min(bytes.len() - offset);
+        buf[..n].copy_from_slice(&bytes[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn read_dir(&self, index: usize) -> Option<String> {
+        match self {
+            MemoryNode::Root => PROGRAMS.get(index).map(|name| name.to_string()),
+            MemoryNode::File { .. } => None,
+        }
+    }
 }
 
 impl MemoryNode {
     fn new(id: usize, prefix: &'static [u8]) -> Self {
-        Self { id, prefix }
+        Self::File { id, prefix }
+    }
+
+    /// ファイルの中身. ディレクトリは空
+    fn bytes(&self) -> &'static [u8] {
+        match *self {
+            MemoryNode::Root => &[],
+            MemoryNode::File { prefix, .. } => prefix,
+        }
     }
 }
@@ -0,0 +1,461 @@
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt::{self, Display};
+
+use bitflags::bitflags;
+
+//
+// Sv39 のページテーブル
+//
+// 3段のページテーブルで 39 ビットの仮想アドレスを変換する. 扱うのは 4KiB のページだけ
+// 途中の段のページテーブルは FrameAllocator から割り当て, PageTable を捨てるときに返す
+// マップしたページ自体は呼び出し側のもので, PageTable は返さない
+//
+// カーネルに依存しないので, ホストで FrameAllocator の代わりを使ってテストできる
+//
+
+pub const PAGE_SIZE: usize = 4096;
+/// 1つのページテーブルのエントリの数
+const ENTRIES: usize = 512;
+const VPN_MASK: usize = ENTRIES - 1;
+/// エントリのうち物理ページ番号が始まるビット
+const PPN_SHIFT: usize = 10;
+const FLAGS_MASK: usize = (1 << PPN_SHIFT) - 1;
+/// 仮想アドレスの有効なビット数. これより上のビットは最上位ビットと同じでなければならない
+const VA_BITS: usize = 39;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PageFlags: usize {
+        const V = 1 << 0;
+        const R = 1 << 1;
+        const W = 1 << 2;
+        const X = 1 << 3;
+        const U = 1 << 4;
+    }
+}
+
+/// ページテーブルに使う物理ページを割り当てるもの
+///
+/// 物理アドレスはそのままポインタとして読み書きできなければならない
+/// (カーネルは物理メモリを恒等写像している)
+pub trait FrameAllocator {
+    /// 0 で埋めた物理ページを1つ割り当て, その物理アドレスを返す. 足りない場合は None
+    fn alloc_frame(&mut self) -> Option<usize>;
+    /// alloc_frame で割り当てたページを返す
+    fn free_frame(&mut self, paddr: usize);
+}
+
+/// map が失敗した理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// 仮想アドレスか物理アドレスがページの境界に揃っていない
+    Misaligned,
+    /// 既にマップされている
+    AlreadyMapped,
+    /// 途中の段のページテーブルを割り当てられなかった
+    OutOfFrames,
+}
+
+impl Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MapError::Misaligned => write!(f, "address is not page aligned"),
+            MapError::AlreadyMapped => write!(f, "page is already mapped"),
+            MapError::OutOfFrames => write!(f, "out of page table frames"),
+        }
+    }
+}
+
+impl core::error::Error for MapError {}
+
+/// 1つのアドレス空間のページテーブル
+#[derive(Debug)]
+pub struct PageTable<A: FrameAllocator> {
+    /// 最上位の段の物理アドレス
+    root: usize,
+    alloc: A,
+}
+
+impl<A: FrameAllocator> PageTable<A> {
+    /// 空のページテーブルを作る. 最上位の段を割り当てられなければ None
+    pub fn new(mut alloc: A) -> Option<Self> {
+        let root = alloc.alloc_frame()?;
+        Some(Self { root, alloc })
+    }
+
+    /// 最上位の段の物理アドレス. satp にはこのページ番号を設定する
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    /// vaddr のページを paddr に flags の権限でマップする. V は flags に応じてつける
+    ///
+    /// flags が R, W, X のどれも含まない場合はアクセスできないエントリになる (protect を参照)
+    pub fn map(&mut self, vaddr: usize, paddr: usize, flags: PageFlags) -> Result<(), MapError> {
+        if !vaddr.is_multiple_of(PAGE_SIZE) || !paddr.is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Misaligned);
+        }
+        let pte = self.entry_or_create(vaddr)?;
+        if *pte != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+        *pte = leaf_entry(paddr, flags);
+        Ok(())
+    }
+
+    /// vaddr のページのマッピングを外し, マップしていた物理アドレスを返す
+    ///
+    /// マップしていなかった場合は None. protect でアクセスできなくしたページのマッピングも外す
+    pub fn unmap(&mut self, vaddr: usize) -> Option<usize> {
+        let pte = self.entry_mut(vaddr)?;
+        if *pte == 0 {
+            return None;
+        }
+        let paddr = pte_paddr(*pte);
+        *pte = 0;
+        Some(paddr)
+    }
+
+    /// vaddr に対応する物理アドレスとページのフラグ. マップしていなければ None
+    ///
+    /// protect でアクセスできなくしたページは V を含まないフラグで返す
+    pub fn translate(&self, vaddr: usize) -> Option<(usize, PageFlags)> {
+        let pte = self.entry(vaddr)?;
+        if pte == 0 {
+            return None;
+        }
+        Some((pte_paddr(pte) + vaddr % PAGE_SIZE, pte_flags(pte)))
+    }
+
+    /// vaddr にマップしているページの権限を flags に変える. マップしていなかった場合は false
+    ///
+    /// flags が R, W, X のどれも含まない場合は V も外してアクセスできなくするが, 物理ページの番号は残しておく
+    /// (V があって R, W, X が無いエントリは次の段のページテーブルを指すことになるため)
+    pub fn protect(&mut self, vaddr: usize, flags: PageFlags) -> bool {
+        let Some(pte) = self.entry_mut(vaddr) else {
+            return false;
+        };
+        if *pte == 0 {
+            return false;
+        }
+        *pte = leaf_entry(pte_paddr(*pte), flags);
+        true
+    }
+
+    /// マップしている全てのページの (仮想アドレス, 物理アドレス, フラグ) を仮想アドレスの順に返す
+    pub fn iter(&self) -> Mappings<'_, A> {
+        Mappings {
+            table: self,
+            index: 0,
+        }
+    }
+
+    /// vaddr の最下段のエントリを指すポインタ. 途中の段が無い場合は None
+    fn entry_ptr(&self, vaddr: usize) -> Option<*mut usize> {
+        let mut table = self.root;
+        for level in [2, 1] {
+            let pte = unsafe { table_mut(table)[vpn(vaddr, level)] };
+            if !is_table(pte) {
+                return None;
+            }
+            table = pte_paddr(pte);
+        }
+        Some(unsafe { &raw mut table_mut(table)[vpn(vaddr, 0)] })
+    }
+
+    fn entry(&self, vaddr: usize) -> Option<usize> {
+        self.entry_ptr(vaddr).map(|pte| unsafe { *pte })
+    }
+
+    fn entry_mut(&mut self, vaddr: usize) -> Option<&mut usize> {
+        self.entry_ptr(vaddr).map(|pte| unsafe { &mut *pte })
+    }
+
+    /// vaddr の最下段のエントリ. 途中の段が無い場合は割り当てる
+    fn entry_or_create(&mut self, vaddr: usize) -> Result<&mut usize, MapError> {
+        let mut table = unsafe { table_mut(self.root) };
+        for level in [2, 1] {
+            let pte = &mut table[vpn(vaddr, level)];
+            if *pte == 0 {
+                let frame = self.alloc.alloc_frame().ok_or(MapError::OutOfFrames)?;
+                *pte = (frame / PAGE_SIZE) << PPN_SHIFT | PageFlags::V.bits();
+            } else if !is_table(*pte) {
+                // 大きなページとしてマップされている
+                return Err(MapError::AlreadyMapped);
+            }
+            table = unsafe { table_mut(pte_paddr(*pte)) };
+        }
+        Ok(&mut table[vpn(vaddr, 0)])
+    }
+}
+
+impl<A: FrameAllocator> Drop for PageTable<A> {
+    /// 途中の段と最上位の段のページテーブルを返す. マップしていたページは返さない
+    fn drop(&mut self) {
+        let root = unsafe { table_mut(self.root) };
+        for &pte2 in root.iter().filter(|pte| is_table(**pte)) {
+            let table1 = unsafe { table_mut(pte_paddr(pte2)) };
+            for &pte1 in table1.iter().filter(|pte| is_table(**pte)) {
+                self.alloc.free_frame(pte_paddr(pte1));
+            }
+            self.alloc.free_frame(pte_paddr(pte2));
+        }
+        self.alloc.free_frame(self.root);
+    }
+}
+
+/// PageTable::iter() が返すイテレータ
+pub struct Mappings<'a, A: FrameAllocator> {
+    table: &'a PageTable<A>,
+    /// 次に調べる最下段のエントリの通し番号. 仮想ページ番号と同じ
+    index: usize,
+}
+
+impl<A: FrameAllocator> Iterator for Mappings<'_, A> {
+    type Item = (usize, usize, PageFlags);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < ENTRIES * ENTRIES * ENTRIES {
+            let vaddr = canonical(self.index * PAGE_SIZE);
+            let pte2 = unsafe { table_mut(self.table.root)[vpn(vaddr, 2)] };
+            if !is_table(pte2) {
+                // 次の段が無いところはまとめて飛ばす
+                self.index = (self.index / (ENTRIES * ENTRIES) + 1) * ENTRIES * ENTRIES;
+                continue;
+            }
+            let pte1 = unsafe { table_mut(pte_paddr(pte2))[vpn(vaddr, 1)] };
+            if !is_table(pte1) {
+                self.index = (self.index / ENTRIES + 1) * ENTRIES;
+                continue;
+            }
+            let pte0 = unsafe { table_mut(pte_paddr(pte1))[vpn(vaddr, 0)] };
+            self.index += 1;
+            if pte0 != 0 {
+                return Some((vaddr, pte_paddr(pte0), pte_flags(pte0)));
+            }
+        }
+        None
+    }
+}
+
+/// 物理アドレス paddr のページテーブル
+///
+/// # Safety
+/// paddr は FrameAllocator から割り当てたページテーブルのページでなければならない
+unsafe fn table_mut<'a>(paddr: usize) -> &'a mut [usize; ENTRIES] {
+    unsafe { &mut *(paddr as *mut [usize; ENTRIES]) }
+}
+
+/// vaddr の level 段目の仮想ページ番号
+fn vpn(vaddr: usize, level: usize) -> usize {
+    vaddr >> (12 + 9 * level) & VPN_MASK
+}
+
+/// 下位 VA_BITS ビットの仮想アドレスの上位ビットを最上位ビットで埋める
+fn canonical(vaddr: usize) -> usize {
+    if vaddr & (1 << (VA_BITS - 1)) != 0 {
+        vaddr | !((1 << VA_BITS) - 1)
+    } else {
+        vaddr
+    }
+}
+
+/// paddr を flags でマップする最下段のエントリ
+fn leaf_entry(paddr: usize, flags: PageFlags) -> usize {
+    let mut bits = flags.bits() & !PageFlags::V.bits();
+    if flags.intersects(PageFlags::R | PageFlags::W | PageFlags::X) {
+        bits |= PageFlags::V.bits();
+    }
+    (paddr / PAGE_SIZE) << PPN_SHIFT | bits
+}
+
+/// 次の段のページテーブルを指すエントリか
+fn is_table(pte: usize) -> bool {
+    let leaf = PageFlags::R | PageFlags::W | PageFlags::X;
+    pte & PageFlags::V.bits() != 0 && pte & leaf.bits() == 0
+}
+
+fn pte_paddr(pte: usize) -> usize {
+    (pte >> PPN_SHIFT) * PAGE_SIZE
+}
+
+fn pte_flags(pte: usize) -> PageFlags {
+    PageFlags::from_bits_truncate(pte & FLAGS_MASK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc, vec::Vec};
+
+    #[repr(C, align(4096))]
+    struct Frame([usize; ENTRIES]);
+
+    #[derive(Default)]
+    struct State {
+        /// 割り当てたページ. テストが終わるまで持っておく
+        frames: Vec<Box<Frame>>,
+        /// 割り当てていて, まだ返されていないページ
+        live: Vec<usize>,
+        /// 割り当てられる数の上限
+        limit: Option<usize>,
+    }
+
+    /// ホストのメモリからページを割り当てる FrameAllocator
+    #[derive(Clone, Default)]
+    struct MockFrames(Rc<RefCell<State>>);
+
+    impl MockFrames {
+        fn live(&self) -> usize {
+            self.0.borrow().live.len()
+        }
+    }
+
+    impl FrameAllocator for MockFrames {
+        fn alloc_frame(&mut self) -> Option<usize> {
+            let mut state = self.0.borrow_mut();
+            if state.limit.is_some_and(|limit| state.frames.len() >= limit) {
+                return None;
+            }
+            let frame = Box::new(Frame([0; ENTRIES]));
+            let paddr = &*frame as *const Frame as usize;
+            state.frames.push(frame);
+            state.live.push(paddr);
+            Some(paddr)
+        }
+
+        fn free_frame(&mut self, paddr: usize) {
+            let mut state = self.0.borrow_mut();
+            let index = state.live.iter().position(|&p| p == paddr);
+            let index = index.expect("freed a frame that is not allocated");
+            state.live.swap_remove(index);
+        }
+    }
+
+    const USER_RW: PageFlags = PageFlags::U.union(PageFlags::R).union(PageFlags::W);
+
+    #[test]
+    fn map_and_translate() {
+        let frames = MockFrames::default();
+        let mut table = PageTable::new(frames.clone()).unwrap();
+        table.map(0x1000_0000, 0x8020_0000, USER_RW).unwrap();
+        assert_eq!(
+            table.translate(0x1000_0123),
+            Some((0x8020_0123, USER_RW | PageFlags::V))
+        );
+        assert_eq!(table.translate(0x1000_1000), None);
+        assert_eq!(table.translate(0x4000_0000), None);
+        // 最上位の段と途中の2段
+        assert_eq!(frames.live(), 3);
+        // 同じ途中の段を使うページでは割り当てない
+        table.map(0x1000_1000, 0x8020_1000, USER_RW).unwrap();
+        assert_eq!(frames.live(), 3);
+    }
+
+    #[test]
+    fn map_rejects_misaligned_and_mapped() {
+        let mut table = PageTable::new(MockFrames::default()).unwrap();
+        assert_eq!(
+            table.map(0x1000_0010, 0x8020_0000, USER_RW),
+            Err(MapError::Misaligned)
+        );
+        assert_eq!(
+            table.map(0x1000_0000, 0x8020_0010, USER_RW),
+            Err(MapError::Misaligned)
+        );
+        table.map(0x1000_0000, 0x8020_0000, USER_RW).unwrap();
+        assert_eq!(
+            table.map(0x1000_0000, 0x8030_0000, USER_RW),
+            Err(MapError::AlreadyMapped)
+        );
+        // 失敗しても元のマッピングは変わらない
+        assert_eq!(table.translate(0x1000_0000).unwrap().0, 0x8020_0000);
+    }
+
+    #[test]
+    fn unmap() {
+        let mut table = PageTable::new(MockFrames::default()).unwrap();
+        table.map(0x1000_0000, 0x8020_0000, USER_RW).unwrap();
+        assert_eq!(table.unmap(0x1000_0000), Some(0x8020_0000));
+        assert_eq!(table.translate(0x1000_0000), None);
+        assert_eq!(table.unmap(0x1000_0000), None);
+        assert_eq!(table.unmap(0x4000_0000), None);
+        // 外した後は同じ位置にマップし直せる
+        table.map(0x1000_0000, 0x8030_0000, USER_RW).unwrap();
+        assert_eq!(table.translate(0x1000_0000).unwrap().0, 0x8030_0000);
+    }
+
+    #[test]
+    fn protect() {
+        let mut table = PageTable::new(MockFrames::default()).unwrap();
+        table.map(0x1000_0000, 0x8020_0000, USER_RW).unwrap();
+        assert!(table.protect(0x1000_0000, PageFlags::U | PageFlags::R));
+        assert_eq!(
+            table.translate(0x1000_0000),
+            Some((0x8020_0000, PageFlags::V | PageFlags::U | PageFlags::R))
+        );
+        // 権限を全て外しても物理ページは残り, 戻せる
+        assert!(table.protect(0x1000_0000, PageFlags::U));
+        assert_eq!(
+            table.translate(0x1000_0000),
+            Some((0x8020_0000, PageFlags::U))
+        );
+        assert_eq!(
+            table.map(0x1000_0000, 0x8030_0000, USER_RW),
+            Err(MapError::AlreadyMapped)
+        );
+        assert!(table.protect(0x1000_0000, USER_RW));
+        assert_eq!(
+            table.translate(0x1000_0000),
+            Some((0x8020_0000, USER_RW | PageFlags::V))
+        );
+        assert!(!table.protect(0x1000_1000, USER_RW));
+    }
+
+    #[test]
+    fn iter_in_address_order() {
+        let mut table = PageTable::new(MockFrames::default()).unwrap();
+        let high = 0xffff_ffc0_0000_0000;
+        let rx = PageFlags::R | PageFlags::X;
+        table.map(high, 0x8000_0000, rx).unwrap();
+        table.map(0x4000_2000, 0x8020_2000, USER_RW).unwrap();
+        table.map(0x1000_0000, 0x8020_0000, USER_RW).unwrap();
+        table.map(0x1000_1000, 0x8020_1000, PageFlags::U).unwrap();
+        let mappings: Vec<_> = table.iter().collect();
+        assert_eq!(
+            mappings,
+            [
+                (0x1000_0000, 0x8020_0000, USER_RW | PageFlags::V),
+                (0x1000_1000, 0x8020_1000, PageFlags::U),
+                (0x4000_2000, 0x8020_2000, USER_RW | PageFlags::V),
+                (high, 0x8000_0000, rx | PageFlags::V),
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_frames() {
+        let frames = MockFrames::default();
+        frames.0.borrow_mut().limit = Some(2);
+        let mut table = PageTable::new(frames.clone()).unwrap();
+        assert_eq!(
+            table.map(0x1000_0000, 0x8020_0000, USER_RW),
+            Err(MapError::OutOfFrames)
+        );
+        assert_eq!(table.translate(0x1000_0000), None);
+    }
+
+    #[test]
+    fn drop_frees_tables() {
+        let frames = MockFrames::default();
+        let mut table = PageTable::new(frames.clone()).unwrap();
+        table.map(0x1000_0000, 0x8020_0000, USER_RW).unwrap();
+        table.map(0x1020_0000, 0x8020_1000, USER_RW).unwrap();
+        table.map(0x4000_0000, 0x8020_2000, USER_RW).unwrap();
+        // 最上位の段, 1GiB ごとの途中の段が2つ, 2MiB ごとの最下段が3つ
+        assert_eq!(frames.live(), 6);
+        drop(table);
+        assert_eq!(frames.live(), 0);
+    }
+}
@@ -12,6 +12,15 @@ cp ./target/user-riscv64gc-unknown-none-elf/release/sh ./sh.elf
 cargo build -r --bin ps --target user/user-riscv64gc-unknown-none-elf.json
 cp ./target/user-riscv64gc-unknown-none-elf/release/ps ./ps.elf
 
+cargo build -r --bin cat --target user/user-riscv64gc-unknown-none-elf.json
+cp ./target/user-riscv64gc-unknown-none-elf/release/cat ./cat.elf
+
+cargo build -r --bin grep --target user/user-riscv64gc-unknown-none-elf.json
+cp ./target/user-riscv64gc-unknown-none-elf/release/grep ./grep.elf
+
+cargo build -r --bin printenv --target user/user-riscv64gc-unknown-none-elf.json
+cp ./target/user-riscv64gc-unknown-none-elf/release/printenv ./printenv.elf
+
 cargo build -r --bin kernel --target kernel/kernel-riscv64gc-unknown-none-elf.json
 cp ./target/kernel-riscv64gc-unknown-none-elf/release/kernel ./kernel.elf
 
@@ -6,3 +6,175 @@ pub const SYS_YIELD_PROCESS: usize = 3;
 pub const SYS_EXIT_PROCESS: usize = 4;
 pub const SYS_CREATE_PROCESS: usize = 5;
 pub const SYS_LIST_PROCESS: usize = 6;
+pub const SYS_OPEN: usize = 7;
+pub const SYS_READ: usize = 8;
+pub const SYS_WRITE: usize = 9;
+pub const SYS_CLOSE: usize = 10;
+pub const SYS_READDIR: usize = 11;
+pub const SYS_STAT: usize = 12;
+pub const SYS_FSTAT: usize = 13;
+pub const SYS_PIPE: usize = 14;
+pub const SYS_DUP: usize = 15;
+pub const SYS_DUP2: usize = 16;
+pub const SYS_WAIT_PROCESS: usize = 17;
+pub const SYS_GET_ARGS: usize = 18;
+pub const SYS_GET_ENV: usize = 19;
+pub const SYS_SETPGID: usize = 20;
+pub const SYS_GETPGID: usize = 21;
+pub const SYS_TCSETPGRP: usize = 22;
+pub const SYS_TCGETPGRP: usize = 23;
+pub const SYS_KILL: usize = 24;
+pub const SYS_SIGACTION: usize = 25;
+pub const SYS_SIGPROCMASK: usize = 26;
+pub const SYS_SIGRETURN: usize = 27;
+pub const SYS_BRK: usize = 28;
+pub const SYS_MMAP: usize = 29;
+pub const SYS_MUNMAP: usize = 30;
+pub const SYS_MPROTECT: usize = 31;
+
+// エラー番号
+// システムコールは失敗すると -1 か, エラー番号の負の値を返す
+/// 入出力に失敗した
+pub const EIO: isize = 5;
+/// 引数の列が長すぎる
+pub const E2BIG: isize = 7;
+/// 実行ファイルの形式が正しくない
+pub const ENOEXEC: isize = 8;
+/// メモリが足りない
+pub const ENOMEM: isize = 12;
+/// ユーザー空間のアドレスが正しくない
+pub const EFAULT: isize = 14;
+/// 引数が正しくない
+pub const EINVAL: isize = 22;
+/// パスが長すぎる
+pub const ENAMETOOLONG: isize = 36;
+/// エラー番号の最大値. -MAX_ERRNO から -1 までの戻り値はエラーを表す
+pub const MAX_ERRNO: isize = 4095;
+
+pub const STDIN_FILENO: usize = 0;
+pub const STDOUT_FILENO: usize = 1;
+pub const STDERR_FILENO: usize = 2;
+
+// open のフラグ
+pub const O_RDONLY: usize = 0;
+pub const O_WRONLY: usize = 1;
+pub const O_RDWR: usize = 2;
+pub const O_ACCMODE: usize = 3;
+pub const O_CREAT: usize = 0o100;
+pub const O_TRUNC: usize = 0o1000;
+pub const O_APPEND: usize = 0o2000;
+
+// mmap, mprotect のメモリの保護
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+// mmap のフラグ. 今は MAP_PRIVATE | MAP_ANONYMOUS のマッピングだけに対応している
+pub const MAP_PRIVATE: usize = 0x02;
+/// addr をヒントではなくそのまま使う. 重なっていた領域のマッピングは外す
+pub const MAP_FIXED: usize = 0x10;
+/// ファイルではなく 0 で埋めたメモリをマップする
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+// ユーザースタックの配置
+/// ユーザースタックの上端. その下に USER_STACK_SIZE だけ割り当て, さらに下の1ページは割り当てない
+pub const USER_STACK_TOP: usize = 0x8000_0000;
+/// PT_GNU_STACK で大きさが指定されていない場合のユーザースタックの大きさ
+pub const USER_STACK_SIZE: usize = 64 * 1024;
+/// PT_GNU_STACK で指定できるユーザースタックの最大の大きさ
+pub const USER_STACK_MAX: usize = 1024 * 1024;
+
+// wait のオプション
+/// 子プロセスが終了していなければ待たずに 0 を返す
+pub const WNOHANG: usize = 1;
+/// 停止した子プロセスも報告する
+pub const WUNTRACED: usize = 2;
+
+/// wait の状態のうち, 子プロセスが停止したことを表すビット
+///
+/// 下位8ビットは停止させたシグナルの番号. このビットが無い場合は下位8ビットが終了コード
+pub const WAIT_STOPPED: i32 = 0x100;
+
+// シグナルの番号
+pub const SIGINT: usize = 2;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGBUS: usize = 7;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+/// シグナルの番号は 1 から NSIG - 1 まで
+pub const NSIG: usize = 32;
+
+/// シグナルを既定の動作で処理する
+pub const SIG_DFL: usize = 0;
+/// シグナルを無視する
+pub const SIG_IGN: usize = 1;
+
+// sigprocmask の how
+/// 指定したシグナルを保留の対象に加える
+pub const SIG_BLOCK: usize = 0;
+/// 指定したシグナルを保留の対象から除く
+pub const SIG_UNBLOCK: usize = 1;
+/// 保留するシグナルの集合を置き換える
+pub const SIG_SETMASK: usize = 2;
+
+/// sigaction で設定するシグナルの処理方法
+///
+/// handler は SIG_DFL, SIG_IGN かシグナルの番号を引数に呼ばれる関数のアドレス
+/// ハンドラから戻ると restorer に戻るので, restorer は sigreturn を呼ぶ
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SigAction {
+    pub handler: usize,
+    /// ハンドラの実行中に保留するシグナルのビット集合. 処理中のシグナルも保留される
+    pub mask: u32,
+    pub flags: u32,
+    pub restorer: usize,
+}
+
+pub const FILE_TYPE_REGULAR: u32 = 1;
+pub const FILE_TYPE_DIRECTORY: u32 = 2;
+pub const FILE_TYPE_CHAR_DEVICE: u32 = 3;
+pub const FILE_TYPE_FIFO: u32 = 4;
+
+/// stat/fstat が返すファイルの情報
+///
+/// 時刻は起動からの経過秒数
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Metadata {
+    pub file_type: u32,
+    /// rwx のパーミッションビット (例: 0o644)
+    pub mode: u32,
+    pub id: u64,
+    pub size: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl Metadata {
+    pub fn is_file(&self) -> bool {
+        self.file_type == FILE_TYPE_REGULAR
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FILE_TYPE_DIRECTORY
+    }
+
+    pub fn is_char_device(&self) -> bool {
+        self.file_type == FILE_TYPE_CHAR_DEVICE
+    }
+
+    pub fn is_fifo(&self) -> bool {
+        self.file_type == FILE_TYPE_FIFO
+    }
+}
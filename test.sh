@@ -1,7 +1,18 @@
 cargo fmt --all
+
+# ページテーブルの単体テストはホストで実行する
+# .cargo/config.toml の build-std と target を使わないように, リポジトリの外から呼ぶ
+(cd / && cargo +nightly test --manifest-path "$OLDPWD/pagetable/Cargo.toml")
+
 cargo build --features shell-test --bin sh --target user/user-riscv64gc-unknown-none-elf.json
 cp ./target/user-riscv64gc-unknown-none-elf/debug/sh ./sh.elf
 
+# テストから起動するプログラム
+for bin in ps cat grep printenv; do
+    cargo build -r --bin $bin --target user/user-riscv64gc-unknown-none-elf.json
+    cp ./target/user-riscv64gc-unknown-none-elf/release/$bin ./$bin.elf
+done
+
 cargo build -r --bin kernel --target kernel/kernel-riscv64gc-unknown-none-elf.json
 cp ./target/kernel-riscv64gc-unknown-none-elf/release/kernel ./kernel.elf
 
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+use syscall::{STDIN_FILENO, STDOUT_FILENO};
+use userlib::{args, eprintln, exit_with_code, fs, user_main};
+
+user_main!(main);
+
+fn main() {
+    let mut code = 0;
+    let mut files = args().skip(1).peekable();
+    if files.peek().is_none() {
+        // ファイルが指定されていない場合は標準入力を読む
+        if copy_to_stdout(STDIN_FILENO).is_err() {
+            eprintln!("cat: read error");
+            code = 1;
+        }
+    }
+    for path in files {
+        let Ok(file) = fs::File::open(path) else {
+            eprintln!("cat: {path}: No such file");
+            code = 1;
+            continue;
+        };
+        if copy_to_stdout(file.fd()).is_err() {
+            eprintln!("cat: {path}: read error");
+            code = 1;
+        }
+    }
+    let _ = exit_with_code(code);
+}
+
+/// fd の終端まで標準出力に書き出す
+fn copy_to_stdout(fd: usize) -> Result<(), isize> {
+    let mut buf = [0u8; 256];
+    loop {
+        let n = fs::read(fd, &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut written = 0;
+        while written < n {
+            written += fs::write(STDOUT_FILENO, &buf[written..n])?;
+        }
+    }
+}
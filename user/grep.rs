@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+use core::str::from_utf8;
+
+use syscall::STDIN_FILENO;
+use userlib::{Writer, args, eprintln, exit_with_code, fs, user_main};
+
+user_main!(main);
+
+/// 1行として扱う最大のバイト数. これより長い行は分割して照合する
+const LINE_SIZE: usize = 256;
+
+fn main() {
+    let mut args = args().skip(1);
+    let Some(pattern) = args.next() else {
+        eprintln!("usage: grep PATTERN [FILE]");
+        let _ = exit_with_code(2);
+        return;
+    };
+
+    // 一致する行があれば 0, 無ければ 1, エラーは 2 で終了する
+    let result = match args.next() {
+        Some(path) => match fs::File::open(path) {
+            Ok(file) => grep(pattern, file.fd()),
+            Err(e) => {
+                eprintln!("grep: {path}: No such file");
+                Err(e)
+            }
+        },
+        None => grep(pattern, STDIN_FILENO),
+    };
+    let code = match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(_) => 2,
+    };
+    let _ = exit_with_code(code);
+}
+
+/// fd を行ごとに読み, pattern を含む行を表示する
+///
+/// 一致する行があったかどうかを返す
+fn grep(pattern: &str, fd: usize) -> Result<bool, isize> {
+    let mut found = false;
+    let mut line = [0u8; LINE_SIZE];
+    let mut len = 0;
+    let mut buf = [0u8; 256];
+    loop {
+        let n = fs::read(fd, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &c in &buf[..n] {
+            if c != b'\n' {
+                line[len] = c;
+                len += 1;
+            }
+            if c == b'\n' || len == LINE_SIZE {
+                found |= print_if_match(pattern, &line[..len])?;
+                len = 0;
+            }
+        }
+    }
+    // 改行で終わっていない最後の行
+    if len > 0 {
+        found |= print_if_match(pattern, &line[..len])?;
+    }
+    Ok(found)
+}
+
+fn print_if_match(pattern: &str, line: &[u8]) -> Result<bool, isize> {
+    let Ok(text) = from_utf8(line) else {
+        return Ok(false);
+    };
+    if !text.contains(pattern) {
+        return Ok(false);
+    }
+    Writer::write_all(line)?;
+    Writer::write_byte(b'\n')?;
+    Ok(true)
+}
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+use userlib::{args, env, exit_with_code, println, user_main};
+
+user_main!(main);
+
+/// 引数が無い場合は全ての環境変数を, ある場合はその変数の値を表示する
+///
+/// 指定した変数が1つでも無ければ 1 で終了する
+fn main() {
+    let mut names = args().skip(1).peekable();
+    if names.peek().is_none() {
+        for (name, value) in env::vars() {
+            println!("{name}={value}");
+        }
+        return;
+    }
+
+    let mut code = 0;
+    for name in names {
+        match env::var(name) {
+            Some(value) => {
+                println!("{value}");
+            }
+            None => code = 1,
+        }
+    }
+    let _ = exit_with_code(code);
+}
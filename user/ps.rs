@@ -1,11 +1,68 @@
 #![no_std]
 #![no_main]
 
-use userlib::{exit_process, list_process, user_main};
+use core::str::from_utf8;
+
+use userlib::{exit_process, fs::File, println, user_main};
 
 user_main!(main);
 
 fn main() {
-    let _ = list_process();
+    if let Err(e) = show_process_list() {
+        println!("ps: failed to read /proc ({e})");
+    }
     let _ = exit_process();
 }
+
+/// /proc 以下の status を読んでプロセスの一覧を表示する
+fn show_process_list() -> Result<(), isize> {
+    let proc_dir = File::open("/proc")?;
+
+    // PID列を右寄せにしてヘッダの"D"と1桁目を揃える
+    println!("\t{:>4}\t{:<8}\t{}", "PID", "State", "Name");
+
+    let mut entry_buf = [0u8; 32];
+    while let Some(entry) = proc_dir.read_dir(&mut entry_buf)? {
+        // pid のディレクトリ以外は飛ばす
+        if entry.parse::<usize>().is_err() {
+            continue;
+        }
+
+        let mut path_buf = [0u8; 64];
+        let Some(path) = concat(&mut path_buf, &["/proc/", entry, "/status"]) else {
+            continue;
+        };
+        // 一覧を読んだ後に終了したプロセスは表示しない
+        let Ok(status) = File::open(path) else {
+            continue;
+        };
+
+        let mut status_buf = [0u8; 256];
+        let n = status.read_full(&mut status_buf)?;
+        let Ok(status) = from_utf8(&status_buf[..n]) else {
+            continue;
+        };
+
+        let (mut name, mut state) = ("", "");
+        for line in status.lines() {
+            match line.split_once(":\t") {
+                Some(("Name", value)) => name = value,
+                Some(("State", value)) => state = value,
+                _ => {}
+            }
+        }
+        println!("\t{:>4}\t{:<8}\t{}", entry, state, name);
+    }
+    Ok(())
+}
+
+/// 文字列を buf の中で連結する
+fn concat<'a>(buf: &'a mut [u8], parts: &[&str]) -> Option<&'a str> {
+    let mut len = 0;
+    for part in parts {
+        let end = len + part.len();
+        buf.get_mut(len..end)?.copy_from_slice(part.as_bytes());
+        len = end;
+    }
+    from_utf8(&buf[..len]).ok()
+}
@@ -1,7 +1,16 @@
 #![no_std]
 #![no_main]
+#![cfg_attr(feature = "shell-test", feature(thread_local))]
+
+#[cfg(feature = "shell-test")]
+extern crate alloc;
 
 mod sh_cmd;
+mod sh_complete;
+mod sh_env;
+mod sh_history;
+mod sh_job;
+mod sh_script;
 
 use core::{
     error,
@@ -9,161 +18,799 @@ use core::{
     str::{Utf8Error, from_utf8},
 };
 
-use userlib::{self, Writer, print, println, read_byte, user_main};
+use sh_complete::Candidates;
+use sh_env::{Env, EnvError, MAX_VARS};
+use sh_history::{History, HistoryError, Search};
+use sh_job::{Job, Jobs};
+use sh_script::Executor;
+use syscall::{
+    ENOEXEC, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, STDERR_FILENO, STDIN_FILENO,
+    STDOUT_FILENO,
+};
+use userlib::{
+    self, Writer, eprintln,
+    fs::{self, File},
+    line::{self, EditError, Key, LineEditor},
+    print, println, user_main,
+};
 
 const HISTORY_SIZE: usize = 128;
 const BUF_SIZE: usize = 128;
 const ARGS_SIZE: usize = 128;
+/// 展開した単語を書き込むバッファの大きさ
+const WORD_BUF_SIZE: usize = 1024;
+/// パイプラインでつなげられるコマンドの数
+const MAX_PIPELINE: usize = 8;
+/// パイプラインの実行中にシェルが開くファイルディスクリプタの数
+const MAX_OPENED: usize = 32;
+
+/// コマンドを探すパスを組み立てるバッファの大きさ
+const PATH_BUF_SIZE: usize = 128;
+/// 起動時に PATH が無い場合の値
+const DEFAULT_PATH: &str = "/bin";
+
+/// $HOME に置く履歴ファイルの名前
+const HISTORY_FILE_NAME: &str = ".nex_history";
+/// $HOME に履歴ファイルを置けない場合のパス
+const FALLBACK_HISTORY_PATH: &str = "/tmp/.sh_history";
+
+/// シェル自身で実行するコマンド
+const BUILTINS: [&str; 16] = [
+    "hello", "help", "echo", "history", "ohgiri", "yield", "exit", "set", "export", "unset", "env",
+    "true", "false", "jobs", "fg", "bg",
+];
+
+/// コマンドが見つからなかったときの終了コード
+const STATUS_NOT_FOUND: i32 = 127;
+/// コマンドを実行できなかったときの終了コード
+const STATUS_NOT_EXECUTABLE: i32 = 126;
+/// スクリプトに構文エラーがあったときの終了コード
+const STATUS_SYNTAX_ERROR: i32 = 2;
+/// 読み込めるスクリプトファイルの大きさ
+const SCRIPT_SIZE: usize = 4096;
+
+/// コマンドラインを区切った単位
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    /// `|`
+    Pipe,
+    /// `<`
+    RedirectIn,
+    /// `>`
+    RedirectOut,
+    /// `>>`
+    RedirectAppend,
+    /// `2>&1`
+    StderrToStdout,
+}
+
+/// 演算子とそのトークン. 先頭から順に照合するので長いものを先に置く
+const OPERATORS: [(&str, Token<'static>); 5] = [
+    ("2>&1", Token::StderrToStdout),
+    (">>", Token::RedirectAppend),
+    ("|", Token::Pipe),
+    ("<", Token::RedirectIn),
+    (">", Token::RedirectOut),
+];
+
+/// 区切られたトークンの列
+struct Tokens<'a> {
+    items: [Token<'a>; ARGS_SIZE],
+    len: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new() -> Self {
+        Self {
+            items: [Token::Word(""); ARGS_SIZE],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, token: Token<'a>) -> Result<(), ParseError> {
+        let slot = self
+            .items
+            .get_mut(self.len)
+            .ok_or(ParseError::TooManyTokens)?;
+        *slot = token;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn as_slice(&self) -> &[Token<'a>] {
+        &self.items[..self.len]
+    }
+}
+
+/// `$` で展開する値を提供する
+trait Variables {
+    /// 変数 name の値. 定義されていない場合は None
+    fn get(&self, name: &str) -> Option<&str>;
+    /// `$?` で展開する, 最後に実行したコマンドの終了コード
+    fn status(&self) -> i32;
+}
+
+/// 入力をトークンに分割する字句解析器
+///
+/// クォートやエスケープを取り除き, 変数を展開した単語を out に書き込む
+/// トークンの単語は out を借用する
+struct Lexer<'a, 'i, V: Variables> {
+    input: &'i [u8],
+    pos: usize,
+    /// 単語を書き込む残りの領域
+    out: &'a mut [u8],
+    /// out の先頭に書き込んだ, 作成中の単語の長さ
+    len: usize,
+    /// 作成中の単語があるか. `""` のような空の単語も1つの単語とする
+    in_word: bool,
+    vars: &'i V,
+    tokens: Tokens<'a>,
+}
+
+impl<'a, 'i, V: Variables> Lexer<'a, 'i, V> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn push_byte(&mut self, c: u8) -> Result<(), ParseError> {
+        let slot = self.out.get_mut(self.len).ok_or(ParseError::TooLong)?;
+        *slot = c;
+        self.len += 1;
+        self.in_word = true;
+        Ok(())
+    }
+
+    fn push_str(&mut self, s: &str) -> Result<(), ParseError> {
+        s.bytes().try_for_each(|c| self.push_byte(c))
+    }
+
+    /// 作成中の単語をトークンとして確定する
+    fn finish_word(&mut self) -> Result<(), ParseError> {
+        if !self.in_word {
+            return Ok(());
+        }
+        // 書き込んだ部分を切り離し, 残りを次の単語に使う
+        let (word, rest) = core::mem::take(&mut self.out).split_at_mut(self.len);
+        self.out = rest;
+        self.len = 0;
+        self.in_word = false;
+        let word: &'a [u8] = word;
+        self.tokens.push(Token::Word(from_utf8(word)?))
+    }
+
+    /// `$` の直後から変数名を読み, その値を書き込む
+    fn expand(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(b'?') => {
+                self.pos += 1;
+                let mut digits = [0u8; 11];
+                self.push_str(format_status(self.vars.status(), &mut digits))
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.next().ok_or(ParseError::BadSubstitution)? != b'}' {}
+                let name = &self.input[start..self.pos - 1];
+                if name.is_empty() || !name.iter().all(|&c| is_name_char(c)) {
+                    return Err(ParseError::BadSubstitution);
+                }
+                self.push_var(name)
+            }
+            Some(c) if is_name_char(c) => {
+                let start = self.pos;
+                while self.peek().is_some_and(is_name_char) {
+                    self.pos += 1;
+                }
+                self.push_var(&self.input[start..self.pos])
+            }
+            // 変数名が続かない `$` はそのまま
+            _ => self.push_byte(b'$'),
+        }
+    }
+
+    fn push_var(&mut self, name: &[u8]) -> Result<(), ParseError> {
+        let name = from_utf8(name)?;
+        // 未定義の変数は空文字列に展開する
+        let vars = self.vars;
+        match vars.get(name) {
+            Some(value) => self.push_str(value),
+            None => Ok(()),
+        }
+    }
+
+    /// `'` の直後から閉じる `'` までをそのまま書き込む
+    fn single_quoted(&mut self) -> Result<(), ParseError> {
+        self.in_word = true;
+        loop {
+            match self.next().ok_or(ParseError::UnterminatedQuote)? {
+                b'\'' => return Ok(()),
+                c => self.push_byte(c)?,
+            }
+        }
+    }
+
+    /// `"` の直後から閉じる `"` までを, 変数を展開して書き込む
+    fn double_quoted(&mut self) -> Result<(), ParseError> {
+        self.in_word = true;
+        loop {
+            match self.next().ok_or(ParseError::UnterminatedQuote)? {
+                b'"' => return Ok(()),
+                b'$' => self.expand()?,
+                // `\` がエスケープするのは特別な意味を持つ文字だけ
+                b'\\' => match self.peek() {
+                    Some(c @ (b'"' | b'\\' | b'$')) => {
+                        self.pos += 1;
+                        self.push_byte(c)?;
+                    }
+                    _ => self.push_byte(b'\\')?,
+                },
+                c => self.push_byte(c)?,
+            }
+        }
+    }
+
+    fn run(mut self) -> Result<Tokens<'a>, ParseError> {
+        while let Some(c) = self.peek() {
+            // 単語の途中でなければ `2>&1` を演算子として扱う
+            let rest = &self.input[self.pos..];
+            let operator = OPERATORS
+                .iter()
+                .filter(|(op, _)| !self.in_word || !op.starts_with('2'))
+                .find(|(op, _)| rest.starts_with(op.as_bytes()));
+            if let Some((op, token)) = operator {
+                self.finish_word()?;
+                self.tokens.push(*token)?;
+                self.pos += op.len();
+                continue;
+            }
+
+            self.pos += 1;
+            match c {
+                c if c.is_ascii_whitespace() => self.finish_word()?,
+                b'\'' => self.single_quoted()?,
+                b'"' => self.double_quoted()?,
+                // 次の1文字をそのまま使う. 行末の場合は `\` 自身
+                b'\\' => {
+                    let c = self.next().unwrap_or(b'\\');
+                    self.push_byte(c)?;
+                }
+                b'$' => self.expand()?,
+                c => self.push_byte(c)?,
+            }
+        }
+        self.finish_word()?;
+        Ok(self.tokens)
+    }
+}
+
+/// 変数名に使える文字
+fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// 終了コードを10進数の文字列にする
+fn format_status(status: i32, buf: &mut [u8; 11]) -> &str {
+    let mut n = status.unsigned_abs();
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    if status < 0 {
+        pos -= 1;
+        buf[pos] = b'-';
+    }
+    // ASCII の数字と符号だけなので必ず成功する
+    from_utf8(&buf[pos..]).unwrap_or("")
+}
+
+/// 入力を単語と演算子に分割する
+///
+/// クォートとエスケープを解釈し, `$VAR`, `${VAR}`, `$?` を展開する
+/// 展開した単語は buf に書き込まれる
+fn tokenize<'a>(
+    input: &str,
+    buf: &'a mut [u8],
+    vars: &impl Variables,
+) -> Result<Tokens<'a>, ParseError> {
+    let lexer = Lexer {
+        input: input.as_bytes(),
+        pos: 0,
+        out: buf,
+        len: 0,
+        in_word: false,
+        vars,
+        tokens: Tokens::new(),
+    };
+    lexer.run()
+}
+
+/// パイプラインの1段分のコマンド
+struct Stage<'a> {
+    args: [&'a str; ARGS_SIZE],
+    argc: usize,
+    /// `<` で指定されたファイル
+    stdin: Option<&'a str>,
+    /// `>` または `>>` で指定されたファイルと, 追記するかどうか
+    stdout: Option<(&'a str, bool)>,
+    stderr_to_stdout: bool,
+}
+
+impl<'a> Stage<'a> {
+    /// パイプで区切られた1段分のトークンを解釈する
+    fn parse(tokens: &[Token<'a>]) -> Result<Self, ParseError> {
+        let mut stage = Stage {
+            args: [""; ARGS_SIZE],
+            argc: 0,
+            stdin: None,
+            stdout: None,
+            stderr_to_stdout: false,
+        };
+        let mut iter = tokens.iter();
+        while let Some(token) = iter.next() {
+            // リダイレクトの後には必ずファイル名が続く
+            let mut target = || match iter.next() {
+                Some(Token::Word(path)) => Ok(*path),
+                _ => Err(ParseError::MissingRedirectTarget),
+            };
+            match *token {
+                Token::Word(word) => {
+                    stage.args[stage.argc] = word;
+                    stage.argc += 1;
+                }
+                Token::RedirectIn => stage.stdin = Some(target()?),
+                Token::RedirectOut => stage.stdout = Some((target()?, false)),
+                Token::RedirectAppend => stage.stdout = Some((target()?, true)),
+                Token::StderrToStdout => stage.stderr_to_stdout = true,
+                Token::Pipe => unreachable!("stages are split at pipes"),
+            }
+        }
+        if stage.argc == 0 {
+            return Err(ParseError::EmptyCommand);
+        }
+        Ok(stage)
+    }
+
+    fn name(&self) -> &'a str {
+        self.args[0]
+    }
+
+    fn is_builtin(&self) -> bool {
+        BUILTINS.contains(&self.name())
+    }
+
+    /// 段の fd 0, 1, 2 にリダイレクトのファイルを開いて割り当てる
+    ///
+    /// 開いたファイルディスクリプタは opened に追加する
+    fn open_redirects(&self, stdio: &mut [usize; 3], opened: &mut FdList) -> Result<(), isize> {
+        if let Some(path) = self.stdin {
+            stdio[0] = opened.push(fs::open_with(path, O_RDONLY)?)?;
+        }
+        if let Some((path, append)) = self.stdout {
+            let mode = if append { O_APPEND } else { O_TRUNC };
+            stdio[1] = opened.push(fs::open_with(path, O_WRONLY | O_CREAT | mode)?)?;
+        }
+        if self.stderr_to_stdout {
+            stdio[2] = stdio[1];
+        }
+        Ok(())
+    }
+}
+
+/// パイプラインの実行後に閉じるファイルディスクリプタの一覧
+struct FdList {
+    fds: [usize; MAX_OPENED],
+    len: usize,
+}
+
+impl FdList {
+    fn new() -> Self {
+        Self {
+            fds: [0; MAX_OPENED],
+            len: 0,
+        }
+    }
+
+    /// fd を一覧に追加してそのまま返す
+    fn push(&mut self, fd: usize) -> Result<usize, isize> {
+        let Some(slot) = self.fds.get_mut(self.len) else {
+            let _ = fs::close(fd);
+            return Err(-1);
+        };
+        *slot = fd;
+        self.len += 1;
+        Ok(fd)
+    }
+}
+
+impl Drop for FdList {
+    fn drop(&mut self) {
+        for &fd in &self.fds[..self.len] {
+            let _ = fs::close(fd);
+        }
+    }
+}
 
 struct Console {
-    history: [[u8; BUF_SIZE]; HISTORY_SIZE],
-    history_len: [usize; HISTORY_SIZE],
-    count: usize,
+    history: History,
+    /// 追記用に開いた履歴ファイル. 開けなかった場合は履歴をメモリ上にだけ持つ
+    history_file: Option<File>,
     buf: [u8; BUF_SIZE],
+    /// 最後に実行したコマンドの終了コード
+    status: i32,
+    env: Env,
+    jobs: Jobs,
+    /// ジョブごとにプロセスグループを作り, 前面のジョブにコンソールを渡すか
+    ///
+    /// 対話的に動いているときだけ真にする
+    job_control: bool,
 }
 
 impl Console {
     fn new() -> Self {
         Self {
-            history: [[0u8; BUF_SIZE]; HISTORY_SIZE],
-            history_len: [0usize; HISTORY_SIZE],
-            count: 0,
+            history: History::new(),
+            history_file: None,
             buf: [0u8; BUF_SIZE],
+            status: 0,
+            env: Self::initial_env(),
+            jobs: Jobs::new(),
+            job_control: false,
+        }
+    }
+
+    /// シェルが受け取った環境変数を引き継ぎ, PATH が無ければ既定値を設定する
+    fn initial_env() -> Env {
+        let mut env = Env::new();
+        for (name, value) in userlib::env::vars() {
+            if env.set(name, value).and_then(|_| env.export(name)).is_err() {
+                eprintln!("sh: ignored environment variable {name}");
+            }
+        }
+        if env.get("PATH").is_none() {
+            let _ = env.set("PATH", DEFAULT_PATH);
+            let _ = env.export("PATH");
         }
+        env
     }
 
     /// 一行読み取り，読み取ったバイト数を返す
     fn read_line(&mut self) -> Result<usize, ReadLineError> {
-        // 入力を受け取る
-        let mut index = 0;
-        let mut hstry_idx = self.count;
+        // 表示している履歴の番号. 最新の次は編集中の空の行を表す
+        let mut hstry_idx = self.history.last_number() + 1;
+        let mut editor = LineEditor::new(&mut self.buf, Writer);
+        // 直前のキーが補完できなかった Tab か
+        let mut pending_list = false;
+        // 履歴の検索を終えたキー
+        let mut next_key = None;
         loop {
-            let c = read_byte().map_err(ReadLineError::Syscall)?;
-            match c {
+            let key = match next_key.take() {
+                Some(key) => key,
+                None => line::read_key().map_err(ReadLineError::Syscall)?,
+            };
+            if key != Key::Tab {
+                pending_list = false;
+            }
+            match key {
                 // 改行キーが押されたとき
-                b'\r' => {
+                Key::Enter => {
                     print!("\n");
                     break;
                 }
-                // Backspaceが押されたとき
-                0x7f | 0x8 => {
-                    // 1つ前の文字を消す
-                    if index > 0 {
-                        self.buf[index - 1] = 0;
-                        index -= 1;
-                        // カーソルを左に1つ動かす
-                        Writer::write_byte(0x8).map_err(ReadLineError::Syscall)?;
-                        // 消したい文字をスペースで置き換える
-                        Writer::write_byte(0x20).map_err(ReadLineError::Syscall)?;
-                        // カーソルを左に動かして入力できるようにする
-                        Writer::write_byte(0x8).map_err(ReadLineError::Syscall)?;
+                // 上向き矢印のとき
+                Key::Up => {
+                    if hstry_idx > self.history.first_number() {
+                        hstry_idx -= 1;
                     }
-                    continue;
+                    editor.set_line(self.history.get(hstry_idx).unwrap_or(&[]))?;
                 }
-                // エスケープ文字のとき
-                0x1b => {
-                    let c2 = read_byte().map_err(ReadLineError::Syscall)?;
-                    let c3 = read_byte().map_err(ReadLineError::Syscall)?;
-                    // 上向き矢印のとき
-                    if c2 == b'[' && c3 == b'A' {
-                        hstry_idx = hstry_idx.saturating_sub(1);
-                        self.show_history_inline(index, hstry_idx)?;
-                        index = self.history_len[hstry_idx];
+                // 下向き矢印のとき
+                Key::Down => {
+                    if hstry_idx <= self.history.last_number() {
+                        hstry_idx += 1;
                     }
-                    // 下向き矢印のとき
-                    if c2 == b'[' && c3 == b'B' {
-                        // countは現在実行されるコマンドが入る空白の場所
-                        // これよりも大きくなってほしくないため
-                        if hstry_idx < self.count {
-                            hstry_idx += 1;
+                    editor.set_line(self.history.get(hstry_idx).unwrap_or(&[]))?;
+                }
+                // Ctrl-C で編集中の行を取り消す
+                Key::Control(b'c') => {
+                    print!("^C\n");
+                    return Ok(0);
+                }
+                // Ctrl-R で履歴を検索する
+                Key::Control(b'r') => next_key = search_history(&self.history, &mut editor)?,
+                // 補完できないまま2回押されたときは候補を一覧表示する
+                Key::Tab => {
+                    let path = self.env.get("PATH").unwrap_or("");
+                    let mut candidates = Candidates::new();
+                    let completed =
+                        sh_complete::complete(&mut editor, &BUILTINS, path, &mut candidates)?;
+                    if completed || candidates.len() < 2 {
+                        pending_list = false;
+                    } else if pending_list {
+                        print!("\n");
+                        for name in candidates.iter() {
+                            print!("{}  ", sh_complete::display_name(name));
                         }
-                        self.show_history_inline(index, hstry_idx)?;
-                        index = self.history_len[hstry_idx];
-                        // println!("[debug] hstry_idx={}", hstry_idx);
+                        print!("\n> ");
+                        editor.redraw()?;
+                    } else {
+                        pending_list = true;
                     }
-                    continue;
                 }
-                _ => {}
+                key => editor.edit(key)?,
             }
-            // バッファに収まる場合のみ書き込む
-            if index < BUF_SIZE {
-                self.buf[index] = c;
-            } else {
-                return Err(ReadLineError::Overflow);
-            }
-            Writer::write_byte(c).map_err(ReadLineError::Syscall)?;
-            index += 1;
         }
 
-        Ok(index)
+        Ok(editor.len())
     }
 
-    /// バッファに入っているバイト数を受け取る
+    /// コマンドを1つ実行し, 終了コードを返す
     ///
-    /// 引数ごとに分割された文字列スライスのリストを返す
-    fn parse_input(&self, input_len: usize) -> Result<[&str; ARGS_SIZE], ParseError> {
-        // バッファを文字列に変換
-        let input = from_utf8(&self.buf[0..input_len])?;
-        if !input.is_ascii() {
-            return Err(ParseError::NonAsciiChar);
-        };
-
-        let mut items: [&str; ARGS_SIZE] = [""; ARGS_SIZE];
-        for (i, item) in input.split_whitespace().enumerate() {
-            items[i] = item;
-        }
-
-        Ok(items)
-    }
-
-    fn run_command(&self, cmd: [&str; ARGS_SIZE]) -> Result<(), ShellError> {
+    /// cmd はコマンド名を含む引数の列で, 空文字列の引数も1つの引数として扱う
+    /// 組み込みコマンド以外はプログラムを起動して終了を待つ
+    fn run_command(&mut self, cmd: &[&str]) -> Result<i32, ShellError> {
         let command = cmd[0];
         match command {
             "hello" => sh_cmd::builtin_hello(),
             "help" => sh_cmd::builtin_help(),
             "echo" => sh_cmd::builtin_echo(cmd),
-            "history" => sh_cmd::builtin_history(&self.history, &self.history_len),
+            "history" => sh_cmd::builtin_history(&self.history),
             "ohgiri" => sh_cmd::builtin_ohgiri(),
             "yield" => sh_cmd::builtin_yield().map_err(ShellError::Syscall)?,
             "exit" => sh_cmd::builtin_exit().map_err(ShellError::Syscall)?,
+            "set" => return Ok(sh_cmd::builtin_set(&mut self.env, cmd)),
+            "export" => return Ok(sh_cmd::builtin_export(&mut self.env, cmd)),
+            "unset" => return Ok(sh_cmd::builtin_unset(&mut self.env, cmd)),
+            "env" => return Ok(sh_cmd::builtin_env(&self.env)),
+            "true" => return Ok(0),
+            "false" => return Ok(1),
+            "jobs" => return Ok(sh_job::builtin_jobs(&self.jobs)),
+            "fg" => return Ok(sh_job::builtin_fg(&mut self.jobs, cmd, self.job_control)),
+            "bg" => return Ok(sh_job::builtin_bg(&mut self.jobs, cmd)),
             _ => {
-                let f = |sysret| {
-                    println!("{command}: command not found");
-                    ShellError::Syscall(sysret)
+                let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+                let pid = match self.spawn(cmd, stdio) {
+                    Ok(pid) => pid,
+                    Err(status) => return Ok(status),
                 };
-                userlib::spawn(command).map_err(f)?;
+                if self.job_control {
+                    let _ = userlib::setpgid(pid, pid);
+                }
+                let job = Job::new(&[Some(pid)], &[0], command).unwrap();
+                return Ok(sh_job::foreground(&mut self.jobs, job, self.job_control));
+            }
+        }
+        Ok(0)
+    }
+
+    /// PATH からコマンドを探し, export された変数を環境変数として渡して起動する
+    ///
+    /// 起動できない場合はメッセージを表示して, 代わりの終了コードを返す
+    fn spawn(&self, args: &[&str], stdio: [usize; 3]) -> Result<usize, i32> {
+        let name = args[0];
+        let mut path_buf = [0u8; PATH_BUF_SIZE];
+        let mut entries = [""; MAX_VARS];
+        let n = self.env.exported(&mut entries);
+        let Some(path) = self.find_command(name, &mut path_buf) else {
+            eprintln!("{name}: command not found");
+            return Err(STATUS_NOT_FOUND);
+        };
+        match userlib::spawn_process(path, args, &entries[..n], stdio) {
+            Ok(pid) => Ok(pid),
+            Err(e) if e == -ENOEXEC => {
+                eprintln!("{name}: exec format error");
+                Err(STATUS_NOT_EXECUTABLE)
+            }
+            Err(_) => {
+                eprintln!("{name}: command not found");
+                Err(STATUS_NOT_FOUND)
+            }
+        }
+    }
+
+    /// コマンドのファイルのパスを buf に書き込んで返す
+    ///
+    /// `/` を含む名前はそのままパスとして使い, それ以外は PATH のディレクトリから順に探す
+    fn find_command<'b>(&self, name: &str, buf: &'b mut [u8]) -> Option<&'b str> {
+        if name.contains('/') {
+            return concat(buf, &[name]).filter(|path| is_executable(path));
+        }
+        let path = self.env.get("PATH").unwrap_or("");
+        let dir = path
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .find(|dir| concat(buf, &[dir, "/", name]).is_some_and(is_executable))?;
+        concat(buf, &[dir, "/", name])
+    }
+
+    /// 組み込みコマンドを fd 0, 1, 2 を stdio に付け替えて実行する
+    fn run_builtin_with(&mut self, stage: &Stage, stdio: [usize; 3]) -> Result<i32, ShellError> {
+        const STD_FDS: [usize; 3] = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+        if stdio == STD_FDS {
+            return self.run_command(&stage.args[..stage.argc]);
+        }
+
+        let mut saved = [0usize; 3];
+        for (fd, saved) in STD_FDS.into_iter().zip(saved.iter_mut()) {
+            *saved = fs::dup(fd).map_err(ShellError::Syscall)?;
+        }
+        let mut result = Ok(0);
+        for (fd, new) in STD_FDS.into_iter().zip(stdio) {
+            if let Err(e) = fs::dup2(new, fd) {
+                result = Err(ShellError::Syscall(e));
+            }
+        }
+        if result.is_ok() {
+            result = self.run_command(&stage.args[..stage.argc]);
+        }
+        // シェルの標準入出力に戻す
+        for (fd, saved) in STD_FDS.into_iter().zip(saved) {
+            let _ = fs::dup2(saved, fd);
+            let _ = fs::close(saved);
+        }
+        result
+    }
+
+    /// `|` でつながったコマンドを全て起動し, 全ての終了を待つ
+    ///
+    /// 最後のコマンドの終了コードを返す
+    /// background が真の場合は起動したプログラムを command という名前のジョブにして待たずに戻る
+    /// 組み込みコマンドは background でもシェルの中で実行し終えてから戻る
+    fn run_pipeline(
+        &mut self,
+        tokens: &[Token],
+        command: &str,
+        background: bool,
+    ) -> Result<i32, ShellError> {
+        // 構文エラーがあれば何も実行しない
+        let mut count = 0;
+        for tokens in tokens.split(|t| *t == Token::Pipe) {
+            Stage::parse(tokens)?;
+            count += 1;
+        }
+        if count > MAX_PIPELINE {
+            return Err(ParseError::TooManyCommands.into());
+        }
+
+        // 前の段の標準出力と次の段の標準入力をパイプでつなぐ
+        let mut opened = FdList::new();
+        let mut stdio = [[STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO]; MAX_PIPELINE];
+        for i in 1..count {
+            let (r, w) = fs::pipe().map_err(ShellError::Syscall)?;
+            stdio[i - 1][1] = opened.push(w).map_err(ShellError::Syscall)?;
+            stdio[i][0] = opened.push(r).map_err(ShellError::Syscall)?;
+        }
+
+        // 先にプログラムを全て起動してから組み込みコマンドを実行する
+        // 組み込みコマンドの出力をパイプの先のプログラムが読めるようにするため
+        let mut pids = [None; MAX_PIPELINE];
+        let mut statuses = [0; MAX_PIPELINE];
+        let mut ready = [false; MAX_PIPELINE];
+        for (i, tokens) in tokens.split(|t| *t == Token::Pipe).enumerate() {
+            let stage = Stage::parse(tokens)?;
+            if stage.open_redirects(&mut stdio[i], &mut opened).is_err() {
+                eprintln!("sh: cannot open redirect file");
+                statuses[i] = 1;
+                continue;
+            }
+            if stage.is_builtin() {
+                ready[i] = true;
+                continue;
+            }
+            match self.spawn(&stage.args[..stage.argc], stdio[i]) {
+                Ok(pid) => {
+                    // 全ての段を最初のプログラムのプロセスグループに入れる
+                    if self.job_control {
+                        let pgid = pids.iter().flatten().next().copied().unwrap_or(pid);
+                        let _ = userlib::setpgid(pid, pgid);
+                    }
+                    pids[i] = Some(pid);
+                }
+                Err(status) => statuses[i] = status,
+            }
+        }
+        for (i, tokens) in tokens.split(|t| *t == Token::Pipe).enumerate() {
+            if ready[i] {
+                let stage = Stage::parse(tokens)?;
+                // 起動済みのプログラムを待つために, エラーでも中断しない
+                statuses[i] = self.run_builtin_with(&stage, stdio[i]).unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    1
+                });
+            }
+        }
+
+        // シェルが持っているパイプの端を閉じて, 読み出し側が終端を検出できるようにする
+        drop(opened);
+        let Some(job) = Job::new(&pids[..count], &statuses[..count], command) else {
+            return Ok(statuses[count - 1]);
+        };
+        if !background {
+            return Ok(sh_job::foreground(&mut self.jobs, job, self.job_control));
+        }
+        // 一覧に加えられない場合は前面で実行する
+        if self.jobs.is_full() {
+            eprintln!("sh: too many jobs (max {})", sh_job::MAX_JOBS);
+            return Ok(sh_job::foreground(&mut self.jobs, job, self.job_control));
+        }
+        let pgid = job.pgid();
+        let number = self.jobs.add(job).unwrap();
+        if self.job_control {
+            println!("[{number}] {pgid}");
+        }
+        Ok(0)
+    }
+
+    /// 履歴ファイルを読み込み, 以後のコマンドを追記できるようにする
+    ///
+    /// $HOME/.nex_history, /tmp/.sh_history の順に試す
+    /// どちらも開けない場合は履歴をメモリ上にだけ持つ
+    fn load_history(&mut self) {
+        let mut path_buf = [0u8; PATH_BUF_SIZE];
+        let home_path = self
+            .env
+            .get("HOME")
+            .and_then(|home| concat(&mut path_buf, &[home, "/", HISTORY_FILE_NAME]));
+        for path in home_path.into_iter().chain([FALLBACK_HISTORY_PATH]) {
+            if self.open_history(path).is_ok() {
+                return;
             }
         }
+    }
+
+    /// path の履歴ファイルを読み込み, 追記用に開いたままにする
+    fn open_history(&mut self, path: &str) -> Result<(), isize> {
+        let file = File::open_with(path, O_RDWR | O_CREAT | O_APPEND)?;
+        // 途中で終わっている行に次のコマンドがつながらないようにする
+        if self.history.load(&file)? {
+            file.write_all(b"\n")?;
+        }
+        self.history_file = Some(file);
         Ok(())
     }
 
-    #[inline]
+    /// 履歴に追加し, 履歴ファイルがあれば追記する
     fn save_history(&mut self, input_len: usize) {
-        let index = self.count % HISTORY_SIZE;
-        self.history[index][..input_len].copy_from_slice(&self.buf[..input_len]);
-        self.history_len[index] = input_len;
-    }
-
-    #[inline]
-    fn show_history_inline(&mut self, index: usize, hstry_idx: usize) -> Result<(), ReadLineError> {
-        for i in 0..index {
-            // コンソールのクリア
-            Writer::write_byte(0x8).map_err(ReadLineError::Syscall)?;
-            Writer::write_byte(0x20).map_err(ReadLineError::Syscall)?;
-            Writer::write_byte(0x8).map_err(ReadLineError::Syscall)?;
-            // バッファのクリア
-            self.buf[i] = 0;
-        }
-        for i in 0..self.history_len[hstry_idx] {
-            // コンソールの出力
-            Writer::write_byte(self.history[hstry_idx][i]).map_err(ReadLineError::Syscall)?;
-            // バッファの出力
-            self.buf[i] = self.history[hstry_idx][i];
+        self.history.push(&self.buf[..input_len]);
+        let Some(file) = &self.history_file else {
+            return;
+        };
+        // 1回の書き込みで行全体を追記する
+        let mut line = [0u8; BUF_SIZE + 1];
+        line[..input_len].copy_from_slice(&self.buf[..input_len]);
+        line[input_len] = b'\n';
+        if file.write_all(&line[..=input_len]).is_err() {
+            // 書き込めなくなった場合はメモリ上の履歴だけを使う
+            self.history_file = None;
         }
-        Ok(())
+    }
+
+    /// buf の `!!` や `!n` を履歴で置き換え, 置き換えた後のバイト数を返す
+    ///
+    /// 置き換えた場合は実行する前に表示する
+    fn expand_history(&mut self, input_len: usize) -> Result<usize, HistoryError> {
+        let mut expanded = [0u8; BUF_SIZE];
+        let len = self.history.expand(&self.buf[..input_len], &mut expanded)?;
+        if expanded[..len] != self.buf[..input_len] {
+            self.buf[..len].copy_from_slice(&expanded[..len]);
+            println!("{}", from_utf8(&self.buf[..len]).unwrap_or(""));
+        }
+        Ok(len)
     }
 
     fn prompt(&mut self) -> Result<(), ShellError> {
+        self.jobs.notify();
         print!("> ");
 
         let input_len = self.read_line()?;
@@ -171,14 +818,153 @@ impl Console {
         if input_len == 0 {
             return Ok(());
         }
+        let input_len = self.expand_history(input_len)?;
 
-        let cmd = self.parse_input(input_len)?;
-        self.run_command(cmd)?;
+        // 実行中に self を変更できるように行を写しておく
+        let mut line = [0u8; BUF_SIZE];
+        line[..input_len].copy_from_slice(&self.buf[..input_len]);
+        let input = from_utf8(&line[..input_len]).map_err(ParseError::from)?;
+        if !input.is_ascii() {
+            return Err(ParseError::NonAsciiChar.into());
+        }
+        sh_script::execute(input, self)?;
 
         self.save_history(input_len);
-        self.count += 1;
         Ok(())
     }
+
+    /// スクリプトファイルを読み込んで実行し, 終了コードを返す
+    fn run_file(&mut self, path: &str) -> i32 {
+        let mut buf = [0u8; SCRIPT_SIZE];
+        let result = File::open(path).and_then(|file| {
+            let n = file.read_full(&mut buf)?;
+            // 収まりきらない場合は途中までを実行しないようにする
+            let mut rest = [0u8; 1];
+            if n == buf.len() && file.read(&mut rest)? > 0 {
+                return Err(-1);
+            }
+            Ok(n)
+        });
+        let Ok(n) = result else {
+            eprintln!("sh: {path}: cannot read script (max {} bytes)", SCRIPT_SIZE);
+            return STATUS_NOT_FOUND;
+        };
+        let Ok(src) = from_utf8(&buf[..n]) else {
+            eprintln!("sh: {path}: invalid utf-8");
+            return STATUS_SYNTAX_ERROR;
+        };
+        match sh_script::execute(src, self) {
+            Ok(()) => self.status,
+            Err(e) => {
+                eprintln!("sh: {path}: {e}");
+                STATUS_SYNTAX_ERROR
+            }
+        }
+    }
+}
+
+impl Executor for Console {
+    fn run(&mut self, pipeline: &str, background: bool) -> Result<i32, ShellError> {
+        let mut words = [0u8; WORD_BUF_SIZE];
+        let tokens = tokenize(pipeline, &mut words, self)?;
+        // 展開した結果が空になった場合は何もしない
+        if tokens.len == 0 {
+            return Ok(0);
+        }
+        self.run_pipeline(tokens.as_slice(), pipeline, background)
+    }
+
+    fn set_status(&mut self, status: i32) {
+        self.status = status;
+    }
+
+    fn set_var(&mut self, name: &str, value: &str) -> Result<(), ShellError> {
+        self.env.set(name, value).map_err(ShellError::Env)
+    }
+}
+
+/// Ctrl-R による履歴の逐次検索
+///
+/// 見つかった履歴を行に入れ, 検索を終えたキーを返す
+/// Ctrl-G で取り消した場合や見つからなかった場合は元の行に戻す
+fn search_history(
+    history: &History,
+    editor: &mut LineEditor<Writer>,
+) -> Result<Option<Key>, ReadLineError> {
+    let mut saved = [0u8; BUF_SIZE];
+    let saved_len = editor.len();
+    saved[..saved_len].copy_from_slice(editor.line());
+    editor.set_line(&[])?;
+
+    let mut search = Search::new();
+    let mut width = 0;
+    let key = loop {
+        width = show_search(&search, history, width);
+        match line::read_key().map_err(ReadLineError::Syscall)? {
+            Key::Char(c) => search.push(c, history),
+            Key::Backspace => search.pop(history),
+            Key::Control(b'r') => search.next(history),
+            Key::Control(b'g') => break None,
+            key => break Some(key),
+        }
+    };
+
+    // 検索の表示を消してプロンプトを出し直す
+    print!("\r{:width$}\r> ", "");
+    match search.found().and_then(|n| history.get(n)) {
+        Some(line) if key.is_some() => editor.set_line(line)?,
+        _ => editor.set_line(&saved[..saved_len])?,
+    }
+    Ok(key)
+}
+
+/// 検索の状態を表示し, その幅を返す
+///
+/// 前の表示 prev_width より短くなった部分は空白で消す
+fn show_search(search: &Search, history: &History, prev_width: usize) -> usize {
+    let label = if search.failed() {
+        "failed reverse-i-search"
+    } else {
+        "reverse-i-search"
+    };
+    let pattern = from_utf8(search.pattern()).unwrap_or("");
+    let found = search
+        .found()
+        .and_then(|n| history.get(n))
+        .and_then(|line| from_utf8(line).ok())
+        .unwrap_or("");
+    print!("\r({label})`{pattern}': {found}");
+    let width = label.len() + pattern.len() + found.len() + 6;
+    if width < prev_width {
+        print!("{:1$}", "", prev_width - width);
+    }
+    width.max(prev_width)
+}
+
+/// 文字列を buf の中で連結する
+fn concat<'a>(buf: &'a mut [u8], parts: &[&str]) -> Option<&'a str> {
+    let mut len = 0;
+    for part in parts {
+        let end = len + part.len();
+        buf.get_mut(len..end)?.copy_from_slice(part.as_bytes());
+        len = end;
+    }
+    from_utf8(&buf[..len]).ok()
+}
+
+/// 起動できる通常のファイルか
+fn is_executable(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file())
+}
+
+impl Variables for Console {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.env.get(name)
+    }
+
+    fn status(&self) -> i32 {
+        self.status
+    }
 }
 
 user_main!(main);
@@ -188,10 +974,28 @@ fn main() {
 }
 
 fn shell() {
+    // テストから子プロセスとして実行された場合は, 指定された動作だけをする
+    #[cfg(feature = "shell-test")]
+    if let Some(name) = userlib::args()
+        .nth(1)
+        .and_then(|arg| arg.strip_prefix("--test-child="))
+    {
+        test_child(name);
+    }
+
+    // 引数にファイルが渡された場合はスクリプトとして実行して終了する
+    if let Some(path) = userlib::args().nth(1) {
+        let mut con = Console::new();
+        let status = con.run_file(path);
+        let _ = userlib::exit_with_code(status);
+    }
+
     #[cfg(feature = "shell-test")]
     test_runner();
 
     let mut con = Console::new();
+    con.job_control = true;
+    con.load_history();
 
     loop {
         if let Err(e) = con.prompt() {
@@ -211,6 +1015,10 @@ enum ShellError {
     ReadLine(#[from] ReadLineError),
     #[error("Parse Error: {0}")]
     Parse(#[from] ParseError),
+    #[error("History Error: {0}")]
+    History(#[from] HistoryError),
+    #[error("Variable Error: {0}")]
+    Env(EnvError),
     #[error("Syscall Error: {0}")]
     Syscall(isize),
 }
@@ -233,11 +1041,38 @@ impl Display for ReadLineError {
 
 impl error::Error for ReadLineError {}
 
+impl From<EditError> for ReadLineError {
+    fn from(err: EditError) -> ReadLineError {
+        match err {
+            EditError::Overflow => ReadLineError::Overflow,
+            EditError::Terminal(code) => ReadLineError::Syscall(code),
+        }
+    }
+}
+
 /// パースに関するエラー
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 enum ParseError {
     Utf8Error(Utf8Error),
     NonAsciiChar,
+    TooManyTokens,
+    /// 展開した単語がバッファに収まらない
+    TooLong,
+    /// 閉じられていないクォート
+    UnterminatedQuote,
+    /// `${` の後に正しい変数名と `}` が無い
+    BadSubstitution,
+    TooManyCommands,
+    /// パイプの前後などにコマンドが無い
+    EmptyCommand,
+    /// リダイレクトの後にファイル名が無い
+    MissingRedirectTarget,
+    /// 予期しない位置にある演算子やキーワード
+    Unexpected(&'static str),
+    /// if や while などが閉じられないまま終わった
+    UnexpectedEof,
+    /// for の後に正しい変数名が無い
+    BadForVariable,
 }
 
 impl From<Utf8Error> for ParseError {
@@ -251,6 +1086,22 @@ impl fmt::Display for ParseError {
         match *self {
             ParseError::Utf8Error(e) => write!(f, "{e}"),
             ParseError::NonAsciiChar => write!(f, "non ascii character is not supported"),
+            ParseError::TooManyTokens => write!(f, "too many arguments (max {})", ARGS_SIZE),
+            ParseError::TooLong => write!(f, "command line too long (max {})", WORD_BUF_SIZE),
+            ParseError::UnterminatedQuote => write!(f, "syntax error: unterminated quote"),
+            ParseError::BadSubstitution => write!(f, "bad substitution"),
+            ParseError::TooManyCommands => {
+                write!(f, "too many commands in pipeline (max {})", MAX_PIPELINE)
+            }
+            ParseError::EmptyCommand => write!(f, "syntax error: missing command"),
+            ParseError::MissingRedirectTarget => {
+                write!(f, "syntax error: missing file name after redirection")
+            }
+            ParseError::Unexpected(token) => {
+                write!(f, "syntax error near unexpected token `{token}'")
+            }
+            ParseError::UnexpectedEof => write!(f, "syntax error: unexpected end of input"),
+            ParseError::BadForVariable => write!(f, "syntax error: bad for loop variable"),
         }
     }
 }
@@ -261,6 +1112,33 @@ impl error::Error for ParseError {}
 // テスト
 //
 
+/// テストが子プロセスとして実行する動作. 結果はテストが終了コードを wait で確かめる
+#[cfg(feature = "shell-test")]
+fn test_child(name: &str) {
+    use syscall::{USER_STACK_MAX, USER_STACK_TOP};
+    use userlib::mman::PAGE_SIZE;
+
+    match name {
+        // スタックを1ページずつ下へ触っていく. スタックの下のページで SIGSEGV を受けて終了する
+        "stack-overflow" => {
+            let local = 0u8;
+            let mut addr = &raw const local as usize & !(PAGE_SIZE - 1);
+            while addr > USER_STACK_TOP - USER_STACK_MAX - 2 * PAGE_SIZE {
+                addr -= PAGE_SIZE;
+                unsafe { core::ptr::read_volatile(addr as *const u8) };
+            }
+        }
+        // 不正な命令や ebreak を実行すると, それぞれのシグナルを受けて終了する
+        "illegal-instruction" => unsafe { core::arch::asm!("unimp") },
+        "breakpoint" => unsafe { core::arch::asm!("ebreak") },
+        _ => {
+            eprintln!("unknown test child: {name}");
+        }
+    }
+    // ここまで来た場合はテストの失敗
+    let _ = userlib::exit_with_code(1);
+}
+
 #[cfg(feature = "shell-test")]
 pub fn test_runner() {
     println!("Starting Test...");
@@ -268,63 +1146,1611 @@ pub fn test_runner() {
     test_many_echo();
     test_history();
     test_many_history();
+    test_devfs();
+    test_procfs();
+    test_metadata();
+    test_pipe();
+    test_pipe_between_processes();
+    test_tokenize();
+    test_open_flags();
+    test_wait_exit_code();
+    test_exec_format_error();
+    test_elf_segment_too_large();
+    test_elf_segment_out_of_range();
+    test_elf_no_load_segment();
+    test_elf_bad_entry();
+    test_elf_bad_tls();
+    test_pipeline();
+    test_quotes();
+    test_escapes();
+    test_expansion();
+    test_env();
+    test_path_search();
+    test_exported_env();
+    test_line_editor();
+    test_line_editor_kill();
+    test_complete();
+    test_history_wraparound();
+    test_history_expansion();
+    test_history_search();
+    test_history_file();
+    test_command_list();
+    test_control_flow();
+    test_script_syntax_error();
+    test_script_file();
+    test_signals();
+    test_signal_handlers();
+    test_fault_signals();
+    test_thread_local();
+    test_user_stack();
+    test_heap();
+    test_mmap();
+    test_demand_paging();
+    test_jobs();
     userlib::exit_process();
 }
 
 #[cfg(feature = "shell-test")]
 fn test_echo() {
     println!("[test] test_echo:");
-    let mut cmd = [""; ARGS_SIZE];
-    cmd[0] = "echo";
-    cmd[1] = "foo";
-    let con = Console::new();
-    con.run_command(cmd).unwrap();
+    let cmd = ["echo", "foo"];
+    let mut con = Console::new();
+    con.run_command(&cmd).unwrap();
     println!("[OK]");
 }
 
 #[cfg(feature = "shell-test")]
 fn test_many_echo() {
     println!("[test] test_echo:");
-    let mut cmd = [""; ARGS_SIZE];
-    cmd[0] = "echo";
-    cmd[1] = "foo";
-    cmd[2] = "bar";
-    cmd[3] = "hoge";
-    cmd[4] = "piyo";
-    let con = Console::new();
-    con.run_command(cmd).unwrap();
+    let cmd = ["echo", "foo", "bar", "hoge", "piyo"];
+    let mut con = Console::new();
+    con.run_command(&cmd).unwrap();
     println!("[OK]");
 }
 
 #[cfg(feature = "shell-test")]
 fn test_history() {
     println!("[test] test_history:");
-    let mut cmd = [""; ARGS_SIZE];
-    cmd[0] = "history";
+    let cmd = ["history"];
     let mut con = Console::new();
-    let dummy = "dummy";
-    for (i, b) in dummy.bytes().enumerate() {
-        con.history[0][i] = b;
-    }
-    con.history_len[0] = dummy.len();
-    con.run_command(cmd).unwrap();
+    con.history.push(b"dummy");
+    con.run_command(&cmd).unwrap();
     println!("[OK]");
 }
 
 #[cfg(feature = "shell-test")]
 fn test_many_history() {
     println!("[test] test_many_history:");
-    let mut cmd = [""; ARGS_SIZE];
-    cmd[0] = "history";
+    let cmd = ["history"];
 
     let mut con = Console::new();
-    let dummy = "dummy";
-    for i in 0..5 {
-        for (j, b) in dummy.bytes().enumerate() {
-            con.history[i][j] = b;
-        }
-        con.history_len[i] = dummy.len();
+    for _ in 0..5 {
+        con.history.push(b"dummy");
     }
-    con.run_command(cmd).unwrap();
+    con.run_command(&cmd).unwrap();
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_history_wraparound() {
+    println!("[test] test_history_wraparound:");
+    let mut history = History::new();
+    let mut line = [0u8; 8];
+    // 保存できる数を超えて追加すると古いものから上書きされる
+    for i in 1..=HISTORY_SIZE + 10 {
+        let s = concat(&mut line, &["cmd", if i % 2 == 0 { "even" } else { "odd" }]).unwrap();
+        let len = s.len();
+        history.push(&line[..len]);
+    }
+    assert_eq!(history.last_number(), HISTORY_SIZE + 10);
+    assert_eq!(history.first_number(), 11);
+    assert_eq!(history.get(10), None);
+    assert_eq!(history.get(11), Some(&b"cmdodd"[..]));
+    assert_eq!(history.get(HISTORY_SIZE + 10), Some(&b"cmdeven"[..]));
+    assert_eq!(history.get(HISTORY_SIZE + 11), None);
+    assert_eq!(history.iter().count(), HISTORY_SIZE);
+    assert_eq!(history.iter().next().map(|(n, _)| n), Some(11));
+    // 空の行は追加しない
+    history.push(b"");
+    assert_eq!(history.last_number(), HISTORY_SIZE + 10);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_history_expansion() {
+    println!("[test] test_history_expansion:");
+    let mut history = History::new();
+    let mut out = [0u8; BUF_SIZE];
+    assert_eq!(
+        history.expand(b"!!", &mut out),
+        Err(HistoryError::EventNotFound)
+    );
+
+    history.push(b"echo one");
+    history.push(b"echo two");
+    let mut expand = |input: &[u8], expected: &[u8]| {
+        let len = history.expand(input, &mut out).unwrap();
+        assert_eq!(&out[..len], expected);
+    };
+    expand(b"!!", b"echo two");
+    expand(b"!1 | cat", b"echo one | cat");
+    expand(b"echo \"!2\"", b"echo \"echo two\"");
+    // 履歴の指定でない ! やクォートされた ! はそのまま
+    expand(b"echo hi!", b"echo hi!");
+    expand(b"echo '!!' \\!!", b"echo '!!' \\!!");
+    assert_eq!(
+        history.expand(b"!3", &mut out),
+        Err(HistoryError::EventNotFound)
+    );
+    let mut small = [0u8; 4];
+    assert_eq!(
+        history.expand(b"!!", &mut small),
+        Err(HistoryError::TooLong)
+    );
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_history_search() {
+    println!("[test] test_history_search:");
+    let mut history = History::new();
+    history.push(b"cat /proc/meminfo");
+    history.push(b"echo hello");
+    history.push(b"cat /proc/uptime");
+
+    let mut search = Search::new();
+    search.push(b'c', &history);
+    assert_eq!(search.found(), Some(3));
+    search.push(b'a', &history);
+    assert_eq!(search.found(), Some(3));
+    // Ctrl-R でさらに古いものを探す
+    search.next(&history);
+    assert_eq!(search.found(), Some(1));
+    search.next(&history);
+    assert_eq!(search.found(), Some(1));
+    assert!(search.failed());
+
+    // 見つからない文字を加えても前の結果は残る
+    search.pop(&history);
+    search.pop(&history);
+    search.push(b'h', &history);
+    assert_eq!(search.found(), Some(2));
+    search.push(b'x', &history);
+    assert!(search.failed());
+    assert_eq!(search.found(), Some(2));
+    search.pop(&history);
+    assert!(!search.failed());
+    assert_eq!(search.found(), Some(2));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_history_file() {
+    println!("[test] test_history_file:");
+    let path = "/tmp/test_history";
+    let file = File::create(path).unwrap();
+    file.write_all(b"echo one\n\necho two").unwrap();
+    drop(file);
+
+    let mut con = Console::new();
+    con.open_history(path).unwrap();
+    // 空行は読み飛ばし, 改行で終わらない最後の行も読む
+    assert_eq!(con.history.last_number(), 2);
+    assert_eq!(con.history.get(2), Some(&b"echo two"[..]));
+
+    // 実行したコマンドを追記する
+    con.buf[..8].copy_from_slice(b"echo new");
+    con.save_history(8);
+    assert_eq!(con.history.last_number(), 3);
+
+    let mut reloaded = History::new();
+    let unterminated = reloaded.load(&File::open(path).unwrap()).unwrap();
+    assert!(!unterminated);
+    assert_eq!(reloaded.last_number(), 3);
+    assert_eq!(reloaded.get(2), Some(&b"echo two"[..]));
+    assert_eq!(reloaded.get(3), Some(&b"echo new"[..]));
+
+    // 書き込めない場所ではメモリ上の履歴だけを使う
+    let mut con = Console::new();
+    assert!(con.open_history("/bin/.nex_history").is_err());
+    assert!(con.history_file.is_none());
+    con.buf[..8].copy_from_slice(b"echo mem");
+    con.save_history(8);
+    assert_eq!(con.history.last_number(), 1);
+    println!("[OK]");
+}
+
+/// 実行したコマンドを記録するだけの Executor
+#[cfg(feature = "shell-test")]
+struct TestExecutor {
+    env: Env,
+    status: i32,
+    /// `more` が成功する残りの回数
+    remaining: usize,
+    log: [u8; 512],
+    log_len: usize,
+}
+
+#[cfg(feature = "shell-test")]
+impl TestExecutor {
+    fn new() -> Self {
+        Self {
+            env: Env::new(),
+            status: 0,
+            remaining: 0,
+            log: [0u8; 512],
+            log_len: 0,
+        }
+    }
+
+    /// src を実行し, 展開した各コマンドを1行ずつ並べたものを返す
+    fn execute(&mut self, src: &str) -> Result<&str, ShellError> {
+        self.log_len = 0;
+        sh_script::execute(src, self)?;
+        Ok(from_utf8(&self.log[..self.log_len]).unwrap())
+    }
+}
+
+#[cfg(feature = "shell-test")]
+impl Variables for TestExecutor {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.env.get(name)
+    }
+
+    fn status(&self) -> i32 {
+        self.status
+    }
+}
+
+#[cfg(feature = "shell-test")]
+impl Executor for TestExecutor {
+    fn run(&mut self, pipeline: &str, background: bool) -> Result<i32, ShellError> {
+        let mut words = [0u8; WORD_BUF_SIZE];
+        let tokens = tokenize(pipeline, &mut words, self)?;
+        let mut status = 0;
+        for (i, token) in tokens.as_slice().iter().enumerate() {
+            let word = match *token {
+                Token::Word(word) => word,
+                Token::Pipe => "|",
+                _ => "?",
+            };
+            if i == 0 {
+                status = match word {
+                    "false" => 1,
+                    "more" if self.remaining == 0 => 1,
+                    "more" => {
+                        self.remaining -= 1;
+                        0
+                    }
+                    _ => 0,
+                };
+            } else {
+                self.log[self.log_len] = b' ';
+                self.log_len += 1;
+            }
+            self.log[self.log_len..self.log_len + word.len()].copy_from_slice(word.as_bytes());
+            self.log_len += word.len();
+        }
+        if background {
+            self.log[self.log_len..self.log_len + 2].copy_from_slice(b" &");
+            self.log_len += 2;
+        }
+        self.log[self.log_len] = b'\n';
+        self.log_len += 1;
+        Ok(status)
+    }
+
+    fn set_status(&mut self, status: i32) {
+        self.status = status;
+    }
+
+    fn set_var(&mut self, name: &str, value: &str) -> Result<(), ShellError> {
+        self.env.set(name, value).map_err(ShellError::Env)
+    }
+}
+
+#[cfg(feature = "shell-test")]
+fn test_command_list() {
+    println!("[test] test_command_list:");
+    let mut exec = TestExecutor::new();
+    assert_eq!(exec.execute("echo a; echo b").unwrap(), "echo a\necho b\n");
+    assert_eq!(exec.execute("echo a;echo b;").unwrap(), "echo a\necho b\n");
+    assert_eq!(
+        exec.execute("true && echo yes || echo no").unwrap(),
+        "true\necho yes\n"
+    );
+    assert_eq!(
+        exec.execute("false && echo yes || echo no").unwrap(),
+        "false\necho no\n"
+    );
+    assert_eq!(exec.status, 0);
+    assert_eq!(exec.execute("false || false").unwrap(), "false\nfalse\n");
+    assert_eq!(exec.status, 1);
+    // $? は直前のコマンドの終了コード
+    assert_eq!(exec.execute("false; echo $?").unwrap(), "false\necho 1\n");
+    // パイプやリダイレクト, クォートの中の演算子は区切りにならない
+    assert_eq!(
+        exec.execute("echo 'a;b' \"&&\" a\\;b | cat 2>&1").unwrap(),
+        "echo a;b && a;b | cat ?\n"
+    );
+    // コメントと空行
+    assert_eq!(
+        exec.execute("# comment\n\necho a # trailing\n").unwrap(),
+        "echo a\n"
+    );
+    // & は背後で実行し, それ自体が区切りになる
+    assert_eq!(
+        exec.execute("echo a & echo b").unwrap(),
+        "echo a &\necho b\n"
+    );
+    assert_eq!(
+        exec.execute("echo a | cat 2>&1 &\n").unwrap(),
+        "echo a | cat ? &\n"
+    );
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_control_flow() {
+    println!("[test] test_control_flow:");
+    let mut exec = TestExecutor::new();
+    assert_eq!(
+        exec.execute("if true; then echo yes; else echo no; fi")
+            .unwrap(),
+        "true\necho yes\n"
+    );
+    assert_eq!(
+        exec.execute("if false; then echo 1; elif true; then echo 2; else echo 3; fi")
+            .unwrap(),
+        "false\ntrue\necho 2\n"
+    );
+    assert_eq!(
+        exec.execute("if false; then echo 1; elif false; then echo 2; else echo 3; fi")
+            .unwrap(),
+        "false\nfalse\necho 3\n"
+    );
+    // どの枝も実行しなければ終了コードは 0
+    assert_eq!(
+        exec.execute("if false; then echo 1; fi").unwrap(),
+        "false\n"
+    );
+    assert_eq!(exec.status, 0);
+
+    exec.remaining = 2;
+    assert_eq!(
+        exec.execute("while more; do echo loop; done").unwrap(),
+        "more\necho loop\nmore\necho loop\nmore\n"
+    );
+    assert_eq!(exec.status, 0);
+    assert_eq!(
+        exec.execute("until true; do echo never; done").unwrap(),
+        "true\n"
+    );
+
+    let _ = exec.env.set("V", "v");
+    assert_eq!(
+        exec.execute("for x in a 'b c' $V; do echo $x; done")
+            .unwrap(),
+        "echo a\necho b c\necho v\n"
+    );
+    assert_eq!(exec.env.get("x"), Some("v"));
+    assert_eq!(exec.execute("for x in; do echo $x; done").unwrap(), "");
+
+    // 入れ子と複数行
+    let script = "
+for x in 1 2
+do
+    if false
+    then
+        echo no
+    else
+        echo $x && echo ok
+    fi
+done
+echo end
+";
+    assert_eq!(
+        exec.execute(script).unwrap(),
+        "false\necho 1\necho ok\nfalse\necho 2\necho ok\necho end\n"
+    );
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_script_syntax_error() {
+    println!("[test] test_script_syntax_error:");
+    let mut exec = TestExecutor::new();
+    let mut check = |src: &str, expected: ParseError| {
+        match exec.execute(src) {
+            Err(ShellError::Parse(e)) => assert_eq!(e, expected),
+            _ => panic!("expected syntax error: {src}"),
+        }
+        // 構文エラーがあれば何も実行しない
+        assert_eq!(exec.log_len, 0);
+    };
+    check("echo a; if true; then echo b", ParseError::UnexpectedEof);
+    check("echo a; fi", ParseError::Unexpected("fi"));
+    check("while true; do done", ParseError::Unexpected("done"));
+    check(
+        "if true; then echo a; fi echo",
+        ParseError::Unexpected("word"),
+    );
+    check("echo a && ; echo b", ParseError::Unexpected(";"));
+    check("; echo a", ParseError::Unexpected(";"));
+    check("echo a && echo b &", ParseError::Unexpected("&"));
+    check("if true; then echo a; fi &", ParseError::Unexpected("&"));
+    check("echo a & ; echo b", ParseError::Unexpected(";"));
+    check("for 1x in a; do echo; done", ParseError::BadForVariable);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_script_file() {
+    println!("[test] test_script_file:");
+    let script = "# テスト用のスクリプト
+echo start
+for x in a b; do
+    echo $x
+done
+false
+";
+    File::create("/tmp/test_script.sh")
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+
+    let mut con = Console::new();
+    let status = con
+        .run("sh /tmp/test_script.sh > /tmp/script_out", false)
+        .unwrap();
+    assert_eq!(status, 1);
+    let mut buf = [0u8; 64];
+    let n = File::open("/tmp/script_out")
+        .unwrap()
+        .read_full(&mut buf)
+        .unwrap();
+    assert_eq!(&buf[..n], b"start\na\nb\n");
+
+    // 構文エラーのあるスクリプトは実行しない
+    File::create("/tmp/test_script.sh")
+        .unwrap()
+        .write_all(b"echo start\nif true\n")
+        .unwrap();
+    let status = con
+        .run("sh /tmp/test_script.sh > /tmp/script_out", false)
+        .unwrap();
+    assert_eq!(status, STATUS_SYNTAX_ERROR);
+    let n = File::open("/tmp/script_out")
+        .unwrap()
+        .read_full(&mut buf)
+        .unwrap();
+    assert_eq!(n, 0);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_signals() {
+    use syscall::{SIGCONT, SIGSTOP, SIGTERM, WNOHANG, WUNTRACED};
+    use userlib::WaitStatus;
+
+    println!("[test] test_signals:");
+    let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    // 実行を始める前に届いた SIGTERM で終了する
+    let pid = userlib::spawn_process("grep", &["grep"], &[], stdio).unwrap();
+    userlib::kill(pid as isize, SIGTERM).unwrap();
+    assert_eq!(userlib::wait(pid).unwrap(), 128 + SIGTERM as i32);
+    assert!(userlib::kill(pid as isize, SIGTERM).is_err());
+
+    // 子プロセスは親のプロセスグループを引き継ぐ
+    let pid = userlib::spawn_process("grep", &["grep"], &[], stdio).unwrap();
+    assert_eq!(userlib::getpgid(pid), userlib::getpgid(0));
+    userlib::setpgid(pid, 0).unwrap();
+    assert_eq!(userlib::getpgid(pid).unwrap(), pid);
+
+    // グループに送った停止は一度だけ報告され, SIGCONT で再開する
+    userlib::kill(-(pid as isize), SIGSTOP).unwrap();
+    let options = WNOHANG | WUNTRACED;
+    assert_eq!(
+        userlib::wait_with(pid, options).unwrap(),
+        Some(WaitStatus::Stopped(SIGSTOP))
+    );
+    assert_eq!(userlib::wait_with(pid, options).unwrap(), None);
+    userlib::kill(pid as isize, SIGCONT).unwrap();
+    assert_eq!(
+        userlib::wait_with(pid, 0).unwrap(),
+        Some(WaitStatus::Exited(2))
+    );
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_fault_signals() {
+    use alloc::format;
+    use syscall::{SIGILL, SIGTRAP};
+
+    println!("[test] test_fault_signals:");
+    // ユーザーモードの例外はカーネルを止めず, そのプロセスにシグナルとして届く
+    let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    for (child, sig) in [("illegal-instruction", SIGILL), ("breakpoint", SIGTRAP)] {
+        let arg = format!("--test-child={child}");
+        let pid = userlib::spawn_process("sh", &["sh", &arg], &[], stdio).unwrap();
+        assert_eq!(userlib::wait(pid).unwrap(), 128 + sig as i32);
+    }
+    println!("[OK]");
+}
+
+/// テスト用のスレッドローカル変数. 初期値を持つもの (.tdata) と 0 で埋めるもの (.tbss)
+#[cfg(feature = "shell-test")]
+#[thread_local]
+static mut TLS_COUNTER: usize = 42;
+#[cfg(feature = "shell-test")]
+#[thread_local]
+static mut TLS_BUFFER: [u8; 256] = [0; 256];
+
+/// tp はカーネルがプロセスを作るときに PT_TLS から設定するので, QEMU の上で動くこのテストの中で確かめる
+#[cfg(feature = "shell-test")]
+fn test_thread_local() {
+    println!("[test] test_thread_local:");
+    let tp = userlib::thread_pointer();
+    assert_ne!(tp, 0);
+    let counter = &raw mut TLS_COUNTER;
+    let buffer = &raw mut TLS_BUFFER;
+    // 変数は tp から始まる TLS の領域にある
+    assert!(counter as usize >= tp);
+    assert!(buffer as usize >= tp);
+    unsafe {
+        // 初期値がコピーされ, 残りは 0 で埋められている
+        assert_eq!(*counter, 42);
+        assert!((*buffer).iter().all(|b| *b == 0));
+        *counter += 1;
+        (*buffer)[255] = 7;
+        assert_eq!(*counter, 43);
+        assert_eq!((*buffer)[255], 7);
+    }
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_user_stack() {
+    use syscall::{SIGSEGV, USER_STACK_MAX, USER_STACK_TOP};
+
+    println!("[test] test_user_stack:");
+    // スタックはプログラムとは別に, ユーザー空間の最上部にカーネルが割り当てる
+    let local = 0u8;
+    let sp = &raw const local as usize;
+    assert!(sp < USER_STACK_TOP);
+    assert!(sp > USER_STACK_TOP - USER_STACK_MAX);
+    assert!(sp > test_user_stack as *const () as usize);
+
+    // スタックの下端より下のページに触ると SIGSEGV で終了する
+    let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    let args = ["sh", "--test-child=stack-overflow"];
+    let pid = userlib::spawn_process("sh", &args, &[], stdio).unwrap();
+    assert_eq!(userlib::wait(pid).unwrap(), 128 + SIGSEGV as i32);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_heap() {
+    use alloc::{boxed::Box, format, string::String, vec::Vec};
+    use syscall::ENOMEM;
+    use userlib::heap::{brk, sbrk};
+
+    println!("[test] test_heap:");
+    // sbrk で広げた領域は読み書きでき, 縮めると元に戻る
+    let start = sbrk(4096).unwrap();
+    assert_eq!(brk(0).unwrap(), start + 4096);
+    let area = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, 4096) };
+    assert!(area.iter().all(|b| *b == 0));
+    area.fill(0xaa);
+    assert_eq!(sbrk(-4096).unwrap(), start + 4096);
+    assert_eq!(brk(0).unwrap(), start);
+    // ヒープの先頭より前には縮められない
+    assert_eq!(brk(1), Err(-ENOMEM));
+
+    // alloc のコレクションが使える
+    let mut numbers = Vec::new();
+    for i in 0..1000usize {
+        numbers.push(i);
+    }
+    assert_eq!(numbers.iter().sum::<usize>(), 1000 * 999 / 2);
+    let mut text = String::from("hello");
+    text.push_str(", heap");
+    assert_eq!(format!("{text}!"), "hello, heap!");
+    let boxed = Box::new([7u64; 512]);
+    assert!(boxed.iter().all(|n| *n == 7));
+
+    // 解放した領域は再利用され, ヒープは広がらない
+    drop(numbers);
+    let end = brk(0).unwrap();
+    let reused: Vec<usize> = Vec::with_capacity(1000);
+    assert_eq!(brk(0).unwrap(), end);
+    drop(reused);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_mmap() {
+    use alloc::vec;
+    use syscall::{EINVAL, ENOMEM};
+    use userlib::heap::brk;
+    use userlib::mman::{
+        self, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, PAGE_SIZE, PROT_NONE, PROT_READ, PROT_WRITE,
+    };
+
+    println!("[test] test_mmap:");
+    let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+    let rw = PROT_READ | PROT_WRITE;
+    // 0 で埋めたページがページの境界にマップされる
+    let addr = mman::mmap(0, 3 * PAGE_SIZE, rw, flags).unwrap();
+    assert_eq!(addr % PAGE_SIZE, 0);
+    let pages = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, 3 * PAGE_SIZE) };
+    assert!(pages.iter().all(|b| *b == 0));
+    pages.fill(0x5a);
+
+    // 真ん中のページだけ読み込み専用にしても, 内容はそのまま読める
+    mman::mprotect(addr + PAGE_SIZE, PAGE_SIZE, PROT_READ).unwrap();
+    let middle = unsafe { core::ptr::read_volatile((addr + PAGE_SIZE) as *const u8) };
+    assert_eq!(middle, 0x5a);
+    // 読めなくしてから戻しても内容は残る
+    mman::mprotect(addr, PAGE_SIZE, PROT_NONE).unwrap();
+    mman::mprotect(addr, PAGE_SIZE, rw).unwrap();
+    assert_eq!(pages[0], 0x5a);
+
+    // 真ん中のページを外しても前後のページは使える
+    mman::munmap(addr + PAGE_SIZE, PAGE_SIZE).unwrap();
+    pages[0] = 1;
+    pages[2 * PAGE_SIZE] = 2;
+    assert_eq!(pages[0] + pages[2 * PAGE_SIZE], 3);
+    // 外したページには mprotect できず, MAP_FIXED で同じ位置にマップし直せる
+    assert_eq!(mman::mprotect(addr, 3 * PAGE_SIZE, PROT_READ), Err(-ENOMEM));
+    let fixed = addr + PAGE_SIZE;
+    assert_eq!(
+        mman::mmap(fixed, PAGE_SIZE, rw, flags | MAP_FIXED),
+        Ok(fixed)
+    );
+    assert_eq!(unsafe { *(fixed as *const u8) }, 0);
+    mman::munmap(addr, 3 * PAGE_SIZE).unwrap();
+
+    // 正しくない引数
+    assert_eq!(mman::mmap(0, 0, rw, flags), Err(-EINVAL));
+    assert_eq!(mman::mmap(0, PAGE_SIZE, rw, MAP_PRIVATE), Err(-EINVAL));
+    assert_eq!(
+        mman::mmap(fixed + 1, PAGE_SIZE, rw, flags | MAP_FIXED),
+        Err(-EINVAL)
+    );
+    assert_eq!(mman::munmap(addr + 1, PAGE_SIZE), Err(-EINVAL));
+    assert_eq!(mman::mprotect(addr + 1, PAGE_SIZE, rw), Err(-EINVAL));
+    // 末尾が溢れる範囲はマップしていないページではなく, 正しくない範囲として扱う
+    assert_eq!(mman::mprotect(addr, usize::MAX, rw), Err(-EINVAL));
+
+    // 大きな割り当てはヒープを広げずに mmap で行う
+    let end = brk(0).unwrap();
+    let large = vec![3u8; 256 * 1024];
+    assert!(large.iter().all(|b| *b == 3));
+    assert_eq!(brk(0).unwrap(), end);
+    drop(large);
+    println!("[OK]");
+}
+
+/// /proc/meminfo の MemUsed の値 (kB)
+#[cfg(feature = "shell-test")]
+fn mem_used_kb() -> usize {
+    let mut buf = [0u8; 256];
+    let n = File::open("/proc/meminfo")
+        .unwrap()
+        .read_full(&mut buf)
+        .unwrap();
+    let line = from_utf8(&buf[..n])
+        .unwrap()
+        .lines()
+        .find(|line| line.starts_with("MemUsed:"))
+        .unwrap();
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+#[cfg(feature = "shell-test")]
+fn test_demand_paging() {
+    use userlib::mman::{self, MAP_ANONYMOUS, MAP_PRIVATE, PAGE_SIZE, PROT_READ, PROT_WRITE};
+
+    println!("[test] test_demand_paging:");
+    const LEN: usize = 1024 * 1024;
+    // マップしただけでは物理ページを使わない
+    let before = mem_used_kb();
+    let addr = mman::mmap(0, LEN, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS).unwrap();
+    assert!(mem_used_kb() - before < 64);
+    // 触ったページだけ割り当てられ, 0 で埋められている
+    let pages = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, LEN) };
+    for page in pages.chunks_mut(PAGE_SIZE) {
+        assert_eq!(page[0], 0);
+        page[PAGE_SIZE - 1] = 1;
+    }
+    assert!(mem_used_kb() - before >= LEN / 1024);
+    assert_eq!(pages.iter().filter(|b| **b == 1).count(), LEN / PAGE_SIZE);
+    // 外したページの物理ページはページアロケータに返される
+    mman::munmap(addr, LEN).unwrap();
+    assert!(mem_used_kb() - before < 64);
+
+    // カーネルが書き込むバッファのページも割り当てられる
+    let addr = mman::mmap(
+        0,
+        PAGE_SIZE,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+    )
+    .unwrap();
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) };
+    let n = File::open("/proc/meminfo").unwrap().read_full(buf).unwrap();
+    assert!(from_utf8(&buf[..n]).unwrap().starts_with("MemTotal:"));
+    mman::munmap(addr, PAGE_SIZE).unwrap();
+    println!("[OK]");
+}
+
+/// テストのハンドラが最後に受け取ったシグナル
+#[cfg(feature = "shell-test")]
+static RECEIVED: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(feature = "shell-test")]
+extern "C" fn record_signal(sig: usize) {
+    RECEIVED.store(sig, core::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(feature = "shell-test")]
+fn test_signal_handlers() {
+    use core::sync::atomic::Ordering;
+    use userlib::signal::{self, Handler, SIG_BLOCK, SIG_UNBLOCK, SIGCHLD, SIGKILL, SIGUSR1};
+
+    println!("[test] test_signal_handlers:");
+    let received = || RECEIVED.swap(0, Ordering::SeqCst);
+    // カーネルが起動したシェルは自分のプロセスグループの先頭なので pgid が pid になる
+    let me = userlib::getpgid(0).unwrap() as isize;
+
+    // ハンドラは kill から戻る前に呼ばれる
+    signal::sigaction(SIGUSR1, Handler::Function(record_signal), 0).unwrap();
+    userlib::kill(me, SIGUSR1).unwrap();
+    assert_eq!(received(), SIGUSR1);
+
+    // 保留したシグナルは保留を解いたときに届く
+    signal::sigprocmask(SIG_BLOCK, signal::sigbit(SIGUSR1)).unwrap();
+    userlib::kill(me, SIGUSR1).unwrap();
+    assert_eq!(received(), 0);
+    let old = signal::sigprocmask(SIG_UNBLOCK, signal::sigbit(SIGUSR1)).unwrap();
+    assert_ne!(old & signal::sigbit(SIGUSR1), 0);
+    assert_eq!(received(), SIGUSR1);
+
+    // 無視するシグナルでは終了しない
+    signal::sigaction(SIGUSR1, Handler::Ignore, 0).unwrap();
+    userlib::kill(me, SIGUSR1).unwrap();
+    assert_eq!(received(), 0);
+    signal::sigaction(SIGUSR1, Handler::Default, 0).unwrap();
+
+    // 子プロセスの終了で SIGCHLD が届く
+    signal::sigaction(SIGCHLD, Handler::Function(record_signal), 0).unwrap();
+    let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    let pid = userlib::spawn_process("grep", &["grep"], &[], stdio).unwrap();
+    assert_eq!(userlib::wait(pid).unwrap(), 2);
+    assert_eq!(received(), SIGCHLD);
+    signal::sigaction(SIGCHLD, Handler::Default, 0).unwrap();
+
+    assert!(signal::sigaction(SIGKILL, Handler::Ignore, 0).is_err());
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_jobs() {
+    use sh_job::JobState;
+    use syscall::SIGSTOP;
+
+    println!("[test] test_jobs:");
+    let mut con = Console::new();
+    // 背後で起動したジョブは待たずに 0 を返す
+    assert_eq!(con.run("grep", true).unwrap(), 0);
+    assert_eq!(con.run("cat /nonexistent", true).unwrap(), 0);
+    assert_eq!(con.jobs.current(), Some(2));
+    assert_eq!(con.run("jobs", false).unwrap(), 0);
+    // fg は指定したジョブの終了を待ち, その終了コードを返す
+    assert_eq!(con.run("fg %1", false).unwrap(), 2);
+    assert_eq!(con.jobs.current(), Some(2));
+    assert_eq!(con.run("fg", false).unwrap(), 1);
+    assert_eq!(con.jobs.current(), None);
+    assert_eq!(con.run("fg", false).unwrap(), 1);
+    assert_eq!(con.run("bg %3", false).unwrap(), 1);
+
+    // 停止したジョブを bg で再開する
+    con.job_control = true;
+    assert_eq!(con.run("grep", true).unwrap(), 0);
+    let pgid = con.jobs.get_mut(1).unwrap().pgid();
+    userlib::kill(-(pgid as isize), SIGSTOP).unwrap();
+    con.jobs.notify();
+    assert_eq!(con.jobs.get_mut(1).unwrap().state(), JobState::Stopped);
+    assert_eq!(con.run("bg", false).unwrap(), 0);
+    assert_eq!(con.jobs.get_mut(1).unwrap().state(), JobState::Running);
+    assert_eq!(con.run("fg", false).unwrap(), 2);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_devfs() {
+    use userlib::fs::File;
+
+    println!("[test] test_devfs:");
+    let null = File::open_with("/dev/null", fs::O_RDWR).unwrap();
+    assert_eq!(null.write(b"discarded").unwrap(), 9);
+    let mut buf = [0xffu8; 16];
+    assert_eq!(null.read(&mut buf).unwrap(), 0);
+
+    let zero = File::open("/dev/zero").unwrap();
+    assert_eq!(zero.read(&mut buf).unwrap(), buf.len());
+    assert!(buf.iter().all(|&b| b == 0));
+
+    let random = File::open("/dev/random").unwrap();
+    assert_eq!(random.read(&mut buf).unwrap(), buf.len());
+
+    assert!(File::open("/dev/nonexistent").is_err());
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_procfs() {
+    use userlib::fs::File;
+
+    println!("[test] test_procfs:");
+    let mut buf = [0u8; 256];
+    let meminfo = File::open("/proc/meminfo").unwrap();
+    let n = meminfo.read_full(&mut buf).unwrap();
+    assert!(from_utf8(&buf[..n]).unwrap().starts_with("MemTotal:"));
+
+    let uptime = File::open("/proc/uptime").unwrap();
+    assert!(uptime.read_full(&mut buf).unwrap() > 0);
+
+    // シェル自身の status が読めること
+    let proc_dir = File::open("/proc").unwrap();
+    let mut found = false;
+    while let Some(entry) = proc_dir.read_dir(&mut buf).unwrap() {
+        if entry == "1" {
+            found = true;
+        }
+    }
+    assert!(found);
+    let status = File::open("/proc/1/status").unwrap();
+    let n = status.read_full(&mut buf).unwrap();
+    assert!(from_utf8(&buf[..n]).unwrap().contains("Name:\tsh"));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_metadata() {
+    use userlib::fs::{self, File};
+
+    println!("[test] test_metadata:");
+    assert!(fs::metadata("/dev/null").unwrap().is_char_device());
+    assert!(fs::metadata("/proc").unwrap().is_dir());
+    assert!(fs::metadata("/nonexistent").is_err());
+
+    let sh = fs::metadata("/sh").unwrap();
+    assert!(sh.is_file());
+    assert!(sh.size > 0);
+
+    // fstat でも同じ情報が得られること
+    let meminfo = File::open("/proc/meminfo").unwrap();
+    let meta = meminfo.metadata().unwrap();
+    assert!(meta.is_file());
+    assert_eq!(meta.id, fs::metadata("/proc/meminfo").unwrap().id);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_pipe() {
+    use userlib::fs;
+
+    println!("[test] test_pipe:");
+    let (r, w) = fs::pipe().unwrap();
+    assert!(fs::fstat(r).unwrap().is_fifo());
+    assert_eq!(fs::write(w, b"hello").unwrap(), 5);
+
+    let mut buf = [0u8; 16];
+    assert_eq!(fs::read(r, &mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"hello");
+
+    // 書き込み側を閉じると終端になる
+    fs::close(w).unwrap();
+    assert_eq!(fs::read(r, &mut buf).unwrap(), 0);
+    fs::close(r).unwrap();
+
+    // 読み出し側が無いパイプへの書き込みはエラーになる
+    let (r, w) = fs::pipe().unwrap();
+    fs::close(r).unwrap();
+    assert!(fs::write(w, b"hello").is_err());
+    fs::close(w).unwrap();
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_pipe_between_processes() {
+    use syscall::STDOUT_FILENO;
+    use userlib::fs;
+
+    println!("[test] test_pipe_between_processes:");
+    let (r, w) = fs::pipe().unwrap();
+
+    // ps の標準出力をパイプにつなぐ
+    let saved = fs::dup(STDOUT_FILENO).unwrap();
+    fs::dup2(w, STDOUT_FILENO).unwrap();
+    let spawned = userlib::spawn("ps");
+    fs::dup2(saved, STDOUT_FILENO).unwrap();
+    fs::close(saved).unwrap();
+    fs::close(w).unwrap();
+    spawned.unwrap();
+
+    let mut buf = [0u8; 512];
+    let mut len = 0;
+    loop {
+        let n = fs::read(r, &mut buf[len..]).unwrap();
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    fs::close(r).unwrap();
+    assert!(from_utf8(&buf[..len]).unwrap().contains("PID"));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_tokenize() {
+    println!("[test] test_tokenize:");
+    let mut con = Console::new();
+    let mut words = [0u8; WORD_BUF_SIZE];
+    let tokens = tokenize("cat<in |grep  foo>>out 2>&1", &mut words, &con).unwrap();
+    assert_eq!(
+        tokens.as_slice(),
+        [
+            Token::Word("cat"),
+            Token::RedirectIn,
+            Token::Word("in"),
+            Token::Pipe,
+            Token::Word("grep"),
+            Token::Word("foo"),
+            Token::RedirectAppend,
+            Token::Word("out"),
+            Token::StderrToStdout,
+        ]
+    );
+
+    let stage = Stage::parse(&tokens.as_slice()[4..]).unwrap();
+    assert_eq!(&stage.args[..stage.argc], ["grep", "foo"]);
+    assert_eq!(stage.stdout, Some(("out", true)));
+    assert!(stage.stderr_to_stdout);
+
+    // リダイレクト先が無い, またはコマンドが無い場合はエラー
+    let mut words = [0u8; WORD_BUF_SIZE];
+    let tokens = tokenize("echo >", &mut words, &con).unwrap();
+    assert!(Stage::parse(tokens.as_slice()).is_err());
+    let mut words = [0u8; WORD_BUF_SIZE];
+    let tokens = tokenize("| grep a", &mut words, &con).unwrap();
+    assert!(
+        con.run_pipeline(tokens.as_slice(), "| grep a", false)
+            .is_err()
+    );
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_open_flags() {
+    use userlib::fs::{self, File};
+
+    println!("[test] test_open_flags:");
+    let file = File::create("/tmp/flags").unwrap();
+    assert_eq!(file.write(b"hello").unwrap(), 5);
+    // 書き込み専用で開いたファイルは読めない
+    let mut buf = [0u8; 16];
+    assert!(file.read(&mut buf).is_err());
+    drop(file);
+
+    let file = File::open_with("/tmp/flags", fs::O_WRONLY | fs::O_APPEND).unwrap();
+    file.write(b" world").unwrap();
+    drop(file);
+    let file = File::open("/tmp/flags").unwrap();
+    let n = file.read_full(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hello world");
+    // 読み出し専用で開いたファイルには書き込めない
+    assert!(file.write(b"x").is_err());
+
+    // O_TRUNC で空になる
+    drop(File::create("/tmp/flags").unwrap());
+    assert_eq!(fs::metadata("/tmp/flags").unwrap().size, 0);
+
+    // /tmp の外には作成できない
+    assert!(File::create("/newfile").is_err());
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_wait_exit_code() {
+    println!("[test] test_wait_exit_code:");
+    let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    // 引数が不足している grep は 2 で終了する
+    let pid = userlib::spawn_process("grep", &["grep"], &[], stdio).unwrap();
+    assert_eq!(userlib::wait(pid).unwrap(), 2);
+    // 一度回収したプロセスは待てない
+    assert!(userlib::wait(pid).is_err());
+
+    assert!(userlib::spawn_process("nonexistent", &["nonexistent"], &[], stdio).is_err());
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_exec_format_error() {
+    println!("[test] test_exec_format_error:");
+    let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    let spawn = |path: &str| userlib::spawn_process(path, &[path], &[], stdio);
+
+    // ELF でないファイル
+    File::create("/tmp/notelf")
+        .unwrap()
+        .write_all(b"echo hello\n")
+        .unwrap();
+    assert_eq!(spawn("/tmp/notelf"), Err(-ENOEXEC));
+
+    // 正しい ELF ヘッダを元に壊したファイルを作る
+    let mut header = [0u8; 64];
+    File::open("grep").unwrap().read_full(&mut header).unwrap();
+    let write_broken = |patch: &dyn Fn(&mut [u8; 64])| {
+        let mut broken = header;
+        patch(&mut broken);
+        File::create("/tmp/broken")
+            .unwrap()
+            .write_all(&broken)
+            .unwrap();
+    };
+    // プログラムヘッダがファイルに収まっていない
+    write_broken(&|_| {});
+    assert_eq!(spawn("/tmp/broken"), Err(-ENOEXEC));
+    // 32ビットの ELF
+    write_broken(&|h| h[4] = 1);
+    assert_eq!(spawn("/tmp/broken"), Err(-ENOEXEC));
+    // x86_64 用の ELF
+    write_broken(&|h| h[18..20].copy_from_slice(&62u16.to_le_bytes()));
+    assert_eq!(spawn("/tmp/broken"), Err(-ENOEXEC));
+
+    // シェルからは 126 で終了したことになる
+    let mut con = Console::new();
+    let mut words = [0u8; WORD_BUF_SIZE];
+    let tokens = tokenize("/tmp/notelf", &mut words, &con).unwrap();
+    let status = con.run_pipeline(tokens.as_slice(), "/tmp/notelf", false);
+    assert_eq!(status.unwrap(), STATUS_NOT_EXECUTABLE);
+    println!("[OK]");
+}
+
+/// ELF ファイル path の p_type が最初に一致するプログラムヘッダを patch で書き換えたファイルを実行する
+#[cfg(feature = "shell-test")]
+fn spawn_patched_phdr(path: &str, p_type: u32, patch: &dyn Fn(&mut [u8])) -> Result<usize, isize> {
+    const PHDR_SIZE: usize = 56;
+    spawn_patched_elf(path, &|elf| {
+        let phoff = u64::from_le_bytes(elf[32..40].try_into().unwrap()) as usize;
+        let phnum = u16::from_le_bytes(elf[56..58].try_into().unwrap()) as usize;
+        let phdr = (0..phnum)
+            .map(|i| phoff + i * PHDR_SIZE)
+            .find(|&off| u32::from_le_bytes(elf[off..off + 4].try_into().unwrap()) == p_type)
+            .unwrap();
+        patch(&mut elf[phdr..phdr + PHDR_SIZE]);
+    })
+}
+
+/// ELF ファイル path の全体を patch で書き換えたファイルを実行する
+#[cfg(feature = "shell-test")]
+fn spawn_patched_elf(path: &str, patch: &dyn Fn(&mut [u8])) -> Result<usize, isize> {
+    let file = File::open(path).unwrap();
+    let mut elf = alloc::vec![0u8; file.metadata().unwrap().size as usize];
+    file.read_full(&mut elf).unwrap();
+    patch(&mut elf);
+    File::create("/tmp/broken")
+        .unwrap()
+        .write_all(&elf)
+        .unwrap();
+    let stdio = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    userlib::spawn_process("/tmp/broken", &["/tmp/broken"], &[], stdio)
+}
+
+#[cfg(feature = "shell-test")]
+fn test_elf_segment_too_large() {
+    const PT_LOAD: u32 = 1;
+    println!("[test] test_elf_segment_too_large:");
+    // p_memsz を 1TiB にする. アドレス空間は超えないが, 1つのセグメントとしては大きすぎる
+    let result = spawn_patched_phdr("grep", PT_LOAD, &|phdr| {
+        phdr[40..48].copy_from_slice(&(1u64 << 40).to_le_bytes())
+    });
+    assert_eq!(result, Err(-ENOEXEC));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_elf_segment_out_of_range() {
+    const PT_LOAD: u32 = 1;
+    println!("[test] test_elf_segment_out_of_range:");
+    // p_vaddr をスタックのすぐ下, カーネルの領域の直前に置く
+    let result = spawn_patched_phdr("grep", PT_LOAD, &|phdr| {
+        phdr[16..24].copy_from_slice(&0x7ff0_0000u64.to_le_bytes())
+    });
+    assert_eq!(result, Err(-ENOEXEC));
+    // p_vaddr をカーネルの領域に置く
+    let result = spawn_patched_phdr("grep", PT_LOAD, &|phdr| {
+        phdr[16..24].copy_from_slice(&0x8020_0000u64.to_le_bytes())
+    });
+    assert_eq!(result, Err(-ENOEXEC));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_elf_no_load_segment() {
+    println!("[test] test_elf_no_load_segment:");
+    // プログラムヘッダの数 e_phnum を 0 にする
+    let result = spawn_patched_elf("grep", &|elf| elf[56..58].fill(0));
+    assert_eq!(result, Err(-ENOEXEC));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_elf_bad_entry() {
+    println!("[test] test_elf_bad_entry:");
+    // e_entry をどの PT_LOAD にも含まれない位置にする
+    let result = spawn_patched_elf("grep", &|elf| {
+        elf[24..32].copy_from_slice(&0x100u64.to_le_bytes())
+    });
+    assert_eq!(result, Err(-ENOEXEC));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_elf_bad_tls() {
+    const PT_GNU_STACK: u32 = 0x6474_e551;
+    const PT_TLS: u32 = 7;
+    println!("[test] test_elf_bad_tls:");
+    // PT_GNU_STACK を, プログラムの先頭 (リンク時の 0x100_0000) を初期値にする PT_TLS に変える
+    let spawn_tls = |memsz: u64, align: u64| {
+        spawn_patched_phdr("grep", PT_GNU_STACK, &|phdr| {
+            phdr[0..4].copy_from_slice(&PT_TLS.to_le_bytes());
+            phdr[16..24].copy_from_slice(&0x100_0000u64.to_le_bytes());
+            phdr[40..48].copy_from_slice(&memsz.to_le_bytes());
+            phdr[48..56].copy_from_slice(&align.to_le_bytes());
+        })
+    };
+    // 境界が2の累乗でない
+    assert_eq!(spawn_tls(0x100, 3), Err(-ENOEXEC));
+    // 境界が大きすぎる
+    assert_eq!(spawn_tls(0x100, 1 << 30), Err(-ENOEXEC));
+    // 大きすぎる
+    assert_eq!(spawn_tls(1 << 40, 8), Err(-ENOEXEC));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_pipeline() {
+    use userlib::fs::File;
+
+    println!("[test] test_pipeline:");
+    let mut con = Console::new();
+    let mut run = |line: &str| {
+        let mut words = [0u8; WORD_BUF_SIZE];
+        let tokens = tokenize(line, &mut words, &con).unwrap();
+        con.run_pipeline(tokens.as_slice(), line, false).unwrap()
+    };
+    let read = |path: &str| {
+        let mut buf = [0u8; 64];
+        let n = File::open(path).unwrap().read_full(&mut buf).unwrap();
+        let mut out = [0u8; 64];
+        out[..n].copy_from_slice(&buf[..n]);
+        (out, n)
+    };
+
+    assert_eq!(run("echo foo > /tmp/pipeline"), 0);
+    assert_eq!(run("echo bar >> /tmp/pipeline"), 0);
+    let (out, n) = read("/tmp/pipeline");
+    assert_eq!(&out[..n], b"foo\nbar\n");
+
+    // 組み込みコマンドとプログラムをつなぐ
+    assert_eq!(run("cat < /tmp/pipeline | grep ba | cat > /tmp/result"), 0);
+    let (out, n) = read("/tmp/result");
+    assert_eq!(&out[..n], b"bar\n");
+
+    // 終了コードは最後のコマンドのもの
+    assert_eq!(run("echo foo | grep nomatch"), 1);
+    assert_eq!(run("nonexistent | cat"), 0);
+    assert_eq!(run("echo foo | nonexistent"), STATUS_NOT_FOUND);
+
+    // 2>&1 で標準エラー出力も同じファイルに書かれる
+    assert_eq!(run("cat /nonexistent > /tmp/result 2>&1"), 1);
+    let (out, n) = read("/tmp/result");
+    assert!(from_utf8(&out[..n]).unwrap().contains("No such file"));
+    println!("[OK]");
+}
+
+/// テスト用の変数
+#[cfg(feature = "shell-test")]
+struct TestVars;
+
+#[cfg(feature = "shell-test")]
+impl Variables for TestVars {
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "HOME" => Some("/home/nex"),
+            "GREETING" => Some("hello world"),
+            _ => None,
+        }
+    }
+
+    fn status(&self) -> i32 {
+        127
+    }
+}
+
+/// 単語だけを取り出してトークン列を比較する
+#[cfg(feature = "shell-test")]
+fn assert_words(line: &str, expected: &[&str]) {
+    let mut words = [0u8; WORD_BUF_SIZE];
+    let tokens = tokenize(line, &mut words, &TestVars).unwrap();
+    assert_eq!(tokens.len, expected.len());
+    for (token, expected) in tokens.as_slice().iter().zip(expected) {
+        assert_eq!(*token, Token::Word(expected));
+    }
+}
+
+#[cfg(feature = "shell-test")]
+fn test_quotes() {
+    println!("[test] test_quotes:");
+    assert_words("echo \"hello world\"", &["echo", "hello world"]);
+    assert_words("echo 'a  b' c", &["echo", "a  b", "c"]);
+    // クォートの前後は同じ単語につながる
+    assert_words("echo foo\"bar baz\"'qux'", &["echo", "foobar bazqux"]);
+    // 空のクォートも1つの単語になる
+    assert_words("echo '' \"\"", &["echo", "", ""]);
+    // 空の引数で引数の列が終わらない
+    let mut con = Console::new();
+    let mut words = [0u8; WORD_BUF_SIZE];
+    let line = "echo a \"\" b > /tmp/quotes";
+    let tokens = tokenize(line, &mut words, &con).unwrap();
+    assert_eq!(con.run_pipeline(tokens.as_slice(), line, false).unwrap(), 0);
+    let mut buf = [0u8; 16];
+    let n = File::open("/tmp/quotes")
+        .unwrap()
+        .read_full(&mut buf)
+        .unwrap();
+    assert_eq!(&buf[..n], b"a  b\n");
+    // クォートの中の演算子はただの文字
+    assert_words("echo 'a|b' \">out\"", &["echo", "a|b", ">out"]);
+
+    let mut words = [0u8; WORD_BUF_SIZE];
+    assert!(matches!(
+        tokenize("echo 'unterminated", &mut words, &TestVars),
+        Err(ParseError::UnterminatedQuote)
+    ));
+    let mut words = [0u8; WORD_BUF_SIZE];
+    assert!(matches!(
+        tokenize("echo \"unterminated", &mut words, &TestVars),
+        Err(ParseError::UnterminatedQuote)
+    ));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_escapes() {
+    println!("[test] test_escapes:");
+    assert_words("echo hello\\ world", &["echo", "hello world"]);
+    assert_words("echo \\'quoted\\'", &["echo", "'quoted'"]);
+    assert_words("echo a\\|b", &["echo", "a|b"]);
+    assert_words("echo \\$HOME", &["echo", "$HOME"]);
+    // ダブルクォートの中では特別な文字だけがエスケープされる
+    assert_words("echo \"\\\"\\$\\\\\\n\"", &["echo", "\"$\\\\n"]);
+    // シングルクォートの中ではエスケープされない
+    assert_words("echo '\\n'", &["echo", "\\n"]);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_expansion() {
+    println!("[test] test_expansion:");
+    assert_words("echo $HOME", &["echo", "/home/nex"]);
+    assert_words("echo ${HOME}/bin", &["echo", "/home/nex/bin"]);
+    assert_words("echo $HOME/bin", &["echo", "/home/nex/bin"]);
+    assert_words("echo $?", &["echo", "127"]);
+    // 展開した値は分割しない
+    assert_words("echo $GREETING", &["echo", "hello world"]);
+    assert_words("echo \"[$GREETING]\"", &["echo", "[hello world]"]);
+    assert_words("echo '$HOME'", &["echo", "$HOME"]);
+    // 未定義の変数は空になり, 単語も作られない
+    assert_words("echo $UNDEFINED", &["echo"]);
+    assert_words("echo \"$UNDEFINED\"", &["echo", ""]);
+    // 変数名が続かない $ はそのまま
+    assert_words("echo $ a$", &["echo", "$", "a$"]);
+
+    let mut words = [0u8; WORD_BUF_SIZE];
+    assert!(matches!(
+        tokenize("echo ${HOME", &mut words, &TestVars),
+        Err(ParseError::BadSubstitution)
+    ));
+
+    // シェルの $? は直前のコマンドの終了コード
+    let mut con = Console::new();
+    con.status = 1;
+    let mut words = [0u8; WORD_BUF_SIZE];
+    let tokens = tokenize("echo $?", &mut words, &con).unwrap();
+    assert_eq!(tokens.as_slice()[1], Token::Word("1"));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_env() {
+    use sh_env::EnvError;
+
+    println!("[test] test_env:");
+    let mut env = Env::new();
+    env.set("FOO", "bar").unwrap();
+    assert_eq!(env.get("FOO"), Some("bar"));
+    env.set("FOO", "baz").unwrap();
+    assert_eq!(env.get("FOO"), Some("baz"));
+
+    // export した変数だけが子プロセスに渡される
+    env.set("LOCAL", "1").unwrap();
+    env.export("FOO").unwrap();
+    let mut entries = [""; MAX_VARS];
+    let n = env.exported(&mut entries);
+    assert_eq!(&entries[..n], ["FOO=baz"]);
+    // 値を変えても export されたまま
+    env.set("FOO", "qux").unwrap();
+    let mut entries = [""; MAX_VARS];
+    let n = env.exported(&mut entries);
+    assert_eq!(&entries[..n], ["FOO=qux"]);
+
+    env.unset("FOO");
+    assert_eq!(env.get("FOO"), None);
+    assert_eq!(env.exported(&mut [""; MAX_VARS]), 0);
+
+    assert_eq!(env.set("1ABC", "x"), Err(EnvError::InvalidName));
+    assert_eq!(env.set("A-B", "x"), Err(EnvError::InvalidName));
+
+    // 組み込みコマンドから操作する
+    let mut con = Console::new();
+    let mut run = |line: &str| {
+        let mut words = [0u8; WORD_BUF_SIZE];
+        let tokens = tokenize(line, &mut words, &con).unwrap();
+        con.run_pipeline(tokens.as_slice(), line, false).unwrap()
+    };
+    assert_eq!(run("set GREETING=hello"), 0);
+    assert_eq!(run("export TARGET=world"), 0);
+    assert_eq!(run("set NOEQUAL"), 1);
+    assert_eq!(run("export 9BAD=x"), 1);
+    assert_eq!(run("unset TARGET"), 0);
+    assert_eq!(run("env > /tmp/env"), 0);
+    assert_eq!(con.get("GREETING"), Some("hello"));
+    assert_eq!(con.get("TARGET"), None);
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_path_search() {
+    println!("[test] test_path_search:");
+    let mut con = Console::new();
+    let mut buf = [0u8; PATH_BUF_SIZE];
+    assert_eq!(con.get("PATH"), Some(DEFAULT_PATH));
+    assert_eq!(con.find_command("grep", &mut buf), Some("/bin/grep"));
+    assert_eq!(con.find_command("/bin/cat", &mut buf), Some("/bin/cat"));
+    assert_eq!(con.find_command("nonexistent", &mut buf), None);
+    // ディレクトリは実行できない
+    assert_eq!(con.find_command("/proc", &mut buf), None);
+
+    // 空の要素や存在しないディレクトリは飛ばす
+    con.env.set("PATH", ":/nonexistent::/bin").unwrap();
+    assert_eq!(con.find_command("ps", &mut buf), Some("/bin/ps"));
+
+    // PATH が無ければ名前だけでは見つからない
+    con.env.unset("PATH");
+    assert_eq!(con.find_command("ps", &mut buf), None);
+    assert_eq!(con.find_command("/bin/ps", &mut buf), Some("/bin/ps"));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_exported_env() {
+    use userlib::fs::File;
+
+    println!("[test] test_exported_env:");
+    let mut con = Console::new();
+    let mut run = |line: &str| {
+        let mut words = [0u8; WORD_BUF_SIZE];
+        let tokens = tokenize(line, &mut words, &con).unwrap();
+        con.run_pipeline(tokens.as_slice(), line, false).unwrap()
+    };
+    run("export GREETING=hello");
+    run("set LOCAL=secret");
+    assert_eq!(run("printenv GREETING LOCAL > /tmp/printenv"), 1);
+
+    let mut buf = [0u8; 64];
+    let n = File::open("/tmp/printenv")
+        .unwrap()
+        .read_full(&mut buf)
+        .unwrap();
+    assert_eq!(&buf[..n], b"hello\n");
+    println!("[OK]");
+}
+
+/// 行エディタの出力を受け取り, 1行分の表示とカーソルの位置を再現する
+#[cfg(feature = "shell-test")]
+fn test_complete() {
+    println!("[test] test_complete:");
+    let complete = |input: &str| {
+        let mut buf = [0u8; BUF_SIZE];
+        let mut screen = TestScreen::new();
+        let mut editor = LineEditor::new(&mut buf, &mut screen);
+        type_str(&mut editor, input);
+        let mut candidates = Candidates::new();
+        sh_complete::complete(&mut editor, &BUILTINS, DEFAULT_PATH, &mut candidates).unwrap();
+        let mut line = [0u8; BUF_SIZE];
+        line[..editor.len()].copy_from_slice(editor.line());
+        (line, editor.len(), candidates.len())
+    };
+    let check = |input: &str, expected: &str, count: usize| {
+        let (line, len, n) = complete(input);
+        assert_eq!(&line[..len], expected.as_bytes());
+        assert_eq!(n, count);
+    };
+
+    // 組み込みコマンドと /bin のプログラム
+    check("his", "history ", 1);
+    check("gr", "grep ", 1);
+    // 候補が複数ある場合は共通部分だけ補う
+    check("e", "e", 4);
+    check("ex", "ex", 2);
+    check("expo", "export ", 1);
+    // パイプの後ろもコマンド名として扱う
+    check("ps | ca", "ps | cat ", 1);
+    // パス
+    check("cat /pr", "cat /proc/", 1);
+    check("/bin/pr", "/bin/printenv ", 1);
+    check("cat /proc/me", "cat /proc/meminfo ", 1);
+    // 候補が無い場合は何もしない
+    check("nothing", "nothing", 0);
+    check("cat fo", "cat fo", 0);
+
+    assert_eq!(sh_complete::display_name("/proc/"), "proc/");
+    assert_eq!(sh_complete::display_name("/bin/cat"), "cat");
+    assert_eq!(sh_complete::display_name("echo"), "echo");
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+struct TestScreen {
+    cells: [u8; BUF_SIZE],
+    width: usize,
+    cursor: usize,
+}
+
+#[cfg(feature = "shell-test")]
+impl TestScreen {
+    fn new() -> Self {
+        Self {
+            cells: [b' '; BUF_SIZE],
+            width: 0,
+            cursor: 0,
+        }
+    }
+
+    /// 行末の空白を除いた表示内容
+    fn text(&self) -> &[u8] {
+        let end = self.cells[..self.width]
+            .iter()
+            .rposition(|c| *c != b' ')
+            .map_or(0, |i| i + 1);
+        &self.cells[..end]
+    }
+}
+
+#[cfg(feature = "shell-test")]
+impl line::Terminal for &mut TestScreen {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), isize> {
+        for &c in bytes {
+            if c == 0x08 {
+                self.cursor -= 1;
+            } else {
+                self.cells[self.cursor] = c;
+                self.cursor += 1;
+                self.width = self.width.max(self.cursor);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "shell-test")]
+fn type_keys<T: line::Terminal>(editor: &mut LineEditor<T>, keys: &[Key]) {
+    for &key in keys {
+        editor.edit(key).unwrap();
+    }
+}
+
+#[cfg(feature = "shell-test")]
+fn type_str<T: line::Terminal>(editor: &mut LineEditor<T>, s: &str) {
+    for c in s.bytes() {
+        editor.edit(Key::Char(c)).unwrap();
+    }
+}
+
+#[cfg(feature = "shell-test")]
+fn test_line_editor() {
+    println!("[test] test_line_editor:");
+    let mut buf = [0u8; BUF_SIZE];
+    let mut screen = TestScreen::new();
+    let mut editor = LineEditor::new(&mut buf, &mut screen);
+
+    type_str(&mut editor, "echo wrld");
+    // 途中に挿入する
+    type_keys(&mut editor, &[Key::Left, Key::Left, Key::Left]);
+    type_str(&mut editor, "o");
+    assert_eq!(editor.line(), b"echo world");
+    assert_eq!(editor.cursor(), 7);
+
+    // 行頭と行末
+    type_keys(&mut editor, &[Key::Home]);
+    assert_eq!(editor.cursor(), 0);
+    type_keys(&mut editor, &[Key::Delete, Key::Right, Key::Right]);
+    assert_eq!(editor.line(), b"cho world");
+    type_str(&mut editor, "X");
+    assert_eq!(editor.line(), b"chXo world");
+    type_keys(&mut editor, &[Key::Backspace, Key::End]);
+    assert_eq!(editor.cursor(), editor.len());
+    // 行末での Delete と行頭での Backspace は何もしない
+    type_keys(&mut editor, &[Key::Delete, Key::Right]);
+    type_keys(&mut editor, &[Key::Home, Key::Backspace, Key::Left]);
+    assert_eq!(editor.line(), b"cho world");
+    assert_eq!(editor.cursor(), 0);
+
+    // 履歴の呼び出しで行全体を置き換える
+    editor.set_line(b"ls").unwrap();
+    assert_eq!(editor.line(), b"ls");
+    assert_eq!(editor.cursor(), 2);
+
+    // 表示がバッファと一致し, カーソルも同じ位置にある
+    assert_eq!(screen.text(), b"ls");
+    assert_eq!(screen.cursor, 2);
+
+    // バッファがいっぱいのときは挿入できない
+    let mut small = [0u8; 2];
+    let mut screen = TestScreen::new();
+    let mut editor = LineEditor::new(&mut small, &mut screen);
+    type_str(&mut editor, "ab");
+    assert_eq!(editor.edit(Key::Char(b'c')), Err(EditError::Overflow));
+    println!("[OK]");
+}
+
+#[cfg(feature = "shell-test")]
+fn test_line_editor_kill() {
+    println!("[test] test_line_editor_kill:");
+    let mut buf = [0u8; BUF_SIZE];
+    let mut screen = TestScreen::new();
+    let mut editor = LineEditor::new(&mut buf, &mut screen);
+
+    type_str(&mut editor, "cat foo  bar");
+    // 単語の後ろの空白もまとめて削除する
+    type_keys(&mut editor, &[Key::DeleteWord]);
+    assert_eq!(editor.line(), b"cat foo  ");
+    type_keys(&mut editor, &[Key::DeleteWord]);
+    assert_eq!(editor.line(), b"cat ");
+
+    type_str(&mut editor, "hello world");
+    type_keys(
+        &mut editor,
+        &[Key::Home, Key::Right, Key::Right, Key::Right],
+    );
+    type_keys(&mut editor, &[Key::KillToEnd]);
+    assert_eq!(editor.line(), b"cat");
+    type_str(&mut editor, "s are cute");
+    for _ in 0.."cute".len() {
+        type_keys(&mut editor, &[Key::Left]);
+    }
+    type_keys(&mut editor, &[Key::KillToStart]);
+    assert_eq!(editor.line(), b"cute");
+    assert_eq!(editor.cursor(), 0);
+
+    assert_eq!(screen.text(), b"cute");
+    assert_eq!(screen.cursor, 0);
     println!("[OK]");
 }
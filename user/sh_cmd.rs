@@ -1,8 +1,11 @@
 use core::str::from_utf8;
 
-use userlib::{exit_process, print, println, yield_process};
+use userlib::{eprintln, exit_process, print, println, yield_process};
 
-use crate::{ARGS_SIZE, BUF_SIZE, HISTORY_SIZE};
+use crate::{
+    sh_env::{Env, EnvError},
+    sh_history::History,
+};
 
 pub fn builtin_hello() {
     println!("hello");
@@ -20,31 +23,40 @@ Available commands:
     echo\t: Builtin echo command
     history\t: Show history
     yield\t: Yields current process
+    set\t: Show or set shell variables (set NAME=value)
+    export\t: Pass variables to programs (export NAME[=value])
+    unset\t: Remove variables
+    env\t: Show exported variables
+    true\t: Exit with status 0
+    false\t: Exit with status 1
+    jobs\t: Show background and stopped jobs
+    fg\t: Resume a job in the foreground (fg [%n])
+    bg\t: Resume a stopped job in the background (bg [%n])
+
+Commands can be combined with `;`, `&&`, `||`, if/while/until/for.
+Run a command in the background with `cmd &`. Ctrl-C/Ctrl-Z interrupt/stop the foreground job.
+Run a script file with `sh script.sh`.
 ";
     println!("{}", help_msg);
 }
 
-pub fn builtin_echo(args: [&str; ARGS_SIZE]) {
-    for (i, arg) in args[1..ARGS_SIZE].iter().enumerate() {
-        if arg.is_empty() {
-            break;
-        }
+/// args はコマンド名を含む引数の列. 空文字列の引数もそのまま出力する
+pub fn builtin_echo(args: &[&str]) {
+    for (i, arg) in args.iter().skip(1).enumerate() {
         // 区切りはすべてスペースにする
         if i != 0 {
             print!(" ");
         }
-        print!("{}", args[i + 1]);
+        print!("{}", arg);
     }
     print!("\n");
 }
 
-pub fn builtin_history(history: &[[u8; BUF_SIZE]], history_len: &[usize; HISTORY_SIZE]) {
-    for (item, len) in history.iter().zip(history_len.iter()).rev() {
-        if *len == 0 {
-            continue;
-        }
-        if let Ok(s) = from_utf8(&item[..*len]) {
-            println!("{}", s);
+/// 履歴を番号をつけて古い順に表示する. 番号は `!n` で使える
+pub fn builtin_history(history: &History) {
+    for (number, line) in history.iter() {
+        if let Ok(s) = from_utf8(line) {
+            println!("{:5}  {}", number, s);
         }
     }
 }
@@ -56,3 +68,75 @@ pub fn builtin_yield() -> Result<(), isize> {
 pub fn builtin_exit() -> Result<(), isize> {
     exit_process()
 }
+
+/// 引数の列のうち, コマンド名より後ろの部分
+fn operands<'a>(args: &[&'a str]) -> impl Iterator<Item = &'a str> {
+    args.iter().skip(1).copied()
+}
+
+/// NAME=value の形の引数で変数を設定し, その名前を返す
+fn assign<'a>(env: &mut Env, arg: &'a str) -> Result<&'a str, EnvError> {
+    match arg.split_once('=') {
+        Some((name, value)) => env.set(name, value).map(|_| name),
+        None if crate::sh_env::is_valid_name(arg) => Ok(arg),
+        None => Err(EnvError::InvalidName),
+    }
+}
+
+/// 引数が無い場合は全ての変数を表示し, NAME=value の引数では変数を設定する
+pub fn builtin_set(env: &mut Env, args: &[&str]) -> i32 {
+    let mut status = 0;
+    let mut operands = operands(args).peekable();
+    if operands.peek().is_none() {
+        for (name, value, _) in env.iter() {
+            println!("{}={}", name, value);
+        }
+        return status;
+    }
+    for arg in operands {
+        if !arg.contains('=') {
+            eprintln!("set: {}: expected NAME=value", arg);
+            status = 1;
+            continue;
+        }
+        if let Err(e) = assign(env, arg) {
+            eprintln!("set: {}: {}", arg, e);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// 変数を子プロセスに渡すようにする. NAME=value の引数では値も設定する
+pub fn builtin_export(env: &mut Env, args: &[&str]) -> i32 {
+    let mut status = 0;
+    let mut operands = operands(args).peekable();
+    if operands.peek().is_none() {
+        for (name, value, _) in env.iter().filter(|(_, _, exported)| *exported) {
+            println!("export {}={}", name, value);
+        }
+        return status;
+    }
+    for arg in operands {
+        if let Err(e) = assign(env, arg).and_then(|name| env.export(name)) {
+            eprintln!("export: {}: {}", arg, e);
+            status = 1;
+        }
+    }
+    status
+}
+
+pub fn builtin_unset(env: &mut Env, args: &[&str]) -> i32 {
+    for name in operands(args) {
+        env.unset(name);
+    }
+    0
+}
+
+/// 子プロセスに渡される変数を表示する
+pub fn builtin_env(env: &Env) -> i32 {
+    for (name, value, _) in env.iter().filter(|(_, _, exported)| *exported) {
+        println!("{}={}", name, value);
+    }
+    0
+}
@@ -0,0 +1,217 @@
+use core::str::from_utf8;
+
+use userlib::{
+    fs::{self, File},
+    line::{EditError, LineEditor, Terminal},
+};
+
+use crate::concat;
+
+//
+// Tab キーによるコマンド名とパスの補完
+//
+// 行頭やパイプの直後の単語は組み込みコマンドと PATH のプログラムから,
+// `/` を含む単語はディレクトリの中身から候補を探す
+// 作業ディレクトリは無いので, `/` を含まない引数は補完しない
+//
+
+/// 保存できる候補の数
+pub const MAX_CANDIDATES: usize = 32;
+/// 1つの候補の最大の大きさ
+const CANDIDATE_SIZE: usize = 64;
+/// ディレクトリのエントリ名を読むバッファの大きさ
+const ENTRY_SIZE: usize = 64;
+
+/// 補完の候補の一覧
+///
+/// 候補は補完する単語全体を置き換えた文字列で, 全て単語で始まる
+pub struct Candidates {
+    names: [[u8; CANDIDATE_SIZE]; MAX_CANDIDATES],
+    lens: [usize; MAX_CANDIDATES],
+    len: usize,
+}
+
+impl Candidates {
+    pub fn new() -> Self {
+        Self {
+            names: [[0u8; CANDIDATE_SIZE]; MAX_CANDIDATES],
+            lens: [0usize; MAX_CANDIDATES],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        if index >= self.len {
+            return None;
+        }
+        from_utf8(&self.names[index][..self.lens[index]]).ok()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        (0..self.len).filter_map(|i| self.get(i))
+    }
+
+    /// parts をつなげた候補を追加する
+    ///
+    /// 同じ候補が既にある場合や一杯の場合, 長すぎる場合は追加しない
+    fn push(&mut self, parts: &[&str]) {
+        if self.len == MAX_CANDIDATES {
+            return;
+        }
+        let buf = &mut self.names[self.len];
+        let mut len = 0;
+        for part in parts {
+            let end = len + part.len();
+            let Some(dst) = buf.get_mut(len..end) else {
+                return;
+            };
+            dst.copy_from_slice(part.as_bytes());
+            len = end;
+        }
+        let (added, new) = self.names.split_at(self.len);
+        let name = &new[0][..len];
+        if added.iter().zip(&self.lens).any(|(n, l)| &n[..*l] == name) {
+            return;
+        }
+        self.lens[self.len] = len;
+        self.len += 1;
+    }
+
+    /// 全ての候補に共通する先頭部分
+    pub fn common_prefix(&self) -> &str {
+        let Some(first) = self.get(0) else {
+            return "";
+        };
+        let mut len = first.len();
+        for name in self.iter().skip(1) {
+            len = first
+                .bytes()
+                .zip(name.bytes())
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count();
+        }
+        &first[..len]
+    }
+}
+
+/// カーソルの前にある補完する単語の先頭の位置
+pub fn word_start(line: &[u8], cursor: usize) -> usize {
+    line[..cursor]
+        .iter()
+        .rposition(|c| matches!(c, b' ' | b'|' | b'<' | b'>'))
+        .map_or(0, |i| i + 1)
+}
+
+/// start から始まる単語がコマンド名の位置にあるか
+///
+/// 行頭かパイプの直後であればコマンド名とみなす
+pub fn is_command_word(line: &[u8], start: usize) -> bool {
+    line[..start]
+        .iter()
+        .rev()
+        .find(|c| **c != b' ')
+        .is_none_or(|c| *c == b'|')
+}
+
+/// カーソルの前の単語を補完する
+///
+/// 候補に共通する部分を挿入し, 候補が1つに決まった場合は区切りの空白も挿入する
+/// 何も挿入できなかった場合は false を返す. 候補は out に残る
+pub fn complete<T: Terminal>(
+    editor: &mut LineEditor<T>,
+    builtins: &[&str],
+    path: &str,
+    out: &mut Candidates,
+) -> Result<bool, EditError> {
+    let line = editor.line();
+    let cursor = editor.cursor();
+    let start = word_start(line, cursor);
+    let command = is_command_word(line, start);
+    let Ok(word) = from_utf8(&line[start..cursor]) else {
+        return Ok(false);
+    };
+    let word_len = word.len();
+    collect(word, command, builtins, path, out);
+
+    let prefix = out.common_prefix();
+    let mut inserted = false;
+    for c in prefix.bytes().skip(word_len) {
+        editor.insert(c)?;
+        inserted = true;
+    }
+    if out.len() == 1 && !prefix.ends_with('/') {
+        editor.insert(b' ')?;
+        inserted = true;
+    }
+    Ok(inserted)
+}
+
+/// word を補完する候補を out に集める
+///
+/// command が真の場合, `/` を含まない単語は builtins と path のディレクトリから探す
+pub fn collect(word: &str, command: bool, builtins: &[&str], path: &str, out: &mut Candidates) {
+    if let Some(slash) = word.rfind('/') {
+        let (dir, prefix) = word.split_at(slash + 1);
+        collect_paths(dir, prefix, command, out);
+        return;
+    }
+    if !command {
+        return;
+    }
+    for name in builtins.iter().filter(|name| name.starts_with(word)) {
+        out.push(&[name]);
+    }
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        for_each_entry(dir, word, |entry| out.push(&[entry]));
+    }
+}
+
+/// dir の中で prefix から始まるエントリを, dir をつけたパスとして集める
+///
+/// ディレクトリには `/` をつける. command が真の場合は通常のファイル以外を飛ばす
+fn collect_paths(dir: &str, prefix: &str, command: bool, out: &mut Candidates) {
+    let mut path_buf = [0u8; CANDIDATE_SIZE];
+    for_each_entry(dir, prefix, |entry| {
+        let Some(meta) = concat(&mut path_buf, &[dir, entry]).and_then(|p| fs::metadata(p).ok())
+        else {
+            return;
+        };
+        if meta.is_dir() {
+            out.push(&[dir, entry, "/"]);
+        } else if !command || meta.is_file() {
+            out.push(&[dir, entry]);
+        }
+    });
+}
+
+/// ディレクトリ dir の prefix から始まるエントリの名前ごとに f を呼ぶ
+///
+/// 開けない場合は何もしない
+fn for_each_entry<F: FnMut(&str)>(dir: &str, prefix: &str, mut f: F) {
+    // 末尾の `/` を付けたままでは開けないので取り除く
+    let path = match dir.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    };
+    let Ok(file) = File::open(path) else {
+        return;
+    };
+    let mut entry_buf = [0u8; ENTRY_SIZE];
+    while let Ok(Some(entry)) = file.read_dir(&mut entry_buf) {
+        if entry.starts_with(prefix) {
+            f(entry);
+        }
+    }
+}
+
+/// 一覧に表示する候補の名前. ディレクトリの部分を除く
+pub fn display_name(candidate: &str) -> &str {
+    let trimmed = candidate.trim_end_matches('/');
+    let start = trimmed.rfind('/').map_or(0, |i| i + 1);
+    &candidate[start..]
+}
@@ -0,0 +1,163 @@
+use core::{
+    fmt::{self, Display},
+    str::from_utf8,
+};
+
+//
+// シェル変数と環境変数の管理
+//
+// 変数は "NAME=value" の形で固定長のバッファに保存する
+// export された変数は子プロセスに環境変数として渡す
+//
+
+/// 保存できる変数の数
+pub const MAX_VARS: usize = 32;
+/// "NAME=value" の形にした1つの変数の最大の大きさ
+pub const VAR_SIZE: usize = 128;
+
+#[derive(Clone, Copy)]
+struct Var {
+    buf: [u8; VAR_SIZE],
+    len: usize,
+    name_len: usize,
+    exported: bool,
+}
+
+impl Var {
+    fn entry(&self) -> &str {
+        // ASCII の名前と str から作った値だけを書き込んでいる
+        from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+
+    fn name(&self) -> &str {
+        &self.entry()[..self.name_len]
+    }
+
+    fn value(&self) -> &str {
+        &self.entry()[self.name_len + 1..]
+    }
+}
+
+/// 変数の操作のエラー
+#[derive(Debug, PartialEq)]
+pub enum EnvError {
+    InvalidName,
+    TooLong,
+    Full,
+}
+
+impl Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EnvError::InvalidName => write!(f, "not a valid identifier"),
+            EnvError::TooLong => write!(f, "variable too long (max {})", VAR_SIZE),
+            EnvError::Full => write!(f, "too many variables (max {})", MAX_VARS),
+        }
+    }
+}
+
+/// 変数名として使えるか. 英字か `_` で始まり, 英数字と `_` だけからなる
+pub fn is_valid_name(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    bytes
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == b'_')
+        && bytes.all(|c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+pub struct Env {
+    vars: [Option<Var>; MAX_VARS],
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self {
+            vars: [None; MAX_VARS],
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&Var> {
+        self.vars.iter().flatten().find(|v| v.name() == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.find(name).map(Var::value)
+    }
+
+    /// 変数に値を設定する. 既にある変数は export されているかを保つ
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), EnvError> {
+        if !is_valid_name(name) {
+            return Err(EnvError::InvalidName);
+        }
+        let len = name.len() + 1 + value.len();
+        if len > VAR_SIZE {
+            return Err(EnvError::TooLong);
+        }
+
+        let exported = self.find(name).is_some_and(|v| v.exported);
+        let mut var = Var {
+            buf: [0; VAR_SIZE],
+            len,
+            name_len: name.len(),
+            exported,
+        };
+        var.buf[..name.len()].copy_from_slice(name.as_bytes());
+        var.buf[name.len()] = b'=';
+        var.buf[name.len() + 1..len].copy_from_slice(value.as_bytes());
+
+        // 同じ名前の場所か, 空いている場所に入れる
+        let slot = match self
+            .vars
+            .iter()
+            .position(|v| v.is_some_and(|v| v.name() == name))
+        {
+            Some(i) => &mut self.vars[i],
+            None => self
+                .vars
+                .iter_mut()
+                .find(|v| v.is_none())
+                .ok_or(EnvError::Full)?,
+        };
+        *slot = Some(var);
+        Ok(())
+    }
+
+    /// 変数を子プロセスに渡すようにする. 無い場合は空の値で作る
+    pub fn export(&mut self, name: &str) -> Result<(), EnvError> {
+        if self.find(name).is_none() {
+            self.set(name, "")?;
+        }
+        for var in self.vars.iter_mut().flatten() {
+            if var.name() == name {
+                var.exported = true;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        for slot in self.vars.iter_mut() {
+            if slot.is_some_and(|v| v.name() == name) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// 全ての変数を (名前, 値, export されているか) の組で返す
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, bool)> {
+        self.vars
+            .iter()
+            .flatten()
+            .map(|v| (v.name(), v.value(), v.exported))
+    }
+
+    /// export された変数を "NAME=value" の形で entries に並べ, その数を返す
+    pub fn exported<'a>(&'a self, entries: &mut [&'a str; MAX_VARS]) -> usize {
+        let mut n = 0;
+        for var in self.vars.iter().flatten().filter(|v| v.exported) {
+            entries[n] = var.entry();
+            n += 1;
+        }
+        n
+    }
+}
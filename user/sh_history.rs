@@ -0,0 +1,271 @@
+use core::fmt::{self, Display};
+
+use userlib::fs::File;
+
+use crate::{BUF_SIZE, HISTORY_SIZE};
+
+//
+// コマンド履歴
+//
+// 最新の HISTORY_SIZE 個をリングバッファに保存する
+// 履歴には 1 から始まる通し番号をつけ, 番号 n は (n - 1) % HISTORY_SIZE の位置に入る
+//
+// 履歴ファイルには1行に1つのコマンドを追記していく
+//
+
+pub struct History {
+    entries: [[u8; BUF_SIZE]; HISTORY_SIZE],
+    lens: [usize; HISTORY_SIZE],
+    /// これまでに追加した数. 最新の履歴の番号でもある
+    count: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: [[0u8; BUF_SIZE]; HISTORY_SIZE],
+            lens: [0usize; HISTORY_SIZE],
+            count: 0,
+        }
+    }
+
+    /// 履歴に追加する. 空の行は追加しない
+    pub fn push(&mut self, line: &[u8]) {
+        if line.is_empty() {
+            return;
+        }
+        let index = self.count % HISTORY_SIZE;
+        let len = line.len().min(BUF_SIZE);
+        self.entries[index][..len].copy_from_slice(&line[..len]);
+        self.lens[index] = len;
+        self.count += 1;
+    }
+
+    /// 履歴ファイルを先頭から読み, 1行ずつ履歴に追加する
+    ///
+    /// BUF_SIZE より長い行は読み飛ばす. ファイルが改行で終わっていない場合は true を返す
+    pub fn load(&mut self, file: &File) -> Result<bool, isize> {
+        let mut chunk = [0u8; BUF_SIZE];
+        let mut line = [0u8; BUF_SIZE];
+        let mut len = 0;
+        let mut too_long = false;
+        loop {
+            let n = file.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            for &c in &chunk[..n] {
+                if c == b'\n' {
+                    if !too_long {
+                        self.push(&line[..len]);
+                    }
+                    len = 0;
+                    too_long = false;
+                } else if len < BUF_SIZE {
+                    line[len] = c;
+                    len += 1;
+                } else {
+                    too_long = true;
+                }
+            }
+        }
+        // 改行で終わっていない最後の行
+        let unterminated = len > 0 || too_long;
+        if !too_long {
+            self.push(&line[..len]);
+        }
+        Ok(unterminated)
+    }
+
+    /// 最新の履歴の番号. 履歴が無ければ 0
+    pub fn last_number(&self) -> usize {
+        self.count
+    }
+
+    /// 保存されている最も古い履歴の番号
+    pub fn first_number(&self) -> usize {
+        self.count.saturating_sub(HISTORY_SIZE) + 1
+    }
+
+    /// 番号 number の履歴. 古くて上書きされたものは None
+    pub fn get(&self, number: usize) -> Option<&[u8]> {
+        if number < self.first_number() || number > self.count {
+            return None;
+        }
+        let index = (number - 1) % HISTORY_SIZE;
+        Some(&self.entries[index][..self.lens[index]])
+    }
+
+    /// 古い順に番号と履歴を返す
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        (self.first_number()..=self.count).filter_map(|n| self.get(n).map(|line| (n, line)))
+    }
+
+    /// 番号が before より前で pattern を含む最新の履歴の番号
+    pub fn search(&self, pattern: &[u8], before: usize) -> Option<usize> {
+        (self.first_number()..before.min(self.count + 1))
+            .rev()
+            .find(|&n| self.get(n).is_some_and(|line| contains(line, pattern)))
+    }
+
+    /// `!!` を直前の履歴に, `!n` を番号 n の履歴に置き換えて out に書き込み, その長さを返す
+    ///
+    /// シングルクォートの中とバックスラッシュの後の `!` は置き換えない
+    pub fn expand(&self, input: &[u8], out: &mut [u8]) -> Result<usize, HistoryError> {
+        let mut len = 0;
+        let mut push = |bytes: &[u8]| {
+            let dst = out
+                .get_mut(len..len + bytes.len())
+                .ok_or(HistoryError::TooLong)?;
+            dst.copy_from_slice(bytes);
+            len += bytes.len();
+            Ok(())
+        };
+
+        let mut in_single = false;
+        let mut in_double = false;
+        let mut i = 0;
+        while i < input.len() {
+            let c = input[i];
+            match c {
+                b'\'' if !in_double => in_single = !in_single,
+                b'"' if !in_single => in_double = !in_double,
+                b'\\' if !in_single && i + 1 < input.len() => {
+                    push(&input[i..i + 2])?;
+                    i += 2;
+                    continue;
+                }
+                b'!' if !in_single => {
+                    if let Some((number, used)) = self.event(&input[i + 1..]) {
+                        let line = self.get(number).ok_or(HistoryError::EventNotFound)?;
+                        push(line)?;
+                        i += 1 + used;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            push(&[c])?;
+            i += 1;
+        }
+        Ok(len)
+    }
+
+    /// `!` の後ろを読み, 指している履歴の番号と読んだバイト数を返す
+    ///
+    /// 履歴の指定でなければ None
+    fn event(&self, rest: &[u8]) -> Option<(usize, usize)> {
+        if rest.first() == Some(&b'!') {
+            return Some((self.count, 1));
+        }
+        let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        // 大きすぎる番号は存在しない履歴として扱う
+        let number = rest[..digits]
+            .iter()
+            .try_fold(0usize, |n, c| {
+                n.checked_mul(10)?.checked_add((c - b'0') as usize)
+            })
+            .unwrap_or(0);
+        Some((number, digits))
+    }
+}
+
+fn contains(line: &[u8], pattern: &[u8]) -> bool {
+    pattern.is_empty() || line.windows(pattern.len()).any(|w| w == pattern)
+}
+
+/// Ctrl-R による履歴の逐次検索の状態
+pub struct Search {
+    pattern: [u8; BUF_SIZE],
+    len: usize,
+    /// 見つかった履歴の番号
+    found: Option<usize>,
+    /// 最後の検索で見つからなかったか. その場合も found は前の結果を指す
+    failed: bool,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self {
+            pattern: [0u8; BUF_SIZE],
+            len: 0,
+            found: None,
+            failed: false,
+        }
+    }
+
+    pub fn pattern(&self) -> &[u8] {
+        &self.pattern[..self.len]
+    }
+
+    pub fn found(&self) -> Option<usize> {
+        self.found
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// 検索する文字列に c を追加し, 今の位置から古い方へ探し直す
+    pub fn push(&mut self, c: u8, history: &History) {
+        if self.len == BUF_SIZE {
+            return;
+        }
+        self.pattern[self.len] = c;
+        self.len += 1;
+        let before = self.found.map_or(history.last_number() + 1, |n| n + 1);
+        self.search(history, before);
+    }
+
+    /// 検索する文字列の最後の文字を消し, 最新の履歴から探し直す
+    pub fn pop(&mut self, history: &History) {
+        self.len = self.len.saturating_sub(1);
+        self.found = None;
+        self.failed = false;
+        if self.len > 0 {
+            self.search(history, history.last_number() + 1);
+        }
+    }
+
+    /// 同じ文字列でさらに古い履歴を探す
+    pub fn next(&mut self, history: &History) {
+        if self.len == 0 {
+            return;
+        }
+        let before = self.found.unwrap_or(history.last_number() + 1);
+        self.search(history, before);
+    }
+
+    fn search(&mut self, history: &History, before: usize) {
+        match history.search(self.pattern(), before) {
+            Some(n) => {
+                self.found = Some(n);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
+    }
+}
+
+/// 履歴の展開に関するエラー
+#[derive(Debug, PartialEq)]
+pub enum HistoryError {
+    /// 指定された番号の履歴が無い
+    EventNotFound,
+    /// 展開した行がバッファに収まらない
+    TooLong,
+}
+
+impl Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HistoryError::EventNotFound => write!(f, "event not found"),
+            HistoryError::TooLong => write!(f, "expanded line too long (max {})", BUF_SIZE),
+        }
+    }
+}
+
+impl core::error::Error for HistoryError {}
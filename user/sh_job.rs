@@ -0,0 +1,291 @@
+use core::str::from_utf8;
+
+use syscall::{SIGCONT, SIGTSTP, WNOHANG, WUNTRACED};
+use userlib::{WaitStatus, eprintln, print, println};
+
+use crate::{BUF_SIZE, MAX_PIPELINE};
+
+//
+// ジョブ制御
+//
+// パイプライン1つで起動したプログラムをまとめてジョブとして扱う
+// 対話的に動いているときは, ジョブごとにプロセスグループを作り,
+// 前面で実行している間はコンソールの前面のプロセスグループにする
+// そうすることで Ctrl-C や Ctrl-Z がシェルではなくそのジョブにだけ届く
+//
+// ジョブの番号は一覧の位置に 1 を足したもので, `%n` の形で指定する
+//
+
+/// 同時に持てるジョブの数
+pub const MAX_JOBS: usize = 8;
+/// 停止したジョブの終了コード
+pub const STATUS_STOPPED: i32 = 128 + SIGTSTP as i32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// 全てのプログラムが終了した. 最後の段の終了コードを持つ
+    Done(i32),
+}
+
+#[derive(Clone)]
+pub struct Job {
+    pgid: usize,
+    pids: [usize; MAX_PIPELINE],
+    /// まだ回収していないプログラムか
+    alive: [bool; MAX_PIPELINE],
+    len: usize,
+    /// 最後の段のプログラムの pid. 組み込みコマンドなどの場合は None
+    last: Option<usize>,
+    /// 最後の段の終了コード
+    status: i32,
+    state: JobState,
+    command: [u8; BUF_SIZE],
+    command_len: usize,
+    /// 一覧に加えた順番. 大きいほど新しい
+    order: usize,
+}
+
+impl Job {
+    /// パイプラインの各段の pid から作る. 起動しなかった段は None で, 終了コードは statuses にある
+    ///
+    /// 1つもプログラムを起動していない場合は None
+    pub fn new(pids: &[Option<usize>], statuses: &[i32], command: &str) -> Option<Self> {
+        let mut job = Self {
+            pgid: 0,
+            pids: [0; MAX_PIPELINE],
+            alive: [false; MAX_PIPELINE],
+            len: 0,
+            last: *pids.last()?,
+            status: *statuses.last()?,
+            state: JobState::Running,
+            command: [0u8; BUF_SIZE],
+            command_len: 0,
+            order: 0,
+        };
+        for pid in pids.iter().flatten() {
+            job.pids[job.len] = *pid;
+            job.alive[job.len] = true;
+            job.len += 1;
+        }
+        job.pgid = *job.pids[..job.len].first()?;
+        // 長すぎるコマンドは表示用に途中までを残す
+        let command = command.trim();
+        job.command_len = command.len().min(BUF_SIZE);
+        job.command[..job.command_len].copy_from_slice(&command.as_bytes()[..job.command_len]);
+        Some(job)
+    }
+
+    pub fn pgid(&self) -> usize {
+        self.pgid
+    }
+
+    pub fn state(&self) -> JobState {
+        self.state
+    }
+
+    pub fn command(&self) -> &str {
+        from_utf8(&self.command[..self.command_len]).unwrap_or("")
+    }
+
+    /// まだ回収していないプログラムの状態の変化を options に従って待ち, ジョブの状態を返す
+    ///
+    /// どれかが停止した後は, 残りのプログラムを待たずに調べる
+    pub fn wait(&mut self, mut options: usize) -> JobState {
+        let mut stopped = false;
+        for i in 0..self.len {
+            if !self.alive[i] {
+                continue;
+            }
+            match userlib::wait_with(self.pids[i], options) {
+                Ok(None) => {}
+                Ok(Some(WaitStatus::Stopped(_))) => {
+                    stopped = true;
+                    options |= WNOHANG;
+                }
+                Ok(Some(WaitStatus::Exited(code))) => {
+                    self.alive[i] = false;
+                    if self.last == Some(self.pids[i]) {
+                        self.status = code;
+                    }
+                }
+                // 既に回収されたものは終了したとみなす
+                Err(_) => self.alive[i] = false,
+            }
+        }
+        if !self.alive[..self.len].contains(&true) {
+            self.state = JobState::Done(self.status);
+        } else if stopped {
+            self.state = JobState::Stopped;
+        }
+        self.state
+    }
+
+    /// 停止しているジョブに SIGCONT を送って再開する
+    pub fn resume(&mut self) {
+        if self.state == JobState::Stopped {
+            let _ = userlib::kill(-(self.pgid as isize), SIGCONT);
+            self.state = JobState::Running;
+        }
+    }
+}
+
+/// シェルが管理しているジョブの一覧
+pub struct Jobs {
+    slots: [Option<Job>; MAX_JOBS],
+    /// 次に加えるジョブの順番
+    next_order: usize,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self {
+            slots: [const { None }; MAX_JOBS],
+            next_order: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.slots.iter().all(|slot| slot.is_some())
+    }
+
+    /// ジョブを加え, その番号を返す. 一杯の場合は None
+    pub fn add(&mut self, mut job: Job) -> Option<usize> {
+        let index = self.slots.iter().position(|slot| slot.is_none())?;
+        job.order = self.next_order;
+        self.next_order += 1;
+        self.slots[index] = Some(job);
+        Some(index + 1)
+    }
+
+    pub fn get_mut(&mut self, number: usize) -> Option<&mut Job> {
+        self.slots.get_mut(number.checked_sub(1)?)?.as_mut()
+    }
+
+    pub fn remove(&mut self, number: usize) -> Option<Job> {
+        self.slots.get_mut(number.checked_sub(1)?)?.take()
+    }
+
+    /// 番号とジョブを番号の順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Job)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| slot.as_ref().map(|job| (i + 1, job)))
+    }
+
+    /// 番号を指定しなかったときに使う, 最後に加えたジョブの番号
+    pub fn current(&self) -> Option<usize> {
+        self.iter()
+            .max_by_key(|(_, job)| job.order)
+            .map(|(number, _)| number)
+    }
+
+    /// `%n` か `n` の形の指定をジョブの番号にする. 指定が無い場合は current()
+    pub fn resolve(&self, spec: Option<&str>) -> Option<usize> {
+        let Some(spec) = spec else {
+            return self.current();
+        };
+        let number = spec.strip_prefix('%').unwrap_or(spec).parse().ok()?;
+        self.iter().any(|(n, _)| n == number).then_some(number)
+    }
+
+    /// 終了や停止したジョブを待たずに調べ, 終了したものを表示して一覧から除く
+    pub fn notify(&mut self) {
+        let current = self.current();
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            let Some(job) = slot else {
+                continue;
+            };
+            let prev = job.state();
+            let state = job.wait(WNOHANG | WUNTRACED);
+            if let JobState::Done(_) = state {
+                print_job(i + 1, job, current);
+                *slot = None;
+            } else if state != prev {
+                print_job(i + 1, job, current);
+            }
+        }
+    }
+}
+
+/// ジョブを `[n]+  Running  command` の形で表示する. current のジョブには `+` をつける
+fn print_job(number: usize, job: &Job, current: Option<usize>) {
+    let mark = if current == Some(number) { '+' } else { ' ' };
+    let state = match job.state() {
+        JobState::Running => "Running",
+        JobState::Stopped => "Stopped",
+        JobState::Done(_) => "Done",
+    };
+    println!("[{number}]{mark}  {state:8}{}", job.command());
+}
+
+/// ジョブを前面で実行し, 終了か停止を待って終了コードを返す
+///
+/// job_control が真の場合は待つ間ジョブをコンソールの前面のプロセスグループにする
+/// 停止した場合はジョブの一覧に加える. 一覧が一杯の場合は再開して待ち続ける
+pub fn foreground(jobs: &mut Jobs, mut job: Job, job_control: bool) -> i32 {
+    loop {
+        if job_control {
+            let _ = userlib::tcsetpgrp(job.pgid());
+        }
+        let state = job.wait(WUNTRACED);
+        if job_control {
+            let _ = userlib::tcsetpgrp(0);
+        }
+        if let JobState::Done(status) = state {
+            return status;
+        }
+        // プロンプトと同じ行に表示しないように改行する
+        print!("\n");
+        if jobs.is_full() {
+            eprintln!("sh: too many jobs (max {})", MAX_JOBS);
+            job.resume();
+            continue;
+        }
+        let number = jobs.add(job).unwrap();
+        print_job(number, jobs.get_mut(number).unwrap(), Some(number));
+        return STATUS_STOPPED;
+    }
+}
+
+/// 引数の列の最初の引数
+fn first_operand<'a>(args: &[&'a str]) -> Option<&'a str> {
+    args.get(1).copied()
+}
+
+/// ジョブの一覧を表示する
+pub fn builtin_jobs(jobs: &Jobs) -> i32 {
+    let current = jobs.current();
+    for (number, job) in jobs.iter() {
+        print_job(number, job, current);
+    }
+    0
+}
+
+/// ジョブを前面に移して再開し, 終了か停止を待つ
+pub fn builtin_fg(jobs: &mut Jobs, args: &[&str], job_control: bool) -> i32 {
+    let Some(mut job) = jobs
+        .resolve(first_operand(args))
+        .and_then(|n| jobs.remove(n))
+    else {
+        eprintln!("fg: no such job");
+        return 1;
+    };
+    println!("{}", job.command());
+    job.resume();
+    foreground(jobs, job, job_control)
+}
+
+/// 停止しているジョブを背後で再開する
+pub fn builtin_bg(jobs: &mut Jobs, args: &[&str]) -> i32 {
+    let Some(number) = jobs.resolve(first_operand(args)) else {
+        eprintln!("bg: no such job");
+        return 1;
+    };
+    let job = jobs.get_mut(number).unwrap();
+    job.resume();
+    println!("[{number}]  {} &", job.command());
+    0
+}
@@ -0,0 +1,392 @@
+use crate::{ParseError, ShellError, Token, Variables, WORD_BUF_SIZE, sh_env, tokenize};
+
+//
+// コマンドの並びと制御構文
+//
+// `;`, `&&`, `||`, `&`, 改行で区切ったコマンドの並びと if, while, until, for を解釈する
+// `&` で背後で実行できるのは, `&&` や `||` でつながっていない単独のパイプラインだけ
+//
+// 構文木は作らず, 読み進めながら実行する
+// ループは本体の先頭の位置を覚えておき, 読み直すことで繰り返す
+// 実行しない部分も構文を確かめるために読み飛ばす
+//
+// 変数の展開は各パイプラインを実行する直前に行うので, ループの中で変数の値が変わってもよい
+//
+
+/// コマンドの先頭でだけ特別な意味を持つ単語
+const KEYWORDS: [&str; 10] = [
+    "if", "then", "elif", "else", "fi", "while", "until", "do", "done", "for",
+];
+
+/// スクリプトのコマンドを実行するもの
+pub trait Executor: Variables {
+    /// パイプライン1つを展開して実行し, 終了コードを返す
+    ///
+    /// background が真の場合は終了を待たずに戻る
+    fn run(&mut self, pipeline: &str, background: bool) -> Result<i32, ShellError>;
+
+    /// 終了コードを `$?` に反映する
+    fn set_status(&mut self, status: i32);
+
+    /// for の変数に値を代入する
+    fn set_var(&mut self, name: &str, value: &str) -> Result<(), ShellError>;
+}
+
+/// src をスクリプトとして実行する
+///
+/// 先に全体の構文を確かめ, 構文エラーがあれば何も実行しない
+pub fn execute<E: Executor>(src: &str, executor: &mut E) -> Result<(), ShellError> {
+    Interpreter::new(src, executor).program(false)?;
+    Interpreter::new(src, executor).program(true)
+}
+
+/// クォートを残したままの字句
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Raw<'s> {
+    /// パイプやリダイレクトも含む単語. 展開はパイプラインの実行時に行う
+    Word(&'s str),
+    /// `;`
+    Semi,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `&`
+    Amp,
+    Newline,
+    Eof,
+}
+
+struct Interpreter<'s, 'e, E: Executor> {
+    src: &'s str,
+    pos: usize,
+    executor: &'e mut E,
+}
+
+impl<'s, 'e, E: Executor> Interpreter<'s, 'e, E> {
+    fn new(src: &'s str, executor: &'e mut E) -> Self {
+        Self {
+            src,
+            pos: 0,
+            executor,
+        }
+    }
+
+    /// スクリプト全体を読む. exec が偽の場合は構文を確かめるだけ
+    fn program(&mut self, exec: bool) -> Result<(), ShellError> {
+        self.list(exec, &[])?;
+        match self.peek() {
+            Raw::Eof => Ok(()),
+            _ => Err(self.unexpected().into()),
+        }
+    }
+
+    /// terminators のキーワードか終端までのコマンドの並びを読み, コマンドの数を返す
+    fn list(&mut self, exec: bool, terminators: &[&str]) -> Result<usize, ShellError> {
+        let mut count = 0;
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Raw::Eof => break,
+                Raw::Word(word) if terminators.contains(&word) => break,
+                _ => {}
+            }
+            let background = self.and_or(exec)?;
+            count += 1;
+            // `&` はそれ自体が区切りになる
+            if background {
+                continue;
+            }
+            match self.peek() {
+                Raw::Semi | Raw::Newline => self.advance(),
+                Raw::Eof => break,
+                _ => return Err(self.unexpected().into()),
+            }
+        }
+        Ok(count)
+    }
+
+    /// if や while の中の, 空であってはならないコマンドの並び
+    fn body(&mut self, exec: bool, terminators: &[&str]) -> Result<(), ShellError> {
+        if self.list(exec, terminators)? == 0 {
+            return Err(self.unexpected().into());
+        }
+        Ok(())
+    }
+
+    /// `&&` と `||` でつないだコマンド
+    ///
+    /// 先頭のパイプラインを `&` で背後で実行した場合は true を返す
+    fn and_or(&mut self, exec: bool) -> Result<bool, ShellError> {
+        if self.command(exec, true)? {
+            return Ok(true);
+        }
+        loop {
+            let and = match self.peek() {
+                Raw::And => true,
+                Raw::Or => false,
+                _ => return Ok(false),
+            };
+            self.advance();
+            self.skip_newlines();
+            // && は直前が成功したときだけ, || は失敗したときだけ次を実行する
+            let run = exec && (self.executor.status() == 0) == and;
+            self.command(run, false)?;
+        }
+    }
+
+    /// コマンドを1つ読む. background が真の場合は後ろの `&` も読み, 背後で実行したかを返す
+    fn command(&mut self, exec: bool, background: bool) -> Result<bool, ShellError> {
+        match self.peek() {
+            Raw::Word("if") => self.if_clause(exec).map(|_| false),
+            Raw::Word("while" | "until") => self.while_clause(exec).map(|_| false),
+            Raw::Word("for") => self.for_clause(exec).map(|_| false),
+            Raw::Word(word) if KEYWORDS.contains(&word) => Err(self.unexpected().into()),
+            Raw::Word(_) => self.pipeline(exec, background),
+            _ => Err(self.unexpected().into()),
+        }
+    }
+
+    /// 区切りまでの単語をまとめて1つのパイプラインとして実行する
+    ///
+    /// background が真で `&` が続く場合は背後で実行し, true を返す
+    fn pipeline(&mut self, exec: bool, background: bool) -> Result<bool, ShellError> {
+        let (_, start, mut end) = self.scan();
+        while let (Raw::Word(_), _, word_end) = self.scan() {
+            self.pos = word_end;
+            end = word_end;
+        }
+        let background = background && self.peek() == Raw::Amp;
+        if background {
+            self.advance();
+        }
+        if exec {
+            let status = self.executor.run(&self.src[start..end], background)?;
+            self.executor.set_status(status);
+        }
+        Ok(background)
+    }
+
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    fn if_clause(&mut self, exec: bool) -> Result<(), ShellError> {
+        self.advance();
+        // 既に実行した枝があるか
+        let mut taken = false;
+        loop {
+            self.body(exec && !taken, &["then"])?;
+            self.expect("then")?;
+            let run = exec && !taken && self.executor.status() == 0;
+            self.body(run, &["elif", "else", "fi"])?;
+            taken |= run;
+
+            if self.is_keyword("elif") {
+                self.advance();
+                continue;
+            }
+            if self.is_keyword("else") {
+                self.advance();
+                let run = exec && !taken;
+                self.body(run, &["fi"])?;
+                taken |= run;
+            }
+            self.expect("fi")?;
+            break;
+        }
+        // どの枝も実行しなかった場合の終了コードは 0
+        if exec && !taken {
+            self.executor.set_status(0);
+        }
+        Ok(())
+    }
+
+    /// `while list; do list; done` と `until list; do list; done`
+    fn while_clause(&mut self, exec: bool) -> Result<(), ShellError> {
+        let until = self.peek() == Raw::Word("until");
+        self.advance();
+        let cond = self.pos;
+        // 本体を一度も実行しなかった場合の終了コードは 0
+        let mut status = 0;
+        loop {
+            self.pos = cond;
+            self.body(exec, &["do"])?;
+            self.expect("do")?;
+            let run = exec && (self.executor.status() == 0) != until;
+            self.body(run, &["done"])?;
+            self.expect("done")?;
+            if !run {
+                break;
+            }
+            status = self.executor.status();
+        }
+        if exec {
+            self.executor.set_status(status);
+        }
+        Ok(())
+    }
+
+    /// `for name [in word...]; do list; done`
+    fn for_clause(&mut self, exec: bool) -> Result<(), ShellError> {
+        self.advance();
+        let name = match self.peek() {
+            Raw::Word(word) if sh_env::is_valid_name(word) => word,
+            Raw::Word(_) => return Err(ParseError::BadForVariable.into()),
+            _ => return Err(self.unexpected().into()),
+        };
+        self.advance();
+
+        let mut words = (self.pos, self.pos);
+        if self.is_keyword("in") {
+            self.advance();
+            words = (self.pos, self.pos);
+            while let (Raw::Word(_), _, end) = self.scan() {
+                self.pos = end;
+                words.1 = end;
+            }
+        }
+        if matches!(self.peek(), Raw::Semi) {
+            self.advance();
+        }
+        self.skip_newlines();
+        self.expect("do")?;
+        let body = self.pos;
+
+        // 本体を一度も実行しなかった場合の終了コードは 0
+        let mut status = 0;
+        if exec {
+            let mut buf = [0u8; WORD_BUF_SIZE];
+            let tokens = tokenize(&self.src[words.0..words.1], &mut buf, &*self.executor)?;
+            // パイプなどの演算子は単語として扱わずに無視する
+            for token in tokens.as_slice() {
+                let Token::Word(word) = *token else {
+                    continue;
+                };
+                self.executor.set_var(name, word)?;
+                self.pos = body;
+                self.body(true, &["done"])?;
+                status = self.executor.status();
+            }
+        }
+        // 実行しなかった場合も done までを読み飛ばす
+        self.pos = body;
+        self.body(false, &["done"])?;
+        self.expect("done")?;
+        if exec {
+            self.executor.set_status(status);
+        }
+        Ok(())
+    }
+
+    /// 次の字句とその開始位置, 終了位置を返す. 位置は進めない
+    fn scan(&self) -> (Raw<'s>, usize, usize) {
+        let bytes = self.src.as_bytes();
+        let mut i = self.pos;
+        // 空白とコメントを飛ばす
+        loop {
+            match bytes.get(i) {
+                Some(b' ' | b'\t' | b'\r') => i += 1,
+                Some(b'#') => {
+                    while bytes.get(i).is_some_and(|c| *c != b'\n') {
+                        i += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        let start = i;
+        let (raw, end) = match bytes.get(i) {
+            None => (Raw::Eof, i),
+            Some(b'\n') => (Raw::Newline, i + 1),
+            Some(b';') => (Raw::Semi, i + 1),
+            Some(b'&') if bytes.get(i + 1) == Some(&b'&') => (Raw::And, i + 2),
+            Some(b'&') => (Raw::Amp, i + 1),
+            Some(b'|') if bytes.get(i + 1) == Some(&b'|') => (Raw::Or, i + 2),
+            Some(_) => {
+                let end = self.word_end(i);
+                (Raw::Word(&self.src[start..end]), end)
+            }
+        };
+        (raw, start, end)
+    }
+
+    /// i から始まる単語の終わりの位置
+    ///
+    /// クォートの中とバックスラッシュの後の文字は区切りとみなさない
+    fn word_end(&self, mut i: usize) -> usize {
+        let bytes = self.src.as_bytes();
+        while let Some(&c) = bytes.get(i) {
+            match c {
+                b' ' | b'\t' | b'\r' | b'\n' | b';' => break,
+                // `2>&1` の & は区切りではない
+                b'&' if i > 0 && matches!(bytes[i - 1], b'>' | b'<') => i += 1,
+                b'&' => break,
+                b'|' if bytes.get(i + 1) == Some(&b'|') => break,
+                b'\\' => i += 2,
+                b'\'' => {
+                    i += 1;
+                    while bytes.get(i).is_some_and(|c| *c != b'\'') {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                b'"' => {
+                    i += 1;
+                    while let Some(&c) = bytes.get(i) {
+                        match c {
+                            b'"' => break,
+                            b'\\' => i += 2,
+                            _ => i += 1,
+                        }
+                    }
+                    i += 1;
+                }
+                _ => i += 1,
+            }
+        }
+        // 閉じられていないクォートは展開するときにエラーにする
+        i.min(bytes.len())
+    }
+
+    fn peek(&self) -> Raw<'s> {
+        self.scan().0
+    }
+
+    fn advance(&mut self) {
+        self.pos = self.scan().2;
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Raw::Newline {
+            self.advance();
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.peek() == Raw::Word(keyword)
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if !self.is_keyword(keyword) {
+            return Err(self.unexpected());
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// 次の字句が予期しないものであるときのエラー
+    fn unexpected(&self) -> ParseError {
+        let token = match self.peek() {
+            Raw::Eof => return ParseError::UnexpectedEof,
+            Raw::Word(word) => KEYWORDS
+                .iter()
+                .find(|keyword| **keyword == word)
+                .copied()
+                .unwrap_or("word"),
+            Raw::Semi => ";",
+            Raw::And => "&&",
+            Raw::Or => "||",
+            Raw::Amp => "&",
+            Raw::Newline => "newline",
+        };
+        ParseError::Unexpected(token)
+    }
+}
@@ -0,0 +1,88 @@
+use core::cell::UnsafeCell;
+
+use syscall::{SYS_GET_ARGS, SYS_GET_ENV};
+
+use crate::syscall;
+
+//
+// 引数と環境変数
+//
+// どちらも NUL で区切った文字列の列としてカーネルから受け取る
+//
+
+/// 引数の列を NUL で区切ってまとめるバッファの大きさ
+pub const ARGS_BUF_SIZE: usize = 512;
+/// 環境変数の列を NUL で区切ってまとめるバッファの大きさ
+pub const ENV_BUF_SIZE: usize = 1024;
+
+struct ListBuffer<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    len: UnsafeCell<usize>,
+}
+
+unsafe impl<const N: usize> Sync for ListBuffer<N> {}
+
+impl<const N: usize> ListBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            buf: UnsafeCell::new([0; N]),
+            len: UnsafeCell::new(0),
+        }
+    }
+
+    /// sysno のシステムコールで列を読み込む
+    fn load(&self, sysno: usize) {
+        let buf = unsafe { &mut *self.buf.get() };
+        if let Ok(len) = syscall(sysno, buf.as_mut_ptr() as usize, buf.len(), 0) {
+            // 入りきらなかった分は捨てる
+            unsafe { *self.len.get() = (len as usize).min(N) };
+        }
+    }
+
+    fn iter(&'static self) -> impl Iterator<Item = &'static str> {
+        let (buf, len) = unsafe { (&*self.buf.get(), *self.len.get()) };
+        let list = core::str::from_utf8(&buf[..len]).unwrap_or("");
+        list.split('\0').filter(move |_| len > 0)
+    }
+}
+
+static ARGS: ListBuffer<ARGS_BUF_SIZE> = ListBuffer::new();
+static ENV: ListBuffer<ENV_BUF_SIZE> = ListBuffer::new();
+
+/// カーネルから引数と環境変数を読み込む
+///
+/// user_main! の開始処理から main の前に一度だけ呼ばれる
+#[doc(hidden)]
+pub fn init() {
+    ARGS.load(SYS_GET_ARGS);
+    ENV.load(SYS_GET_ENV);
+}
+
+/// プログラムの引数を返す. 最初の要素はプログラムの名前
+pub fn args() -> impl Iterator<Item = &'static str> {
+    ARGS.iter()
+}
+
+/// 環境変数を (名前, 値) の組で返す
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    ENV.iter().filter_map(|entry| entry.split_once('='))
+}
+
+/// 環境変数 name の値を返す
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find(|(n, _)| *n == name).map(|(_, value)| value)
+}
+
+/// items を NUL で区切って buf にまとめる. 収まらない場合は None
+pub(crate) fn join<'a>(buf: &'a mut [u8], items: &[&str]) -> Option<&'a [u8]> {
+    let mut len = 0;
+    for (i, item) in items.iter().enumerate() {
+        let sep = usize::from(i > 0);
+        let end = len + sep + item.len();
+        let dst = buf.get_mut(len..end)?;
+        dst[..sep].fill(0);
+        dst[sep..].copy_from_slice(item.as_bytes());
+        len = end;
+    }
+    Some(&buf[..len])
+}
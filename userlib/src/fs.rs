@@ -0,0 +1,157 @@
+use syscall::{
+    SYS_CLOSE, SYS_DUP, SYS_DUP2, SYS_FSTAT, SYS_OPEN, SYS_PIPE, SYS_READ, SYS_READDIR, SYS_STAT,
+    SYS_WRITE,
+};
+
+pub use syscall::{Metadata, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+
+use crate::syscall;
+
+//
+// ファイルディスクリプタを使う入出力
+//
+
+/// パスのファイルを読み出し用に開いてファイルディスクリプタを返す
+pub fn open(path: &str) -> Result<usize, isize> {
+    open_with(path, O_RDONLY)
+}
+
+/// パスのファイルを O_* のフラグ flags で開いてファイルディスクリプタを返す
+pub fn open_with(path: &str, flags: usize) -> Result<usize, isize> {
+    let ptr = path.as_ptr() as usize;
+    let len = path.len();
+    syscall(SYS_OPEN, ptr, len, flags).map(|fd| fd as usize)
+}
+
+/// 読み取ったバイト数を返す, 0 のときは終端
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, isize> {
+    let ptr = buf.as_mut_ptr() as usize;
+    syscall(SYS_READ, fd, ptr, buf.len()).map(|n| n as usize)
+}
+
+/// 書き込んだバイト数を返す
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, isize> {
+    let ptr = buf.as_ptr() as usize;
+    syscall(SYS_WRITE, fd, ptr, buf.len()).map(|n| n as usize)
+}
+
+pub fn close(fd: usize) -> Result<(), isize> {
+    syscall(SYS_CLOSE, fd, 0, 0).map(|_| ())
+}
+
+/// パイプを作り, (読み出し側, 書き込み側) のファイルディスクリプタを返す
+pub fn pipe() -> Result<(usize, usize), isize> {
+    let mut fds = [0usize; 2];
+    syscall(SYS_PIPE, fds.as_mut_ptr() as usize, 0, 0)?;
+    Ok((fds[0], fds[1]))
+}
+
+/// fd と同じファイルを指す新しいファイルディスクリプタを返す
+pub fn dup(fd: usize) -> Result<usize, isize> {
+    syscall(SYS_DUP, fd, 0, 0).map(|fd| fd as usize)
+}
+
+/// new_fd を old_fd と同じファイルを指すようにする
+///
+/// new_fd が開いていた場合は閉じられる
+pub fn dup2(old_fd: usize, new_fd: usize) -> Result<usize, isize> {
+    syscall(SYS_DUP2, old_fd, new_fd, 0).map(|fd| fd as usize)
+}
+
+/// ディレクトリの次のエントリの名前を buf に読み取り, その長さを返す
+///
+/// 全て読み終えたときは 0 を返す
+pub fn read_dir(fd: usize, buf: &mut [u8]) -> Result<usize, isize> {
+    let ptr = buf.as_mut_ptr() as usize;
+    syscall(SYS_READDIR, fd, ptr, buf.len()).map(|n| n as usize)
+}
+
+/// パスのファイルの情報を返す
+pub fn metadata(path: &str) -> Result<Metadata, isize> {
+    let mut meta = Metadata::default();
+    let ptr = path.as_ptr() as usize;
+    let meta_ptr = &mut meta as *mut Metadata as usize;
+    syscall(SYS_STAT, ptr, path.len(), meta_ptr)?;
+    Ok(meta)
+}
+
+/// ファイルディスクリプタのファイルの情報を返す
+pub fn fstat(fd: usize) -> Result<Metadata, isize> {
+    let mut meta = Metadata::default();
+    let meta_ptr = &mut meta as *mut Metadata as usize;
+    syscall(SYS_FSTAT, fd, meta_ptr, 0)?;
+    Ok(meta)
+}
+
+/// 開いているファイル
+///
+/// drop されたときに閉じられる
+pub struct File {
+    fd: usize,
+}
+
+impl File {
+    pub fn open(path: &str) -> Result<Self, isize> {
+        open(path).map(|fd| Self { fd })
+    }
+
+    pub fn open_with(path: &str, flags: usize) -> Result<Self, isize> {
+        open_with(path, flags).map(|fd| Self { fd })
+    }
+
+    /// 書き込み用に開く. 無ければ作成し, あれば空にする
+    pub fn create(path: &str) -> Result<Self, isize> {
+        Self::open_with(path, O_WRONLY | O_CREAT | O_TRUNC)
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, isize> {
+        read(self.fd, buf)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, isize> {
+        write(self.fd, buf)
+    }
+
+    /// buf を全て書き込むまで書き込みを繰り返す
+    pub fn write_all(&self, buf: &[u8]) -> Result<(), isize> {
+        crate::write_all_to(self.fd, buf)
+    }
+
+    pub fn metadata(&self) -> Result<Metadata, isize> {
+        fstat(self.fd)
+    }
+
+    /// ディレクトリの次のエントリの名前を返す
+    ///
+    /// 全て読み終えたときは None
+    pub fn read_dir<'a>(&self, buf: &'a mut [u8]) -> Result<Option<&'a str>, isize> {
+        let n = read_dir(self.fd, buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        core::str::from_utf8(&buf[..n]).map(Some).map_err(|_| -1)
+    }
+
+    /// buf がいっぱいになるか終端に達するまで読む
+    pub fn read_full(&self, buf: &mut [u8]) -> Result<usize, isize> {
+        let mut total = 0;
+        while total < buf.len() {
+            let n = self.read(&mut buf[total..])?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        Ok(total)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
@@ -0,0 +1,182 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    ptr,
+};
+
+use syscall::SYS_BRK;
+
+use crate::{
+    mman::{self, MAP_ANONYMOUS, MAP_PRIVATE, PAGE_SIZE, PROT_READ, PROT_WRITE},
+    syscall,
+};
+
+//
+// ヒープ
+//
+// カーネルが brk で広げる領域の上に, 空き領域をアドレス順につないだリストでメモリを割り当てる
+// 空き領域の先頭に FreeBlock を書き込んでおき, 解放するときは隣の空き領域とつなげる
+// 割り当てる大きさと位置は UNIT の倍数にそろえるので, どの空き領域にも FreeBlock を置ける
+// MMAP_THRESHOLD 以上の大きな割り当ては, ヒープを使わずに mmap で別にマップする
+//
+
+/// 割り当ての単位. FreeBlock が収まる大きさ
+const UNIT: usize = 16;
+/// 空き領域が足りないときに sbrk で一度に広げる最小の大きさ
+const GROW_MIN: usize = 16 * 1024;
+/// これ以上の大きさの割り当ては mmap でマップし, 解放するとマッピングを外す
+const MMAP_THRESHOLD: usize = 64 * 1024;
+
+/// ヒープの終わり (program break) を addr にして, 新しい終わりを返す. addr が 0 の場合は今の終わりを返す
+pub fn brk(addr: usize) -> Result<usize, isize> {
+    syscall(SYS_BRK, addr, 0, 0).map(|brk| brk as usize)
+}
+
+/// ヒープを increment バイト広げ (負の場合は縮め), 前の終わりを返す
+pub fn sbrk(increment: isize) -> Result<usize, isize> {
+    let old = brk(0)?;
+    let new = old.checked_add_signed(increment).ok_or(-1isize)?;
+    if increment != 0 {
+        brk(new)?;
+    }
+    Ok(old)
+}
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// sbrk で広げるヒープから割り当てる GlobalAlloc
+///
+/// ユーザープログラムは1つのスレッドで動くので排他制御はしない
+pub struct Heap {
+    /// アドレスの小さい順に並べた空き領域のリスト
+    head: UnsafeCell<*mut FreeBlock>,
+}
+
+unsafe impl Sync for Heap {}
+
+impl Heap {
+    pub const fn new() -> Self {
+        Self {
+            head: UnsafeCell::new(ptr::null_mut()),
+        }
+    }
+
+    /// align にそろえた size バイトを空き領域から切り出す. 無ければ null
+    unsafe fn take(&self, size: usize, align: usize) -> *mut u8 {
+        let mut link = self.head.get();
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                let start = block as usize;
+                let end = start + (*block).size;
+                let aligned = start.next_multiple_of(align);
+                if aligned + size <= end {
+                    // 切り出した前後の残りは空き領域に戻す
+                    *link = (*block).next;
+                    self.insert(start, aligned - start);
+                    self.insert(aligned + size, end - aligned - size);
+                    return aligned as *mut u8;
+                }
+                link = &raw mut (*block).next;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// start から size バイトを空き領域に加え, 隣り合う空き領域とつなげる
+    unsafe fn insert(&self, start: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut link = self.head.get();
+        unsafe {
+            while !(*link).is_null() && (*link as usize) < start {
+                prev = *link;
+                link = &raw mut (*prev).next;
+            }
+            let block = start as *mut FreeBlock;
+            block.write(FreeBlock { size, next: *link });
+            *link = block;
+
+            let next = (*block).next;
+            if !next.is_null() && start + size == next as usize {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+            if !prev.is_null() && prev as usize + (*prev).size == start {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            }
+        }
+    }
+
+    /// ヒープを広げて, 少なくとも size バイトの空き領域を加える
+    fn grow(&self, size: usize) -> bool {
+        let Ok(increment) = isize::try_from(size.max(GROW_MIN).next_multiple_of(UNIT)) else {
+            return false;
+        };
+        match sbrk(increment) {
+            Ok(old) => {
+                // 他に sbrk を呼んだ場合にそろっていないことがある
+                let start = old.next_multiple_of(UNIT);
+                let end = (old + increment as usize) / UNIT * UNIT;
+                if start < end {
+                    unsafe { self.insert(start, end - start) };
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// layout をヒープではなく mmap で割り当てるか
+fn is_large(layout: Layout) -> bool {
+    layout.size() >= MMAP_THRESHOLD && layout.align() <= PAGE_SIZE
+}
+
+/// layout を割り当てるときに切り出す大きさ
+fn block_size(layout: Layout) -> usize {
+    layout.size().max(1).next_multiple_of(UNIT)
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if is_large(layout) {
+            let prot = PROT_READ | PROT_WRITE;
+            return mman::mmap(0, layout.size(), prot, MAP_PRIVATE | MAP_ANONYMOUS)
+                .map_or(ptr::null_mut(), |addr| addr as *mut u8);
+        }
+        let size = block_size(layout);
+        let align = layout.align().max(UNIT);
+        unsafe {
+            let ptr = self.take(size, align);
+            if !ptr.is_null() {
+                return ptr;
+            }
+            // 位置をそろえるために余分に広げる
+            if !self.grow(size + align) {
+                return ptr::null_mut();
+            }
+            self.take(size, align)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if is_large(layout) {
+            let _ = mman::munmap(ptr as usize, layout.size());
+            return;
+        }
+        unsafe { self.insert(ptr as usize, block_size(layout)) };
+    }
+}
@@ -2,10 +2,25 @@
 #![no_main]
 use core::{arch::asm, panic::PanicInfo};
 use syscall::{
-    SYS_CREATE_PROCESS, SYS_EXIT_PROCESS, SYS_LIST_PROCESS, SYS_READ_BYTE, SYS_WRITE_BYTE,
-    SYS_YIELD_PROCESS,
+    MAX_ERRNO, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO, SYS_CREATE_PROCESS, SYS_EXIT_PROCESS,
+    SYS_GETPGID, SYS_KILL, SYS_LIST_PROCESS, SYS_SETPGID, SYS_TCGETPGRP, SYS_TCSETPGRP,
+    SYS_WAIT_PROCESS, SYS_YIELD_PROCESS, WAIT_STOPPED,
 };
 
+extern crate alloc;
+
+pub mod env;
+pub mod fs;
+pub mod heap;
+pub mod line;
+pub mod mman;
+pub mod signal;
+
+pub use env::args;
+
+#[global_allocator]
+static HEAP: heap::Heap = heap::Heap::new();
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     _print(format_args!("\n{info}"));
@@ -17,19 +32,30 @@ fn panic(info: &PanicInfo) -> ! {
 // システムコール
 //
 
-fn syscall(sysno: usize, arg0: usize, arg1: usize, arg2: usize) -> Result<isize, isize> {
+pub(crate) fn syscall(sysno: usize, arg0: usize, arg1: usize, arg2: usize) -> Result<isize, isize> {
+    syscall7(sysno, [arg0, arg1, arg2, 0, 0, 0, 0])
+}
+
+/// 引数が4つ以上のシステムコール
+///
+/// a3 はシステムコール番号に使うので, 引数は a0, a1, a2, a4, a5, a6, a7 の順に入れる
+pub(crate) fn syscall7(sysno: usize, args: [usize; 7]) -> Result<isize, isize> {
     let sysret: isize;
     unsafe {
         asm!(
             "ecall",
-            in("a0") arg0,
-            in("a1") arg1,
-            in("a2") arg2,
+            in("a0") args[0],
+            in("a1") args[1],
+            in("a2") args[2],
             in("a3") sysno,
+            in("a4") args[3],
+            in("a5") args[4],
+            in("a6") args[5],
+            in("a7") args[6],
             lateout("a0") sysret,
         );
     }
-    if sysret == -1 {
+    if (-MAX_ERRNO..0).contains(&sysret) {
         Err(sysret)
     } else {
         Ok(sysret)
@@ -40,26 +66,52 @@ fn syscall(sysno: usize, arg0: usize, arg1: usize, arg2: usize) -> Result<isize,
 // コンソール入出力
 //
 
+/// 標準入力から1バイト読み取る
 pub fn read_byte() -> Result<u8, isize> {
-    let ret = syscall(SYS_READ_BYTE, 0, 0, 0)?;
-    u8::try_from(ret).map_err(|_| -1)
+    let mut buf = [0u8; 1];
+    match fs::read(STDIN_FILENO, &mut buf)? {
+        1 => Ok(buf[0]),
+        _ => Err(-1),
+    }
 }
 
+/// 標準出力への書き込み
 pub struct Writer;
 
 impl Writer {
     pub fn write_byte(c: u8) -> Result<(), isize> {
-        syscall(SYS_WRITE_BYTE, c as usize, 0, 0).map(|_| ())
+        Writer::write_all(&[c])
     }
+
+    pub fn write_all(buf: &[u8]) -> Result<(), isize> {
+        write_all_to(STDOUT_FILENO, buf)
+    }
+}
+
+/// 標準エラー出力への書き込み
+pub struct ErrorWriter;
+
+pub(crate) fn write_all_to(fd: usize, mut buf: &[u8]) -> Result<(), isize> {
+    while !buf.is_empty() {
+        let n = fs::write(fd, buf)?;
+        if n == 0 {
+            return Err(-1);
+        }
+        buf = &buf[n..];
+    }
+    Ok(())
 }
 
 use core::fmt;
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.bytes() {
-            Writer::write_byte(c).map_err(|_| fmt::Error)?;
-        }
-        Ok(())
+        Writer::write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl fmt::Write for ErrorWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all_to(STDERR_FILENO, s.as_bytes()).map_err(|_| fmt::Error)
     }
 }
 
@@ -69,6 +121,12 @@ pub fn _print(args: fmt::Arguments) {
     writer.write_fmt(args).unwrap();
 }
 
+pub fn _eprint(args: fmt::Arguments) {
+    use core::fmt::Write;
+    // 標準エラー出力が閉じられていても処理は続ける
+    let _ = ErrorWriter.write_fmt(args);
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)););
@@ -80,28 +138,40 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)););
 }
 
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::_eprint(format_args!($($arg)*)););
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)););
+}
+
+/// プログラムの開始位置を定義する
+///
+/// 引数を読み込んでから main を呼び, main から戻ったら終了コード 0 で終了する
 #[macro_export]
 macro_rules! user_main {
     ($main_fn: ident) => {
-        unsafe extern "C" {
-            static __stack_top: u8;
-            static mut __bss: u8;
-            static __bss_end: u8;
-        }
-
         #[unsafe(naked)]
         #[unsafe(no_mangle)]
         #[unsafe(link_section = ".text.start")]
         extern "C" fn start() {
             unsafe {
+                // sp はカーネルがユーザースタックの上端にしてある
                 core::arch::naked_asm!(
-                    "la sp, {stack_top}",
-                    "call {main}",
-                    stack_top = sym __stack_top,
-                    main = sym $main_fn,
+                    "call {user_start}",
+                    user_start = sym __user_start,
                 );
             }
         }
+
+        extern "C" fn __user_start() {
+            $crate::env::init();
+            $main_fn();
+            let _ = $crate::exit_process();
+        }
     };
 }
 
@@ -114,23 +184,143 @@ pub fn yield_process() -> Result<(), isize> {
 }
 
 pub fn exit_process() -> Result<(), isize> {
-    syscall(SYS_EXIT_PROCESS, 0, 0, 0).map(|_| ())
+    exit_with_code(0)
+}
+
+/// 終了コード code でプロセスを終了する
+///
+/// 終了コードは下位8ビットだけが親に渡される
+pub fn exit_with_code(code: i32) -> Result<(), isize> {
+    syscall(SYS_EXIT_PROCESS, code as usize, 0, 0).map(|_| ())
 }
 
-fn create_process(path: &str) -> Result<isize, isize> {
+fn create_process(
+    path: &str,
+    args: &[u8],
+    env: &[u8],
+    stdio: Option<&[usize; 3]>,
+) -> Result<isize, isize> {
     let ptr = path.as_ptr() as usize;
     let len = path.len();
-    syscall(SYS_CREATE_PROCESS, ptr, len, 0)
+    let stdio_ptr = stdio.map_or(0, |fds| fds.as_ptr() as usize);
+    syscall7(
+        SYS_CREATE_PROCESS,
+        [
+            ptr,
+            len,
+            args.as_ptr() as usize,
+            args.len(),
+            stdio_ptr,
+            env.as_ptr() as usize,
+            env.len(),
+        ],
+    )
 }
 
 pub fn spawn(path: &str) -> Result<(), isize> {
-    let pid = create_process(path)?;
+    let pid = create_process(path, &[], &[], None)?;
     if pid >= 0 {
         yield_process()?;
     }
     Ok(())
 }
 
+/// path のプログラムを引数 args, 環境変数 env で起動し, その pid を返す
+///
+/// env の要素は "NAME=value" の形にする
+/// 子プロセスの fd 0, 1, 2 は stdio のファイルディスクリプタと同じファイルを指す
+/// 終了を待つには wait() を呼ぶ
+/// path が正しい実行ファイルでない場合は Err(-ENOEXEC) を返す
+pub fn spawn_process(
+    path: &str,
+    args: &[&str],
+    env: &[&str],
+    stdio: [usize; 3],
+) -> Result<usize, isize> {
+    let mut args_buf = [0u8; env::ARGS_BUF_SIZE];
+    let args = env::join(&mut args_buf, args).ok_or(-1isize)?;
+    let mut env_buf = [0u8; env::ENV_BUF_SIZE];
+    let env = env::join(&mut env_buf, env).ok_or(-1isize)?;
+    create_process(path, args, env, Some(&stdio)).map(|pid| pid as usize)
+}
+
+/// 子プロセス pid の終了を待ち, 終了コードを返す
+pub fn wait(pid: usize) -> Result<i32, isize> {
+    let mut code = 0i32;
+    syscall(SYS_WAIT_PROCESS, pid, &mut code as *mut i32 as usize, 0)?;
+    Ok(code)
+}
+
+/// wait_with() が返す子プロセスの状態
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitStatus {
+    /// 終了コードで終了した
+    Exited(i32),
+    /// シグナルの番号で停止した
+    Stopped(usize),
+}
+
+/// 子プロセス pid の状態の変化を options に従って待つ
+///
+/// options に WNOHANG を含め, まだ変化が無い場合は None を返す
+pub fn wait_with(pid: usize, options: usize) -> Result<Option<WaitStatus>, isize> {
+    let mut code = 0i32;
+    let ret = syscall(
+        SYS_WAIT_PROCESS,
+        pid,
+        &mut code as *mut i32 as usize,
+        options,
+    )?;
+    if ret == 0 {
+        return Ok(None);
+    }
+    Ok(Some(if code & WAIT_STOPPED != 0 {
+        WaitStatus::Stopped((code & 0xff) as usize)
+    } else {
+        WaitStatus::Exited(code)
+    }))
+}
+
+/// シグナル sig を送る. pid が負の場合はプロセスグループ -pid の全てのプロセスに送る
+pub fn kill(pid: isize, sig: usize) -> Result<(), isize> {
+    syscall(SYS_KILL, pid as usize, sig, 0).map(|_| ())
+}
+
+/// pid のプロセスグループを pgid にする
+///
+/// pid が 0 の場合は自分自身, pgid が 0 の場合は pid と同じ番号を使う
+pub fn setpgid(pid: usize, pgid: usize) -> Result<(), isize> {
+    syscall(SYS_SETPGID, pid, pgid, 0).map(|_| ())
+}
+
+/// pid のプロセスグループを返す. pid が 0 の場合は自分自身
+pub fn getpgid(pid: usize) -> Result<usize, isize> {
+    syscall(SYS_GETPGID, pid, 0, 0).map(|pgid| pgid as usize)
+}
+
+/// コンソールの前面のプロセスグループを pgid にする. 0 の場合は無しにする
+///
+/// 前面のプロセスグループには Ctrl-C で SIGINT, Ctrl-Z で SIGTSTP が送られる
+pub fn tcsetpgrp(pgid: usize) -> Result<(), isize> {
+    syscall(SYS_TCSETPGRP, pgid, 0, 0).map(|_| ())
+}
+
+/// コンソールの前面のプロセスグループ. 無い場合は 0
+pub fn tcgetpgrp() -> Result<usize, isize> {
+    syscall(SYS_TCGETPGRP, 0, 0, 0).map(|pgid| pgid as usize)
+}
+
+/// TLS の領域の先頭を指す tp レジスタの値. TLS を持たないプログラムでは 0
+///
+/// `#[thread_local]` の変数は tp からの相対位置で読み書きされる
+pub fn thread_pointer() -> usize {
+    let tp: usize;
+    unsafe {
+        asm!("mv {0}, tp", out(reg) tp);
+    }
+    tp
+}
+
 pub fn list_process() -> Result<(), isize> {
     syscall(SYS_LIST_PROCESS, 0, 0, 0).map(|_| ())
 }
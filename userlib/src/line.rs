@@ -0,0 +1,269 @@
+use crate::{Writer, read_byte};
+
+//
+// 1行の入力を編集する行エディタ
+//
+// カーソルの移動や途中への挿入, 削除を行い, 端末の表示を書き直す
+// 左への移動はバックスペース (0x08), 右への移動は文字の再出力で行う
+//
+
+const ESC: u8 = 0x1b;
+/// カーソルを左に1文字動かす
+const CURSOR_LEFT: u8 = 0x08;
+
+/// 入力されたキー
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    /// 表示できる文字
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    /// Home または Ctrl-A
+    Home,
+    /// End または Ctrl-E
+    End,
+    Up,
+    Down,
+    /// Ctrl-K: カーソルから行末までを削除する
+    KillToEnd,
+    /// Ctrl-U: 行頭からカーソルまでを削除する
+    KillToStart,
+    /// Ctrl-W: カーソルの前の単語を削除する
+    DeleteWord,
+    Tab,
+    /// その他の制御文字. Ctrl-R は Control(b'r') になる
+    Control(u8),
+    /// 解釈できないエスケープシーケンス
+    Unknown,
+}
+
+/// 標準入力から1つのキーを読み取る
+///
+/// 矢印キーなどのエスケープシーケンスは1つのキーにまとめる
+pub fn read_key() -> Result<Key, isize> {
+    let c = read_byte()?;
+    let key = match c {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillToEnd,
+        0x15 => Key::KillToStart,
+        0x17 => Key::DeleteWord,
+        ESC => read_escape()?,
+        0x00..=0x1f => Key::Control(c | 0x60),
+        _ => Key::Char(c),
+    };
+    Ok(key)
+}
+
+/// ESC に続くシーケンスを読み取る
+fn read_escape() -> Result<Key, isize> {
+    let c = read_byte()?;
+    if c != b'[' && c != b'O' {
+        return Ok(Key::Unknown);
+    }
+
+    // 数字と ';' からなる引数の後, 0x40..=0x7e の終端文字が来る
+    let mut param = 0u8;
+    let mut first_param = true;
+    let final_byte = loop {
+        let c = read_byte()?;
+        match c {
+            b'0'..=b'9' if first_param => param = param.saturating_mul(10).saturating_add(c - b'0'),
+            b';' => first_param = false,
+            0x40..=0x7e => break c,
+            _ => {}
+        }
+    };
+
+    let key = match final_byte {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        // ESC [ n ~ の形
+        b'~' => match param {
+            1 | 7 => Key::Home,
+            3 => Key::Delete,
+            4 | 8 => Key::End,
+            _ => Key::Unknown,
+        },
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
+
+/// 行エディタが表示を書き込む先
+pub trait Terminal {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), isize>;
+}
+
+impl Terminal for Writer {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), isize> {
+        Writer::write_all(bytes)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EditError {
+    /// バッファがいっぱいで文字を挿入できない
+    Overflow,
+    /// 表示の書き込みに失敗した
+    Terminal(isize),
+}
+
+/// buf を編集する行エディタ
+///
+/// 編集の開始時にはカーソルが行頭にあり, 何も表示されていないものとする
+pub struct LineEditor<'a, T: Terminal> {
+    buf: &'a mut [u8],
+    len: usize,
+    cursor: usize,
+    term: T,
+}
+
+impl<'a, T: Terminal> LineEditor<'a, T> {
+    pub fn new(buf: &'a mut [u8], term: T) -> Self {
+        Self {
+            buf,
+            len: 0,
+            cursor: 0,
+            term,
+        }
+    }
+
+    /// 入力された行
+    pub fn line(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// 編集のキーを処理する. Enter や Up などの編集以外のキーは無視する
+    pub fn edit(&mut self, key: Key) -> Result<(), EditError> {
+        match key {
+            Key::Char(c) => self.insert(c),
+            Key::Backspace if self.cursor > 0 => self.remove(self.cursor - 1, self.cursor),
+            Key::Delete if self.cursor < self.len => self.remove(self.cursor, self.cursor + 1),
+            Key::Left => self.move_to(self.cursor.saturating_sub(1)),
+            Key::Right => self.move_to((self.cursor + 1).min(self.len)),
+            Key::Home => self.move_to(0),
+            Key::End => self.move_to(self.len),
+            Key::KillToEnd => self.remove(self.cursor, self.len),
+            Key::KillToStart => self.remove(0, self.cursor),
+            Key::DeleteWord => self.remove(self.word_start(), self.cursor),
+            _ => Ok(()),
+        }
+    }
+
+    /// カーソルの位置に文字を挿入する
+    pub fn insert(&mut self, c: u8) -> Result<(), EditError> {
+        if self.len == self.buf.len() {
+            return Err(EditError::Overflow);
+        }
+        self.buf.copy_within(self.cursor..self.len, self.cursor + 1);
+        self.buf[self.cursor] = c;
+        self.len += 1;
+
+        // 挿入した文字から行末までを書き直し, カーソルを挿入した文字の後ろに戻す
+        self.write(self.cursor, self.len)?;
+        self.cursor += 1;
+        self.back(self.len - self.cursor)
+    }
+
+    /// 行全体を line に置き換える. 入りきらない部分は捨てる
+    pub fn set_line(&mut self, line: &[u8]) -> Result<(), EditError> {
+        self.remove(0, self.len)?;
+        let n = line.len().min(self.buf.len());
+        self.buf[..n].copy_from_slice(&line[..n]);
+        self.len = n;
+        self.write(0, n)?;
+        self.cursor = n;
+        Ok(())
+    }
+
+    /// 行全体を表示し直す
+    ///
+    /// 表示上のカーソルが行頭にあるものとし, カーソルを元の位置に戻す
+    pub fn redraw(&mut self) -> Result<(), EditError> {
+        self.write(0, self.len)?;
+        self.back(self.len - self.cursor)
+    }
+
+    /// start..end を削除し, カーソルを start に置く
+    fn remove(&mut self, start: usize, end: usize) -> Result<(), EditError> {
+        if start >= end {
+            return Ok(());
+        }
+        self.move_to(start)?;
+        let removed = end - start;
+        self.buf.copy_within(end..self.len, start);
+        self.len -= removed;
+
+        // 後ろの文字を詰めて書き直し, 余った部分を空白で消す
+        self.write(start, self.len)?;
+        for _ in 0..removed {
+            self.term_write(b" ")?;
+        }
+        self.back(self.len - start + removed)
+    }
+
+    fn move_to(&mut self, pos: usize) -> Result<(), EditError> {
+        if pos < self.cursor {
+            self.back(self.cursor - pos)?;
+        } else {
+            // 右へはその間の文字を出力し直して進める
+            self.write(self.cursor, pos)?;
+        }
+        self.cursor = pos;
+        Ok(())
+    }
+
+    /// カーソルの前の単語の先頭の位置. 単語の後ろの空白も含める
+    fn word_start(&self) -> usize {
+        let line = &self.buf[..self.cursor];
+        let end = line.iter().rposition(|c| *c != b' ').map_or(0, |i| i + 1);
+        line[..end]
+            .iter()
+            .rposition(|c| *c == b' ')
+            .map_or(0, |i| i + 1)
+    }
+
+    /// buf[start..end] を表示する
+    fn write(&mut self, start: usize, end: usize) -> Result<(), EditError> {
+        self.term
+            .write_bytes(&self.buf[start..end])
+            .map_err(EditError::Terminal)
+    }
+
+    fn term_write(&mut self, bytes: &[u8]) -> Result<(), EditError> {
+        self.term.write_bytes(bytes).map_err(EditError::Terminal)
+    }
+
+    /// 表示上のカーソルを n 文字左に動かす
+    fn back(&mut self, n: usize) -> Result<(), EditError> {
+        for _ in 0..n {
+            self.term_write(&[CURSOR_LEFT])?;
+        }
+        Ok(())
+    }
+}
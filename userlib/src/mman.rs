@@ -0,0 +1,34 @@
+use syscall::{SYS_MMAP, SYS_MPROTECT, SYS_MUNMAP};
+
+pub use syscall::{
+    MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE,
+};
+
+use crate::{syscall, syscall7};
+
+//
+// メモリのマッピング
+//
+// 今は MAP_PRIVATE | MAP_ANONYMOUS の, 0 で埋めたメモリのマッピングだけに対応している
+// 大きさや位置はページ単位で, 長さはページの大きさの倍数に切り上げられる
+//
+
+/// ページの大きさ
+pub const PAGE_SIZE: usize = 4096;
+
+/// len バイトのメモリを prot の権限でマップし, その先頭のアドレスを返す
+///
+/// flags に MAP_FIXED を含む場合は addr にマップし, 含まない場合の addr は位置のヒントになる
+pub fn mmap(addr: usize, len: usize, prot: usize, flags: usize) -> Result<usize, isize> {
+    syscall7(SYS_MMAP, [addr, len, prot, flags, 0, 0, 0]).map(|addr| addr as usize)
+}
+
+/// addr から len バイトを含むページのマッピングを外す
+pub fn munmap(addr: usize, len: usize) -> Result<(), isize> {
+    syscall(SYS_MUNMAP, addr, len, 0).map(|_| ())
+}
+
+/// addr から len バイトを含むページの権限を prot にする
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> Result<(), isize> {
+    syscall(SYS_MPROTECT, addr, len, prot).map(|_| ())
+}
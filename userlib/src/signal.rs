@@ -0,0 +1,68 @@
+use core::arch::naked_asm;
+
+use syscall::{SIG_DFL, SIG_IGN, SYS_SIGACTION, SYS_SIGPROCMASK, SYS_SIGRETURN, SigAction};
+
+pub use syscall::{
+    NSIG, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, SIGBUS, SIGCHLD, SIGCONT, SIGILL, SIGINT, SIGKILL,
+    SIGSEGV, SIGSTOP, SIGTERM, SIGTRAP, SIGTSTP, SIGUSR1, SIGUSR2,
+};
+
+use crate::syscall;
+
+//
+// シグナルの処理方法の設定
+//
+// ハンドラはユーザーモードに戻るときにカーネルが呼び出す
+// ハンドラから戻ると restorer() が sigreturn を呼び, 割り込まれたところから再開する
+//
+
+/// シグナルを受け取ったときの処理
+#[derive(Clone, Copy)]
+pub enum Handler {
+    /// 既定の動作. 多くのシグナルはプロセスを終了させる
+    Default,
+    Ignore,
+    /// シグナルの番号を引数に呼ぶ関数
+    Function(extern "C" fn(usize)),
+}
+
+/// sig を受け取ったときの処理を handler にする
+///
+/// mask はハンドラの実行中に保留するシグナルのビット集合. sig 自身も保留される
+/// SIGKILL と SIGSTOP の処理は変えられない
+pub fn sigaction(sig: usize, handler: Handler, mask: u32) -> Result<(), isize> {
+    let action = SigAction {
+        handler: match handler {
+            Handler::Default => SIG_DFL,
+            Handler::Ignore => SIG_IGN,
+            Handler::Function(f) => f as usize,
+        },
+        mask,
+        flags: 0,
+        restorer: restorer as *const () as usize,
+    };
+    let ptr = &action as *const SigAction as usize;
+    syscall(SYS_SIGACTION, sig, ptr, 0).map(|_| ())
+}
+
+/// 保留するシグナルのビット集合を how (SIG_BLOCK, SIG_UNBLOCK, SIG_SETMASK) に従って set で変える
+///
+/// 変える前の集合を返す
+pub fn sigprocmask(how: usize, set: u32) -> Result<u32, isize> {
+    syscall(SYS_SIGPROCMASK, how, set as usize, 0).map(|old| old as u32)
+}
+
+/// シグナルの番号のビット
+pub const fn sigbit(sig: usize) -> u32 {
+    1 << sig
+}
+
+/// ハンドラから戻ったときに実行され, sigreturn でカーネルに戻る
+#[unsafe(naked)]
+extern "C" fn restorer() {
+    naked_asm!(
+        "li a3, {sysno}",
+        "ecall",
+        sysno = const SYS_SIGRETURN,
+    )
+}